@@ -205,10 +205,24 @@ pub unsafe fn write_thread_pointer(tpidr_el0: usize) {
 /// Enable FP/SIMD instructions by setting the `FPEN` field in `CPACR_EL1`.
 #[inline]
 pub fn enable_fp() {
-    CPACR_EL1.write(CPACR_EL1::FPEN::TrapNothing);
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
     barrier::isb(barrier::SY);
 }
 
+/// Disable FP/SIMD instructions by clearing the `FPEN` field in `CPACR_EL1`,
+/// so that they are trapped at both EL0 and EL1.
+#[inline]
+pub fn disable_fp() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0El1);
+    barrier::isb(barrier::SY);
+}
+
+/// Returns whether FP/SIMD instructions are enabled, i.e., not trapped.
+#[inline]
+pub fn fp_enabled() -> bool {
+    CPACR_EL1.matches_all(CPACR_EL1::FPEN::TrapNothing)
+}
+
 #[cfg(feature = "uspace")]
 core::arch::global_asm!(include_str!("user_copy.S"));
 
@@ -19,9 +19,9 @@ pub struct TrapFrame {
     pub elr: u64,
     /// Saved Process Status Register (SPSR_EL1).
     pub spsr: u64,
-
-    /// make sure the size is 16 bytes aligned
-    pub __pad: u64,
+    /// The original `X0` of a syscall, which is overwritten by the return
+    /// value. It also makes sure the size is 16 bytes aligned.
+    pub orig_arg0: u64,
 }
 
 impl fmt::Debug for TrapFrame {
@@ -32,7 +32,7 @@ impl fmt::Debug for TrapFrame {
         }
         writeln!(f, "    elr: {:#x},", self.elr)?;
         writeln!(f, "    spsr: {:#x},", self.spsr)?;
-        writeln!(f, "    pad: {:#x},", self.__pad)?;
+        writeln!(f, "    orig_arg0: {:#x},", self.orig_arg0)?;
         write!(f, "}}")?;
         Ok(())
     }
@@ -129,6 +129,16 @@ impl TrapFrame {
         self.x[0] = r0 as _;
     }
 
+    /// Rewinds the syscall that has just returned, so that it is executed
+    /// again when returning to user space.
+    ///
+    /// It moves the PC back to the `SVC` instruction and restores `X0` from
+    /// [`orig_arg0`](Self::orig_arg0).
+    pub const fn rewind_syscall(&mut self) {
+        self.elr -= 4;
+        self.x[0] = self.orig_arg0;
+    }
+
     /// Sets the return address.
     pub const fn set_ra(&mut self, lr: usize) {
         self.x[30] = lr as _;
@@ -140,6 +150,15 @@ impl TrapFrame {
     }
 }
 
+/// Raw information about the cause of a trap.
+#[derive(Debug, Clone, Copy)]
+pub struct RawTrapCause {
+    /// Exception Syndrome Register (ESR_EL1).
+    pub esr: u64,
+    /// Fault Address Register (FAR_EL1).
+    pub far: u64,
+}
+
 /// FP & SIMD registers.
 #[repr(C, align(16))]
 #[derive(Debug, Default)]
@@ -165,6 +184,30 @@ impl FpState {
     }
 }
 
+/// The FP/SIMD states of the task running on the current CPU, which are
+/// restored on its first FP/SIMD instruction after being switched in.
+#[cfg(feature = "lazy-fp")]
+#[percpu::def_percpu]
+static CURRENT_FP_STATE: usize = 0;
+
+/// Handles the trap of an FP/SIMD instruction while they are disabled by lazy
+/// switching, by enabling them and restoring the states of the current task.
+///
+/// Returns `false` if FP/SIMD instructions are already enabled, i.e., the trap
+/// is not caused by lazy switching.
+#[cfg(feature = "lazy-fp")]
+pub(crate) fn handle_lazy_fp_trap() -> bool {
+    if crate::asm::fp_enabled() {
+        return false;
+    }
+    crate::asm::enable_fp();
+    let state = CURRENT_FP_STATE.read_current() as *const FpState;
+    if let Some(state) = unsafe { state.as_ref() } {
+        state.restore();
+    }
+    true
+}
+
 /// Saved hardware states of a task.
 ///
 /// The context usually includes:
@@ -200,6 +243,13 @@ pub struct TaskContext {
     pub ttbr0_el1: memory_addr::PhysAddr,
     #[cfg(feature = "fp-simd")]
     pub fp_state: FpState,
+    /// SVE and SME states, which are only used after the first SVE or SME
+    /// instruction of the task.
+    #[cfg(feature = "sve")]
+    pub sve_state: super::SveState,
+    /// Hardware breakpoints and watchpoints.
+    #[cfg(feature = "hw-breakpoint")]
+    pub debug_state: crate::debug::DebugState,
 }
 
 impl TaskContext {
@@ -235,22 +285,63 @@ impl TaskContext {
     ///
     /// It first saves the current task's context from CPU to this place, and then
     /// restores the next task's context from `next_ctx` to CPU.
+    ///
+    /// With the `lazy-fp` feature, the FP/SIMD states are only saved if the
+    /// current task has used them since it was switched in, and are restored
+    /// on the first FP/SIMD instruction of the next task, so `next_ctx` must
+    /// not be moved while the task is running. It requires the [`percpu`]
+    /// crate to be initialized.
     pub fn switch_to(&mut self, next_ctx: &Self) {
         #[cfg(feature = "tls")]
         {
             self.tpidr_el0 = crate::asm::read_thread_pointer() as _;
             unsafe { crate::asm::write_thread_pointer(next_ctx.tpidr_el0 as _) };
         }
-        #[cfg(feature = "fp-simd")]
+        // SVE and SME registers are saved first to disable ZA before the
+        // FP/SIMD registers are saved.
+        #[cfg(feature = "sve")]
+        {
+            // SVE and SME registers are also trapped by `CPACR_EL1.FPEN`.
+            #[cfg(feature = "lazy-fp")]
+            if self.sve_state.in_use() {
+                handle_lazy_fp_trap();
+            }
+            self.sve_state.save();
+        }
+        #[cfg(all(feature = "fp-simd", not(feature = "lazy-fp")))]
         {
             self.fp_state.save();
             next_ctx.fp_state.restore();
         }
+        #[cfg(feature = "lazy-fp")]
+        {
+            if crate::asm::fp_enabled() {
+                self.fp_state.save();
+                crate::asm::disable_fp();
+            }
+            CURRENT_FP_STATE.write_current(&next_ctx.fp_state as *const _ as usize);
+        }
+        #[cfg(feature = "sve")]
+        {
+            // The FP/SIMD states of the next task are loaded eagerly if it
+            // uses SVE or SME, as they overlap the SVE registers.
+            #[cfg(feature = "lazy-fp")]
+            if next_ctx.sve_state.in_use() {
+                handle_lazy_fp_trap();
+            }
+            next_ctx.sve_state.restore();
+            next_ctx.sve_state.set_current();
+        }
         #[cfg(feature = "uspace")]
         if self.ttbr0_el1 != next_ctx.ttbr0_el1 {
             unsafe { crate::asm::write_user_page_table(next_ctx.ttbr0_el1) };
             crate::asm::flush_tlb(None); // currently flush the entire TLB
         }
+        #[cfg(feature = "hw-breakpoint")]
+        {
+            self.debug_state.save();
+            next_ctx.debug_state.restore();
+        }
         unsafe { context_switch(self, next_ctx) }
     }
 }
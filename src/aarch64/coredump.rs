@@ -0,0 +1,30 @@
+//! Register notes of ELF core dumps.
+
+pub use crate::coredump::aarch64::{ElfGregset, ELF_FPREGSET_SIZE, ELF_NGREG};
+
+use super::{uspace::UserContext, FpState};
+use crate::coredump::aarch64 as layout;
+
+/// The note type of the thread pointer (`TPIDR_EL0`).
+pub const NT_ARM_TLS: u32 = 0x401;
+
+impl UserContext {
+    /// Converts the registers to `elf_gregset_t`: `X0`-`X30`, `SP`, `PC` and
+    /// `PSTATE`.
+    pub fn to_elf_gregset(&self) -> ElfGregset {
+        layout::gregset(&self.x, self.sp, self.elr, self.spsr)
+    }
+
+    /// Converts the thread pointer to the `NT_ARM_TLS` note.
+    pub fn to_elf_tls(&self) -> [u8; 8] {
+        self.tpidr.to_ne_bytes()
+    }
+}
+
+impl FpState {
+    /// Converts the FP/SIMD registers to the `NT_PRFPREG` note: `V0`-`V31`,
+    /// `FPSR` and `FPCR`, followed by 8 reserved bytes.
+    pub fn to_elf_fpregset(&self) -> [u8; ELF_FPREGSET_SIZE] {
+        layout::fpregset(&self.regs, self.fpsr, self.fpcr)
+    }
+}
@@ -0,0 +1,172 @@
+//! Breakpoint and watchpoint registers (`DBGBVR<n>_EL1`, `DBGBCR<n>_EL1`,
+//! `DBGWVR<n>_EL1` and `DBGWCR<n>_EL1`).
+
+use core::arch::asm;
+
+use aarch64_cpu::registers::{Readable, ID_AA64DFR0_EL1};
+
+use crate::debug::{check_range, DebugError, WatchKind};
+
+/// The maximum number of breakpoints or watchpoints.
+const MAX_SLOTS: usize = 16;
+
+/// Enable bit of `DBGBCR<n>_EL1` and `DBGWCR<n>_EL1`.
+const CTRL_E: u64 = 1 << 0;
+/// Privilege mode control: match at EL0 only.
+const CTRL_EL0: u64 = 0b10 << 1;
+
+macro_rules! def_banked_reg {
+    ($read:ident, $write:ident, $name:literal) => {
+        def_banked_reg!(@impl $read, $write, $name, [0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15]);
+    };
+    (@impl $read:ident, $write:ident, $name:literal, [$($n:literal)*]) => {
+        fn $read(idx: usize) -> u64 {
+            let val;
+            match idx {
+                $($n => unsafe { asm!(concat!("mrs {}, ", $name, $n, "_el1"), out(reg) val) },)*
+                _ => unreachable!(),
+            }
+            val
+        }
+
+        fn $write(idx: usize, val: u64) {
+            match idx {
+                $($n => unsafe { asm!(concat!("msr ", $name, $n, "_el1, {}"), in(reg) val) },)*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+def_banked_reg!(read_bvr, write_bvr, "dbgbvr");
+def_banked_reg!(read_bcr, write_bcr, "dbgbcr");
+def_banked_reg!(read_wvr, write_wvr, "dbgwvr");
+def_banked_reg!(read_wcr, write_wcr, "dbgwcr");
+
+/// Returns the number of hardware breakpoints.
+pub fn num_breakpoints() -> usize {
+    ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::BRPs) as usize + 1
+}
+
+/// Returns the number of hardware watchpoints.
+pub fn num_watchpoints() -> usize {
+    ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::WRPs) as usize + 1
+}
+
+/// Sets the hardware breakpoint `idx` on the instruction at `addr`.
+pub fn set_breakpoint(idx: usize, addr: usize) -> Result<(), DebugError> {
+    if idx >= num_breakpoints() {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    check_range(addr, 4)?;
+    // Match all the bytes of an A64 instruction.
+    const BAS_A64: u64 = 0b1111 << 5;
+    write_bcr(idx, 0);
+    write_bvr(idx, addr as _);
+    write_bcr(idx, BAS_A64 | CTRL_EL0 | CTRL_E);
+    unsafe { asm!("isb") };
+    Ok(())
+}
+
+/// Clears the hardware breakpoint `idx`.
+pub fn clear_breakpoint(idx: usize) {
+    if idx < num_breakpoints() {
+        write_bcr(idx, 0);
+        unsafe { asm!("isb") };
+    }
+}
+
+/// Sets the hardware watchpoint `idx` on `len` bytes at `addr`.
+pub fn set_watchpoint(
+    idx: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), DebugError> {
+    if idx >= num_watchpoints() {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    check_range(addr, len)?;
+    let lsc: u64 = match kind {
+        WatchKind::Read => 0b01,
+        WatchKind::Write => 0b10,
+        WatchKind::ReadWrite => 0b11,
+    };
+    // Byte address select within the 8-byte aligned doubleword.
+    let bas = ((1u64 << len) - 1) << (addr & 7);
+    write_wcr(idx, 0);
+    write_wvr(idx, (addr & !7) as _);
+    write_wcr(idx, bas << 5 | lsc << 3 | CTRL_EL0 | CTRL_E);
+    unsafe { asm!("isb") };
+    Ok(())
+}
+
+/// Clears the hardware watchpoint `idx`.
+pub fn clear_watchpoint(idx: usize) {
+    if idx < num_watchpoints() {
+        write_wcr(idx, 0);
+        unsafe { asm!("isb") };
+    }
+}
+
+/// Enables breakpoint and watchpoint exceptions (`MDSCR_EL1.MDE`) on the
+/// current CPU.
+pub(crate) fn init_percpu() {
+    const MDSCR_MDE: u64 = 1 << 15;
+    let mut mdscr: u64;
+    unsafe { asm!("mrs {}, mdscr_el1", out(reg) mdscr) };
+    mdscr |= MDSCR_MDE;
+    unsafe { asm!("msr mdscr_el1, {}; isb", in(reg) mdscr) };
+}
+
+/// Per-task states of the breakpoint and watchpoint registers.
+///
+/// Only the values of enabled slots are saved and restored.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugState {
+    /// Breakpoint value registers.
+    pub bvr: [u64; MAX_SLOTS],
+    /// Breakpoint control registers.
+    pub bcr: [u64; MAX_SLOTS],
+    /// Watchpoint value registers.
+    pub wvr: [u64; MAX_SLOTS],
+    /// Watchpoint control registers.
+    pub wcr: [u64; MAX_SLOTS],
+}
+
+impl DebugState {
+    /// Saves the breakpoint and watchpoint registers of the current CPU to
+    /// this structure.
+    pub fn save(&mut self) {
+        for i in 0..num_breakpoints() {
+            self.bcr[i] = read_bcr(i);
+            if self.bcr[i] & CTRL_E != 0 {
+                self.bvr[i] = read_bvr(i);
+            }
+        }
+        for i in 0..num_watchpoints() {
+            self.wcr[i] = read_wcr(i);
+            if self.wcr[i] & CTRL_E != 0 {
+                self.wvr[i] = read_wvr(i);
+            }
+        }
+    }
+
+    /// Restores the breakpoint and watchpoint registers of the current CPU
+    /// from this structure.
+    pub fn restore(&self) {
+        for i in 0..num_breakpoints() {
+            if self.bcr[i] & CTRL_E != 0 {
+                write_bvr(i, self.bvr[i]);
+            }
+            write_bcr(i, self.bcr[i]);
+        }
+        for i in 0..num_watchpoints() {
+            if self.wcr[i] & CTRL_E != 0 {
+                write_wvr(i, self.wvr[i]);
+            }
+            write_wcr(i, self.wcr[i]);
+        }
+        unsafe { asm!("isb") };
+    }
+}
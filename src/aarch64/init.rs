@@ -106,10 +106,22 @@ pub unsafe fn init_mmu(root_paddr: PhysAddr) {
 /// Initializes trap handling on the current CPU.
 ///
 /// In detail, it initializes the exception vector, and sets `TTBR0_EL1` to 0 to
-/// block low address access.
+/// block low address access. With the `uspace` feature, it also clears the OS
+/// lock so that debug exceptions (e.g., software step) can be taken from EL0,
+/// and enables hardware breakpoints and watchpoints with the `hw-breakpoint`
+/// feature. With the `sve` feature, it configures the maximum vector lengths in
+/// `ZCR_EL1` and `SMCR_EL1`, and traps SVE and SME instructions until they are
+/// used by a task.
 pub fn init_trap() {
     #[cfg(feature = "uspace")]
-    crate::uspace_common::init_exception_table();
+    {
+        crate::uspace_common::init_exception_table();
+        OSLAR_EL1.set(0);
+    }
+    #[cfg(feature = "hw-breakpoint")]
+    crate::debug::init_percpu();
+    #[cfg(feature = "sve")]
+    super::sve::init();
     unsafe extern "C" {
         fn exception_vector_base();
     }
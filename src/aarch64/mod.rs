@@ -6,7 +6,19 @@ pub mod init;
 #[cfg(target_os = "none")]
 mod trap;
 
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) mod debug;
+
+#[cfg(feature = "sve")]
+mod sve;
+
+#[cfg(feature = "uspace")]
+mod coredump;
+#[cfg(feature = "uspace")]
+mod signal;
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
-pub use self::context::{FpState, TaskContext, TrapFrame};
+pub use self::context::{FpState, RawTrapCause, TaskContext, TrapFrame};
+#[cfg(feature = "sve")]
+pub use self::sve::{SveState, SVE_VL_MAX};
@@ -0,0 +1,216 @@
+//! Signal frames compatible with the Linux ABI.
+
+use core::mem::{offset_of, size_of};
+
+use super::uspace::UserContext;
+#[cfg(feature = "fp-simd")]
+use super::FpState;
+use crate::uspace_common::{
+    read_user, write_user, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+    SIGINFO_SIZE,
+};
+
+#[cfg(feature = "fp-simd")]
+const FPSIMD_MAGIC: u32 = 0x4650_8001;
+
+/// The condition flags (NZCV) in `SPSR_EL1`, the only bits of `pstate` that
+/// can be modified by the user.
+const PSTATE_NZCV: u64 = 0xf << 28;
+
+/// `struct _aarch64_ctx` in Linux, the header of a record in
+/// `sigcontext.__reserved`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CtxHeader {
+    magic: u32,
+    size: u32,
+}
+
+/// `struct fpsimd_context` in Linux.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct FpsimdContext {
+    head: CtxHeader,
+    fpsr: u32,
+    fpcr: u32,
+    vregs: [u128; 32],
+}
+
+/// `sigcontext.__reserved`, which holds a FP/SIMD record followed by a
+/// terminator record.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct Reserved {
+    fpsimd: FpsimdContext,
+    end: CtxHeader,
+    padding: [u8; 4096 - size_of::<FpsimdContext>() - size_of::<CtxHeader>()],
+}
+
+/// `struct sigcontext` in Linux.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    fault_address: u64,
+    regs: [u64; 31],
+    sp: u64,
+    pc: u64,
+    pstate: u64,
+    reserved: Reserved,
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    uc_flags: u64,
+    uc_link: u64,
+    uc_stack: SignalStack,
+    uc_sigmask: u64,
+    unused: [u8; 128 - size_of::<u64>()],
+    uc_mcontext: SigContext,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+struct RtSigFrame {
+    info: [u8; SIGINFO_SIZE],
+    uc: UContext,
+}
+
+/// `struct frame_record` in Linux, which links the signal frame into the
+/// frame pointer chain.
+#[repr(C)]
+struct FrameRecord {
+    fp: u64,
+    lr: u64,
+}
+
+static_assertions::const_assert_eq!(size_of::<FpsimdContext>(), 528);
+static_assertions::const_assert_eq!(offset_of!(SigContext, reserved), 288);
+static_assertions::const_assert_eq!(size_of::<SigContext>(), 4384);
+static_assertions::const_assert_eq!(offset_of!(UContext, uc_mcontext), 176);
+static_assertions::const_assert_eq!(size_of::<RtSigFrame>(), 4688);
+
+impl FpsimdContext {
+    /// Saves the current FP/SIMD states into a record.
+    #[cfg(feature = "fp-simd")]
+    fn save() -> Self {
+        let mut fp_state = FpState::default();
+        fp_state.save();
+        Self {
+            head: CtxHeader {
+                magic: FPSIMD_MAGIC,
+                size: size_of::<Self>() as _,
+            },
+            fpsr: fp_state.fpsr,
+            fpcr: fp_state.fpcr,
+            vregs: fp_state.regs,
+        }
+    }
+
+    /// Returns an empty record, which also terminates the record list.
+    #[cfg(not(feature = "fp-simd"))]
+    fn save() -> Self {
+        Self {
+            head: CtxHeader { magic: 0, size: 0 },
+            fpsr: 0,
+            fpcr: 0,
+            vregs: [0; 32],
+        }
+    }
+}
+
+impl UserContext {
+    /// Pushes a signal frame onto the user stack, and redirects the execution
+    /// to the signal handler.
+    ///
+    /// The frame has the same layout as `struct rt_sigframe` in Linux. The
+    /// FP/SIMD record is saved only with the `fp-simd` feature. The handler is
+    /// called with the signal number, the address of `siginfo_t` and the
+    /// address of `ucontext_t`, and returns to [`SignalFrameParams::restorer`].
+    pub fn push_signal_frame(
+        &mut self,
+        params: &SignalFrameParams<'_>,
+    ) -> Result<(), UserAccessError> {
+        let sp = params.stack_top.unwrap_or(self.sp as usize);
+        let record_addr = sp.wrapping_sub(size_of::<FrameRecord>()) & !15;
+        let frame_addr = record_addr.wrapping_sub(size_of::<RtSigFrame>()) & !15;
+
+        let frame = RtSigFrame {
+            info: *params.siginfo,
+            uc: UContext {
+                uc_flags: 0,
+                uc_link: 0,
+                uc_stack: params.uc_stack,
+                uc_sigmask: params.sigmask,
+                unused: [0; _],
+                uc_mcontext: SigContext {
+                    fault_address: params.fault_addr as _,
+                    regs: self.x,
+                    sp: self.sp,
+                    pc: self.elr,
+                    pstate: self.spsr,
+                    reserved: Reserved {
+                        fpsimd: FpsimdContext::save(),
+                        end: CtxHeader { magic: 0, size: 0 },
+                        padding: [0; _],
+                    },
+                },
+            },
+        };
+        write_user(frame_addr, &frame)?;
+        write_user(
+            record_addr,
+            &FrameRecord {
+                fp: self.x[29],
+                lr: self.x[30],
+            },
+        )?;
+
+        self.x[0] = params.signo as _;
+        self.x[1] = (frame_addr + offset_of!(RtSigFrame, info)) as _;
+        self.x[2] = (frame_addr + offset_of!(RtSigFrame, uc)) as _;
+        self.x[29] = record_addr as _;
+        self.x[30] = params.restorer as _;
+        self.sp = frame_addr as _;
+        self.elr = params.handler as _;
+        Ok(())
+    }
+
+    /// Restores the context saved by [`push_signal_frame`], on the
+    /// `rt_sigreturn` syscall.
+    ///
+    /// Only the condition flags of `pstate` are restored.
+    ///
+    /// [`push_signal_frame`]: UserContext::push_signal_frame
+    pub fn restore_signal_frame(&mut self) -> Result<SignalReturn, UserAccessError> {
+        let frame_addr = self.sp as usize;
+        let uc: UContext = unsafe { read_user(frame_addr + offset_of!(RtSigFrame, uc))? };
+        let mc = &uc.uc_mcontext;
+
+        #[cfg(feature = "fp-simd")]
+        {
+            let fpsimd = &mc.reserved.fpsimd;
+            if fpsimd.head.magic == FPSIMD_MAGIC
+                && fpsimd.head.size as usize == size_of::<FpsimdContext>()
+            {
+                FpState {
+                    regs: fpsimd.vregs,
+                    fpcr: fpsimd.fpcr,
+                    fpsr: fpsimd.fpsr,
+                }
+                .restore();
+            }
+        }
+
+        self.x = mc.regs;
+        self.sp = mc.sp;
+        self.elr = mc.pc;
+        self.spsr = (self.spsr & !PSTATE_NZCV) | (mc.pstate & PSTATE_NZCV);
+
+        Ok(SignalReturn {
+            sigmask: uc.uc_sigmask,
+            uc_stack: uc.uc_stack,
+        })
+    }
+}
@@ -0,0 +1,525 @@
+//! SVE/SVE2 and SME states, which are enabled for a task on its first use.
+//!
+//! SVE and SME instructions are trapped by `CPACR_EL1.ZEN` and
+//! `CPACR_EL1.SMEN` until the current task uses them, so that tasks that never
+//! do so only save and restore the FP/SIMD registers. The vector lengths are
+//! chosen on the first use, and are kept until the task changes them.
+//!
+//! The kernel never runs in the streaming mode, where FP/SIMD instructions may
+//! be illegal: it is left on entry from user space and entered again on return.
+
+use core::arch::{asm, naked_asm};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use aarch64_cpu::registers::{CPACR_EL1, ID_AA64PFR0_EL1, ID_AA64PFR1_EL1};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+/// The maximum vector length in bytes (512 bits) of SVE and SME.
+///
+/// Longer vector lengths of the hardware are constrained to it by `ZCR_EL1` and
+/// `SMCR_EL1`.
+pub const SVE_VL_MAX: usize = 64;
+
+/// The size of Z0-Z31, P0-P15 and FFR with the maximum vector length.
+const REGS_SIZE: usize = 32 * SVE_VL_MAX + 17 * SVE_VL_MAX / 8;
+
+/// The size of the ZT0 register of SME2.
+const ZT0_SIZE: usize = 64;
+
+/// The exception class of SVE instructions trapped by `CPACR_EL1.ZEN`.
+const EC_SVE: u64 = 0b01_1001;
+/// The exception class of SME instructions trapped by `CPACR_EL1.SMEN`.
+const EC_SME: u64 = 0b01_1101;
+
+/// `SVCR.SM`: the streaming SVE mode is enabled.
+const SVCR_SM: u64 = 1 << 0;
+/// `SVCR.ZA`: the ZA storage is enabled.
+const SVCR_ZA: u64 = 1 << 1;
+
+/// `CPACR_EL1.SMEN`: SME instructions are not trapped at EL0 and EL1.
+const CPACR_SMEN: u64 = 0b11 << 24;
+
+/// `SMCR_EL1.FA64`: the full A64 instruction set is allowed in streaming mode.
+const SMCR_FA64: u64 = 1 << 31;
+/// `SMCR_EL1.EZT0`: ZT0 of SME2 is accessible.
+const SMCR_EZT0: u64 = 1 << 30;
+
+/// The maximum SVE vector length of the CPU in bytes, or 0 if SVE is not
+/// supported.
+static MAX_VL: AtomicUsize = AtomicUsize::new(0);
+/// The maximum streaming vector length of the CPU in bytes, or 0 if SME is not
+/// supported.
+static MAX_SVL: AtomicUsize = AtomicUsize::new(0);
+/// The `SMCR_EL1` bits other than the vector length.
+static SMCR_FLAGS: AtomicU64 = AtomicU64::new(0);
+
+/// The SVE and SME states of the task running on the current CPU, which are
+/// enabled on the first SVE or SME instruction of the task.
+#[percpu::def_percpu]
+static CURRENT_SVE_STATE: usize = 0;
+
+/// SVE/SVE2 and SME states of a task.
+///
+/// The registers are only saved and restored if the task has used SVE or SME,
+/// as indicated by non-zero [`vector_length`](Self::vector_length) or
+/// [`streaming_vector_length`](Self::streaming_vector_length).
+#[repr(C, align(16))]
+pub struct SveState {
+    /// Z0-Z31, followed by P0-P15 and FFR, stored with the (streaming) vector
+    /// length in use.
+    regs: [u8; REGS_SIZE],
+    /// The ZA array, which has as many rows as bytes in a row.
+    za: [u8; SVE_VL_MAX * SVE_VL_MAX],
+    /// The ZT0 register of SME2.
+    zt0: [u8; ZT0_SIZE],
+    /// The SVE vector length in bytes, or 0 if SVE has not been used.
+    vl: usize,
+    /// The streaming vector length in bytes, or 0 if SME has not been used.
+    svl: usize,
+    /// The requested SVE vector length in bytes, or 0 for the maximum.
+    vl_limit: usize,
+    /// The requested streaming vector length in bytes, or 0 for the maximum.
+    svl_limit: usize,
+    /// The `SVCR` register, i.e., the `SM` and `ZA` bits.
+    ///
+    /// `SM` is that of user space, whose streaming registers are kept in
+    /// `regs` while the task is in the kernel.
+    svcr: u64,
+    /// The `FPSR` register, which is reset on leaving the streaming mode.
+    fpsr: u64,
+}
+
+impl SveState {
+    /// Returns the maximum SVE vector length in bytes that can be used, or 0 if
+    /// SVE is not supported.
+    pub fn max_vector_length() -> usize {
+        MAX_VL.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum streaming vector length in bytes that can be used,
+    /// or 0 if SME is not supported.
+    pub fn max_streaming_vector_length() -> usize {
+        MAX_SVL.load(Ordering::Relaxed)
+    }
+
+    /// Returns the SVE vector length of the task in bytes, or 0 if the task has
+    /// not used SVE.
+    pub fn vector_length(&self) -> usize {
+        self.vl
+    }
+
+    /// Returns the streaming vector length of the task in bytes, or 0 if the
+    /// task has not used SME.
+    pub fn streaming_vector_length(&self) -> usize {
+        self.svl
+    }
+
+    /// Returns whether the task has used SVE or SME.
+    ///
+    /// With the `lazy-fp` feature, FP/SIMD instructions are always enabled for
+    /// such a task, as SVE and SME registers are only accessible with them.
+    pub fn in_use(&self) -> bool {
+        self.vl != 0 || self.svl != 0
+    }
+
+    /// Sets the SVE vector length in bytes, where 0 means the maximum.
+    ///
+    /// The SVE registers are discarded, and the length takes effect on the next
+    /// SVE instruction of the task. The hardware may choose a shorter length if
+    /// it is not supported. It must not be called on the running task.
+    pub fn set_vector_length(&mut self, vl: usize) {
+        self.vl_limit = vl;
+        self.vl = 0;
+    }
+
+    /// Sets the streaming vector length in bytes, where 0 means the maximum.
+    ///
+    /// The SME states (including the streaming mode and ZA) are discarded, and
+    /// the length takes effect on the next SME instruction of the task. It must
+    /// not be called on the running task.
+    pub fn set_streaming_vector_length(&mut self, svl: usize) {
+        self.svl_limit = svl;
+        self.svl = 0;
+        self.svcr = 0;
+    }
+
+    /// Saves the SVE and SME registers in use from CPU to this structure, and
+    /// disables ZA.
+    ///
+    /// The streaming registers have been saved by [`leave_streaming_mode`].
+    pub(super) fn save(&mut self) {
+        if self.svl != 0 {
+            let za = read_svcr() & SVCR_ZA;
+            if za != 0 {
+                unsafe { za_save(self.za.as_mut_ptr(), self.svl) };
+                if SMCR_FLAGS.load(Ordering::Relaxed) & SMCR_EZT0 != 0 {
+                    let zt0 = self.zt0.as_mut_ptr();
+                    unsafe { asm!(".arch_extension sme2", "str zt0, [{}]", in(reg) zt0) };
+                }
+            }
+            self.svcr = (self.svcr & SVCR_SM) | za;
+        }
+        if self.vl != 0 && self.svcr & SVCR_SM == 0 {
+            unsafe { sve_save(self.regs.as_mut_ptr(), true) };
+        }
+        if self.svcr & SVCR_ZA != 0 {
+            write_svcr(0);
+        }
+    }
+
+    /// Restores the SVE and SME registers in use from this structure to CPU,
+    /// and traps the first SVE or SME instruction if not used.
+    ///
+    /// The streaming registers are restored by [`enter_streaming_mode`].
+    pub(super) fn restore(&self) {
+        if Self::max_vector_length() != 0 {
+            if self.vl != 0 {
+                CPACR_EL1.modify(CPACR_EL1::ZEN::TrapNothing);
+            } else {
+                CPACR_EL1.modify(CPACR_EL1::ZEN::TrapEl0El1);
+            }
+        }
+        if Self::max_streaming_vector_length() != 0 {
+            if self.svl != 0 {
+                CPACR_EL1.set(CPACR_EL1.get() | CPACR_SMEN);
+            } else {
+                CPACR_EL1.set(CPACR_EL1.get() & !CPACR_SMEN);
+            }
+        }
+        unsafe { asm!("isb") };
+        if self.vl != 0 {
+            write_zcr(self.vl);
+        }
+        if self.svl != 0 {
+            write_smcr(self.svl);
+        }
+
+        if self.svcr & SVCR_ZA != 0 {
+            write_svcr(SVCR_ZA);
+            unsafe { za_restore(self.za.as_ptr(), self.svl) };
+            if SMCR_FLAGS.load(Ordering::Relaxed) & SMCR_EZT0 != 0 {
+                let zt0 = self.zt0.as_ptr();
+                unsafe { asm!(".arch_extension sme2", "ldr zt0, [{}]", in(reg) zt0) };
+            }
+        }
+        if self.vl != 0 && self.svcr & SVCR_SM == 0 {
+            unsafe { sve_restore(self.regs.as_ptr(), true) };
+        }
+    }
+
+    /// Records this structure as the states of the task switched in.
+    pub(super) fn set_current(&self) {
+        CURRENT_SVE_STATE.write_current(self as *const _ as usize);
+    }
+}
+
+impl Default for SveState {
+    fn default() -> Self {
+        unsafe { core::mem::MaybeUninit::zeroed().assume_init() }
+    }
+}
+
+impl fmt::Debug for SveState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SveState")
+            .field("vl", &self.vl)
+            .field("svl", &self.svl)
+            .field("svcr", &self.svcr)
+            .finish_non_exhaustive()
+    }
+}
+
+fn has_fa64() -> bool {
+    SMCR_FLAGS.load(Ordering::Relaxed) & SMCR_FA64 != 0
+}
+
+fn read_svcr() -> u64 {
+    let svcr;
+    unsafe { asm!(".arch_extension sme", "mrs {}, svcr", out(reg) svcr) };
+    svcr
+}
+
+fn write_svcr(svcr: u64) {
+    unsafe { asm!(".arch_extension sme", "msr svcr, {}", in(reg) svcr) };
+}
+
+/// Sets the SVE vector length in bytes (`ZCR_EL1.LEN`), and returns the length
+/// chosen by the hardware.
+fn write_zcr(vl: usize) -> usize {
+    let len = (vl / 16).max(1) - 1;
+    let real_vl: usize;
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            "msr zcr_el1, {}",
+            "isb",
+            "rdvl {}, #1",
+            in(reg) len,
+            out(reg) real_vl,
+        )
+    };
+    real_vl
+}
+
+/// Sets the streaming vector length in bytes (`SMCR_EL1.LEN`), and returns the
+/// length chosen by the hardware.
+fn write_smcr(svl: usize) -> usize {
+    let smcr = ((svl / 16).max(1) - 1) as u64 | SMCR_FLAGS.load(Ordering::Relaxed);
+    let real_svl: usize;
+    unsafe {
+        asm!(
+            ".arch_extension sme",
+            "msr smcr_el1, {}",
+            "isb",
+            "rdsvl {}, #1",
+            in(reg) smcr,
+            out(reg) real_svl,
+        )
+    };
+    real_svl
+}
+
+/// Detects SVE and SME, and configures `ZCR_EL1` and `SMCR_EL1` with the
+/// maximum vector lengths on the current CPU.
+///
+/// SVE and SME instructions are trapped until a task uses them.
+pub(super) fn init() {
+    if ID_AA64PFR0_EL1.read(ID_AA64PFR0_EL1::SVE) != 0 {
+        CPACR_EL1.modify(CPACR_EL1::ZEN::TrapNothing);
+        unsafe { asm!("isb") };
+        MAX_VL.store(write_zcr(SVE_VL_MAX), Ordering::Relaxed);
+        CPACR_EL1.modify(CPACR_EL1::ZEN::TrapEl0El1);
+    }
+    if (ID_AA64PFR1_EL1.get() >> 24) & 0xf != 0 {
+        let smfr0: u64;
+        unsafe { asm!(".arch_extension sme", "mrs {}, id_aa64smfr0_el1", out(reg) smfr0) };
+        let mut flags = 0;
+        if smfr0 & (1 << 63) != 0 {
+            flags |= SMCR_FA64;
+        }
+        // `SMEver` is non-zero with SME2.
+        if (smfr0 >> 56) & 0xf != 0 {
+            flags |= SMCR_EZT0;
+        }
+        SMCR_FLAGS.store(flags, Ordering::Relaxed);
+        CPACR_EL1.set(CPACR_EL1.get() | CPACR_SMEN);
+        unsafe {
+            asm!("isb");
+            // Streaming execution priority is not used.
+            asm!(".arch_extension sme", "msr smpri_el1, xzr");
+        }
+        // The hardware may not support any length up to the maximum.
+        let max_svl = write_smcr(SVE_VL_MAX);
+        if max_svl <= SVE_VL_MAX {
+            MAX_SVL.store(max_svl, Ordering::Relaxed);
+        }
+        CPACR_EL1.set(CPACR_EL1.get() & !CPACR_SMEN);
+    }
+    unsafe { asm!("isb") };
+}
+
+/// Handles the trap of the first SVE or SME instruction of the current task
+/// (with the syndrome `esr`), by enabling it with the requested vector length.
+///
+/// The SVE registers are initialized from the FP/SIMD registers, and the SME
+/// states are initialized when the streaming mode or ZA is enabled.
+///
+/// Returns `false` if the trap is not caused by the first use, e.g., SVE or
+/// SME is already enabled or not supported.
+pub(crate) fn handle_sve_trap(esr: u64) -> bool {
+    let Some(state) = current() else {
+        return false;
+    };
+    match (esr >> 26) & 0x3f {
+        EC_SVE => {
+            let max_vl = SveState::max_vector_length();
+            if max_vl == 0 || CPACR_EL1.matches_all(CPACR_EL1::ZEN::TrapNothing) {
+                return false;
+            }
+            CPACR_EL1.modify(CPACR_EL1::ZEN::TrapNothing);
+            unsafe { asm!("isb") };
+            state.vl = write_zcr(limit(state.vl_limit, max_vl));
+            unsafe { sve_init() };
+        }
+        EC_SME => {
+            let max_svl = SveState::max_streaming_vector_length();
+            if max_svl == 0 || CPACR_EL1.get() & CPACR_SMEN == CPACR_SMEN {
+                return false;
+            }
+            // Unlike SVE ones, SME instructions such as `smstart` are not
+            // trapped by `CPACR_EL1.FPEN`, so the FP/SIMD states are loaded
+            // here to keep them enabled while SME is in use.
+            #[cfg(feature = "lazy-fp")]
+            super::context::handle_lazy_fp_trap();
+            CPACR_EL1.set(CPACR_EL1.get() | CPACR_SMEN);
+            unsafe { asm!("isb") };
+            state.svl = write_smcr(limit(state.svl_limit, max_svl));
+            state.svcr = 0;
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Leaves the streaming mode of the current task on entry to the kernel from
+/// user space (with a syscall if `syscall` is true), keeping ZA enabled.
+///
+/// The streaming registers are saved to be restored by
+/// [`enter_streaming_mode`], except that syscalls leave the streaming mode of
+/// user space and discard them, as Linux does.
+pub(super) fn leave_streaming_mode(syscall: bool) {
+    let Some(state) = current() else {
+        return;
+    };
+    if state.svl == 0 {
+        return;
+    }
+    state.svcr = read_svcr();
+    if state.svcr & SVCR_SM == 0 {
+        return;
+    }
+    if syscall {
+        state.svcr &= !SVCR_SM;
+    } else {
+        unsafe { asm!(".arch_extension fp", "mrs {}, fpsr", out(reg) state.fpsr) };
+        unsafe { sve_save(state.regs.as_mut_ptr(), has_fa64()) };
+    }
+    unsafe { asm!(".arch_extension sme", "smstop sm") };
+}
+
+/// Enters the streaming mode of the current task again before returning to
+/// user space, and restores the registers saved by [`leave_streaming_mode`].
+///
+/// It must be called with IRQs disabled, so that the task is not switched out
+/// before returning to user space.
+pub(super) fn enter_streaming_mode() {
+    let Some(state) = current() else {
+        return;
+    };
+    if state.svcr & SVCR_SM != 0 {
+        unsafe { asm!(".arch_extension sme", "smstart sm") };
+        unsafe { sve_restore(state.regs.as_ptr(), has_fa64()) };
+        // Entering the streaming mode resets `FPSR`.
+        unsafe { asm!(".arch_extension fp", "msr fpsr, {}", in(reg) state.fpsr) };
+    }
+}
+
+/// Returns the SVE and SME states of the task running on the current CPU.
+fn current() -> Option<&'static mut SveState> {
+    let ptr = CURRENT_SVE_STATE.read_current() as *mut SveState;
+    // SAFETY: the states of the running task are only accessed by this CPU.
+    unsafe { ptr.as_mut() }
+}
+
+/// Returns the requested vector length, or the maximum if not requested.
+fn limit(requested: usize, max: usize) -> usize {
+    if requested == 0 {
+        max
+    } else {
+        requested.min(max)
+    }
+}
+
+/// Saves Z0-Z31, P0-P15 and FFR (if `ffr` is true) with the current vector
+/// length.
+#[unsafe(naked)]
+unsafe extern "C" fn sve_save(_regs: *mut u8, _ffr: bool) {
+    naked_asm!(
+        r"
+        .arch_extension sve
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        str     z\i, [x0, #\i, mul vl]
+        .endr
+        addvl   x0, x0, #16
+        addvl   x0, x0, #16
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15
+        str     p\i, [x0, #\i, mul vl]
+        .endr
+        cbz     w1, 1f
+        rdffr   p0.b
+        str     p0, [x0, #16, mul vl]
+        ldr     p0, [x0]
+    1:  ret"
+    )
+}
+
+/// Restores Z0-Z31, P0-P15 and FFR (if `ffr` is true) with the current vector
+/// length.
+#[unsafe(naked)]
+unsafe extern "C" fn sve_restore(_regs: *const u8, _ffr: bool) {
+    naked_asm!(
+        r"
+        .arch_extension sve
+        addvl   x2, x0, #16
+        addvl   x2, x2, #16
+        cbz     w1, 1f
+        ldr     p0, [x2, #16, mul vl]
+        wrffr   p0.b
+    1:
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15
+        ldr     p\i, [x2, #\i, mul vl]
+        .endr
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        ldr     z\i, [x0, #\i, mul vl]
+        .endr
+        ret"
+    )
+}
+
+/// Clears the bits of Z0-Z31 above the FP/SIMD registers, P0-P15 and FFR.
+#[unsafe(naked)]
+unsafe extern "C" fn sve_init() {
+    naked_asm!(
+        r"
+        .arch_extension sve
+        .arch_extension simd
+        // Writing a V register clears the upper bits of the Z register.
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        mov     v\i\().16b, v\i\().16b
+        .endr
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15
+        pfalse  p\i\().b
+        .endr
+        wrffr   p0.b
+        ret"
+    )
+}
+
+/// Saves the `svl` rows of the ZA array.
+unsafe fn za_save(za: *mut u8, svl: usize) {
+    unsafe {
+        asm!(
+            ".arch_extension sme",
+            "mov w12, #0",
+            "1:",
+            "str za[w12, 0], [{za}]",
+            "add {za}, {za}, {svl}",
+            "add w12, w12, #1",
+            "cmp x12, {svl}",
+            "b.lo 1b",
+            za = inout(reg) za => _,
+            svl = in(reg) svl,
+            out("x12") _,
+        )
+    }
+}
+
+/// Restores the `svl` rows of the ZA array.
+unsafe fn za_restore(za: *const u8, svl: usize) {
+    unsafe {
+        asm!(
+            ".arch_extension sme",
+            "mov w12, #0",
+            "1:",
+            "ldr za[w12, 0], [{za}]",
+            "add {za}, {za}, {svl}",
+            "add w12, w12, #1",
+            "cmp x12, {svl}",
+            "b.lo 1b",
+            za = inout(reg) za => _,
+            svl = in(reg) svl,
+            out("x12") _,
+        )
+    }
+}
@@ -2,14 +2,19 @@
 // Copyright (C) 2025 The axcpu Authors.
 // Copyright (C) 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSE for license details.
-// 
+//
 // This file has been modified by KylinSoft on 2025.
 
 use aarch64_cpu::registers::{ESR_EL1, FAR_EL1};
 use tock_registers::interfaces::Readable;
 
-use super::TrapFrame;
-use crate::trap::PageFaultFlags;
+use memory_addr::VirtAddr;
+
+use super::{RawTrapCause, TrapFrame};
+use crate::trap::{
+    handle_exception, handle_syscall, handle_unhandled_trap, trap_enter, trap_exit, PageFaultFlags,
+    PageFaultInfo, PageFaultKind, TrapCause,
+};
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -44,13 +49,84 @@ core::arch::global_asm!(
 
 #[inline(always)]
 pub(super) fn is_valid_page_fault(iss: u64) -> bool {
-    // Only handle Translation fault and Permission fault
-    matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
+    // Only handle Translation fault, Access flag fault and Permission fault
+    matches!(iss & 0b111100, 0b0100 | 0b1000 | 0b1100) // IFSC or DFSC bits
+}
+
+#[inline(always)]
+pub(super) fn is_alignment_fault(iss: u64) -> bool {
+    iss & 0b111111 == 0b100001 // DFSC: Alignment fault
+}
+
+/// Decodes the cause of a trap of the given kind from `ESR_EL1` and `FAR_EL1`.
+pub(super) fn trap_cause(kind: &TrapKind) -> TrapCause {
+    use ESR_EL1::EC::Value as EC;
+
+    let esr = ESR_EL1.extract();
+    let far = FAR_EL1.get();
+    let raw = RawTrapCause {
+        esr: esr.get(),
+        far,
+    };
+    let iss = esr.read(ESR_EL1::ISS);
+    let ec = esr.read_as_enum(ESR_EL1::EC);
+    let user = if matches!(ec, Some(EC::InstrAbortLowerEL | EC::DataAbortLowerEL)) {
+        PageFaultFlags::USER
+    } else {
+        PageFaultFlags::empty()
+    };
+    match kind {
+        TrapKind::Irq => TrapCause::Irq(0, raw),
+        TrapKind::Fiq | TrapKind::SError => TrapCause::Other(raw),
+        TrapKind::Synchronous => match ec {
+            Some(EC::InstrAbortCurrentEL | EC::InstrAbortLowerEL) if is_valid_page_fault(iss) => {
+                TrapCause::PageFault(va!(far as usize), PageFaultFlags::EXECUTE | user, raw)
+            }
+            Some(EC::DataAbortCurrentEL | EC::DataAbortLowerEL) if is_valid_page_fault(iss) => {
+                let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
+                let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
+                let access_flags = if wnr & !cm {
+                    PageFaultFlags::WRITE
+                } else {
+                    PageFaultFlags::READ
+                };
+                TrapCause::PageFault(va!(far as usize), access_flags | user, raw)
+            }
+            Some(EC::DataAbortCurrentEL | EC::DataAbortLowerEL) if is_alignment_fault(iss) => {
+                TrapCause::Misaligned(Some(va!(far as usize)), raw)
+            }
+            Some(EC::PCAlignmentFault) => TrapCause::Misaligned(Some(va!(far as usize)), raw),
+            Some(EC::SPAlignmentFault) => TrapCause::Misaligned(None, raw),
+            Some(EC::Brk64 | EC::BreakpointCurrentEL | EC::BreakpointLowerEL) => {
+                TrapCause::Breakpoint(raw)
+            }
+            Some(EC::Unknown | EC::IllegalExecutionState) => TrapCause::IllegalInstruction(raw),
+            Some(EC::TrappedFP | EC::TrappedFP64 | EC::TrappedSve) => TrapCause::FpFault(raw),
+            // SME instructions trapped by `CPACR_EL1.SMEN` or the streaming mode.
+            None if esr.read(ESR_EL1::EC) == 0b01_1101 => TrapCause::FpFault(raw),
+            Some(EC::SVC64) => TrapCause::Syscall(raw),
+            _ => TrapCause::Other(raw),
+        },
+    }
+}
+
+fn page_fault_kind(raw: &RawTrapCause) -> PageFaultKind {
+    match raw.esr & 0b111100 {
+        0b0100 => PageFaultKind::Translation,
+        0b1000 => PageFaultKind::AccessFlag,
+        0b1100 => PageFaultKind::Permission,
+        _ => PageFaultKind::Unknown,
+    }
 }
 
-fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
-    let vaddr = va!(FAR_EL1.get() as usize);
-    if handle_trap!(PAGE_FAULT, vaddr, access_flags) {
+fn handle_page_fault(
+    tf: &mut TrapFrame,
+    vaddr: VirtAddr,
+    access_flags: PageFaultFlags,
+    raw: RawTrapCause,
+) {
+    let info = PageFaultInfo::new(tf, vaddr, access_flags, page_fault_kind(&raw), raw);
+    if handle_trap!(PAGE_FAULT, tf, &info) {
         return;
     }
     #[cfg(feature = "uspace")]
@@ -58,6 +134,9 @@ fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
         return;
     }
     core::hint::cold_path();
+    if handle_unhandled_trap(tf, TrapCause::PageFault(vaddr, access_flags, raw)) {
+        return;
+    }
     panic!(
         "Unhandled EL1 Page Fault @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}\n{}",
         tf.elr,
@@ -75,55 +154,59 @@ fn aarch64_trap_handler(tf: &mut TrapFrame, kind: TrapKind, source: TrapSource)
         source,
         TrapSource::CurrentSpEl0 | TrapSource::LowerAArch64 | TrapSource::LowerAArch32
     ) {
+        if handle_unhandled_trap(tf, trap_cause(&kind)) {
+            return;
+        }
         panic!(
             "Invalid exception {:?} from {:?}:\n{:#x?}",
             kind, source, tf
         );
     }
-    match kind {
-        TrapKind::Fiq | TrapKind::SError => {
-            panic!("Unhandled exception {:?}:\n{:#x?}", kind, tf);
+    let cause = trap_cause(&kind);
+    trap_enter(tf, cause, false);
+    match cause {
+        TrapCause::Irq(irq, _) => {
+            handle_trap!(IRQ, irq);
         }
-        TrapKind::Irq => {
-            handle_trap!(IRQ, 0);
+        TrapCause::PageFault(vaddr, access_flags, raw) => {
+            handle_page_fault(tf, vaddr, access_flags, raw)
         }
-        TrapKind::Synchronous => {
-            let esr = ESR_EL1.extract();
-            let iss = esr.read(ESR_EL1::ISS);
-            match esr.read_as_enum(ESR_EL1::EC) {
-                Some(ESR_EL1::EC::Value::InstrAbortCurrentEL) if is_valid_page_fault(iss) => {
-                    handle_page_fault(tf, PageFaultFlags::EXECUTE);
-                }
-                Some(ESR_EL1::EC::Value::DataAbortCurrentEL) if is_valid_page_fault(iss) => {
-                    let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
-                    let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
-                    handle_page_fault(
-                        tf,
-                        if wnr & !cm {
-                            PageFaultFlags::WRITE
-                        } else {
-                            PageFaultFlags::READ
-                        },
-                    );
+        // `ELR_EL1` already points to the instruction after `svc`.
+        TrapCause::Syscall(_) if handle_syscall(tf) => {}
+        #[cfg(feature = "lazy-fp")]
+        TrapCause::FpFault(_) if super::context::handle_lazy_fp_trap() => {}
+        _ if handle_exception(tf, cause) => {}
+        _ => match kind {
+            TrapKind::Fiq | TrapKind::SError => {
+                if !handle_unhandled_trap(tf, cause) {
+                    panic!("Unhandled exception {:?}:\n{:#x?}", kind, tf);
                 }
-                Some(ESR_EL1::EC::Value::Brk64) => {
-                    debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
-                    tf.elr += 4;
-                }
-                e => {
-                    let vaddr = va!(FAR_EL1.get() as usize);
-                    panic!(
-                        "Unhandled synchronous exception {:?} @ {:#x}: ESR={:#x} (EC {:#08b}, FAR: {:#x} ISS {:#x})\n{}",
-                        e,
-                        tf.elr,
-                        esr.get(),
-                        esr.read(ESR_EL1::EC),
-                        vaddr,
-                        iss,
-                        tf.backtrace()
-                    );
+            }
+            _ => {
+                let esr = ESR_EL1.extract();
+                let iss = esr.read(ESR_EL1::ISS);
+                match esr.read_as_enum(ESR_EL1::EC) {
+                    Some(ESR_EL1::EC::Value::Brk64) => {
+                        debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
+                        tf.elr += 4;
+                    }
+                    _ if handle_unhandled_trap(tf, cause) => {}
+                    e => {
+                        let vaddr = va!(FAR_EL1.get() as usize);
+                        panic!(
+                            "Unhandled synchronous exception {:?} @ {:#x}: ESR={:#x} (EC {:#08b}, FAR: {:#x} ISS {:#x})\n{}",
+                            e,
+                            tf.elr,
+                            esr.get(),
+                            esr.read(ESR_EL1::EC),
+                            vaddr,
+                            iss,
+                            tf.backtrace()
+                        );
+                    }
                 }
             }
-        }
+        },
     }
+    trap_exit(tf, cause, false);
 }
@@ -13,10 +13,21 @@ use aarch64_cpu::registers::{Readable, ESR_EL1, FAR_EL1};
 use memory_addr::VirtAddr;
 use tock_registers::LocalRegisterCopy;
 
-use super::trap::{is_valid_page_fault, TrapKind};
-use crate::{trap::PageFaultFlags, TrapFrame};
+use super::trap::{is_alignment_fault, is_valid_page_fault, trap_cause, TrapKind};
+use crate::{
+    trap::{trap_enter, trap_exit, PageFaultFlags},
+    uspace_common::siginfo::*,
+    TrapFrame,
+};
 
-pub use crate::uspace_common::{ExceptionKind, ReturnReason};
+pub use super::coredump::*;
+pub use crate::uspace_common::{
+    ExceptionKind, ReturnReason, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+    NT_PRFPREG, NT_PRSTATUS, SIGINFO_SIZE,
+};
+
+/// The end of the user address space (the range translated by `TTBR0_EL1`).
+pub(crate) const USER_SPACE_END: usize = 1 << 48;
 
 /// Context to enter user space.
 #[repr(C, align(16))]
@@ -27,10 +38,10 @@ pub struct UserContext {
     pub sp: u64,
     /// Software Thread ID Register (TPIDR_EL0).
     pub tpidr: u64,
+    single_step: bool,
 }
 
 impl UserContext {
-    const PAD_MAGIC: u64 = 0x1234_5678_9abc_def0;
     /// Creates a new context with the given entry point, user stack pointer,
     /// and the argument.
     pub fn new(entry: usize, ustack_top: VirtAddr, arg0: usize) -> Self {
@@ -47,10 +58,11 @@ impl UserContext {
                     + SPSR_EL1::I::Unmasked
                     + SPSR_EL1::F::Masked)
                     .value,
-                __pad: Self::PAD_MAGIC,
+                orig_arg0: 0,
             },
             sp: ustack_top.as_usize() as _,
             tpidr: 0,
+            single_step: false,
         }
     }
 
@@ -74,26 +86,84 @@ impl UserContext {
         self.tpidr = tls as _;
     }
 
+    /// Enables or disables single-stepping.
+    ///
+    /// When enabled, [`run`](Self::run) returns [`ReturnReason::SingleStep`]
+    /// after a single user instruction is executed. It is implemented with the
+    /// software step (`MDSCR_EL1.SS` and `SPSR_EL1.SS`), which are set up on
+    /// each entry to user space.
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
+    }
+
     /// Enters user space.
     ///
     /// It restores the user registers and jumps to the user entry point
     /// (saved in `elr`).
     ///
     /// This function returns when an exception or syscall occurs.
+    /// With the `lazy-fp` feature, the trap of the first FP/SIMD instruction
+    /// after a context switch is handled internally, without returning.
     pub fn run(&mut self) -> ReturnReason {
+        loop {
+            if let Some(ret) = self.run_once() {
+                return ret;
+            }
+        }
+    }
+
+    /// Enters user space once, and returns [`None`] if the trap is handled
+    /// internally, so that user space should be entered again.
+    fn run_once(&mut self) -> Option<ReturnReason> {
         extern "C" {
             fn enter_user(uctx: &mut UserContext) -> TrapKind;
         }
 
         crate::asm::disable_irqs();
+        // `SPSR_EL1.SS` is cleared once the step is completed, so set it again.
+        if self.single_step {
+            self.spsr |= SPSR_SS;
+            set_software_step(true);
+        } else {
+            self.spsr &= !SPSR_SS;
+        }
+        #[cfg(feature = "sve")]
+        super::sve::enter_streaming_mode();
         let kind = unsafe { enter_user(self) };
+        if self.single_step {
+            set_software_step(false);
+        }
+        let cause = trap_cause(&kind);
+        #[cfg(feature = "sve")]
+        super::sve::leave_streaming_mode(matches!(cause, crate::trap::TrapCause::Syscall(_)));
+        trap_enter(self, cause, true);
+
+        #[cfg(feature = "lazy-fp")]
+        if matches!(cause, crate::trap::TrapCause::FpFault(_))
+            && super::context::handle_lazy_fp_trap()
+        {
+            // Re-execute the trapped instruction with FP/SIMD enabled.
+            trap_exit(self, cause, true);
+            return None;
+        }
+        #[cfg(feature = "sve")]
+        if let crate::trap::TrapCause::FpFault(raw) = cause {
+            if super::sve::handle_sve_trap(raw.esr) {
+                // Re-execute the trapped instruction with SVE or SME enabled.
+                trap_exit(self, cause, true);
+                return None;
+            }
+        }
 
         let ret = match kind {
             TrapKind::Irq => {
                 handle_trap!(IRQ, 0);
                 ReturnReason::Interrupt
             }
-            TrapKind::Fiq | TrapKind::SError => ReturnReason::Unknown,
+            TrapKind::Fiq => ReturnReason::Fiq,
+            TrapKind::SError => ReturnReason::AsyncError {
+                syndrome: ESR_EL1.get() as usize,
+            },
             TrapKind::Synchronous => {
                 let esr = ESR_EL1.extract();
                 let far = FAR_EL1.get() as usize;
@@ -101,7 +171,19 @@ impl UserContext {
                 let iss = esr.read(ESR_EL1::ISS);
 
                 match esr.read_as_enum(ESR_EL1::EC) {
-                    Some(ESR_EL1::EC::Value::SVC64) => ReturnReason::Syscall,
+                    Some(ESR_EL1::EC::Value::SVC64) => {
+                        self.orig_arg0 = self.x[0];
+                        ReturnReason::Syscall
+                    }
+                    Some(ESR_EL1::EC::Value::SoftwareStepLowerEL) => ReturnReason::SingleStep,
+                    #[cfg(feature = "hw-breakpoint")]
+                    Some(ESR_EL1::EC::Value::BreakpointLowerEL) => {
+                        ReturnReason::Watchpoint(va!(self.elr as usize))
+                    }
+                    #[cfg(feature = "hw-breakpoint")]
+                    Some(ESR_EL1::EC::Value::WatchpointLowerEL) => {
+                        ReturnReason::Watchpoint(va!(far))
+                    }
                     Some(ESR_EL1::EC::Value::InstrAbortLowerEL) if is_valid_page_fault(iss) => {
                         ReturnReason::PageFault(
                             va!(far),
@@ -120,14 +202,35 @@ impl UserContext {
                             } | PageFaultFlags::USER,
                         )
                     }
-                    _ => ReturnReason::Exception(ExceptionInfo { esr, far }),
+                    _ => ReturnReason::Exception(ExceptionInfo {
+                        esr,
+                        far,
+                        elr: self.elr as _,
+                    }),
                 }
             }
         };
 
+        trap_exit(self, cause, true);
         crate::asm::enable_irqs();
-        ret
+        Some(ret)
+    }
+}
+
+/// The software step bit in `SPSR_EL1`.
+const SPSR_SS: u64 = 1 << 21;
+
+/// Enables or disables software step exceptions from EL0 (`MDSCR_EL1.SS`).
+fn set_software_step(enable: bool) {
+    const MDSCR_SS: u64 = 1 << 0;
+    let mut mdscr: u64;
+    unsafe { core::arch::asm!("mrs {}, mdscr_el1", out(reg) mdscr) };
+    if enable {
+        mdscr |= MDSCR_SS;
+    } else {
+        mdscr &= !MDSCR_SS;
     }
+    unsafe { core::arch::asm!("msr mdscr_el1, {}; isb", in(reg) mdscr) };
 }
 
 impl Deref for UserContext {
@@ -151,17 +254,78 @@ pub struct ExceptionInfo {
     pub esr: LocalRegisterCopy<u64, ESR_EL1::Register>,
     /// Fault Address Register
     pub far: usize,
+    /// Exception Link Register, i.e., the address of the faulting instruction
+    pub elr: usize,
 }
 
 impl ExceptionInfo {
     /// Returns a generalized kind of this exception.
     pub fn kind(&self) -> ExceptionKind {
+        use ESR_EL1::EC::Value as EC;
+        let iss = self.esr.read(ESR_EL1::ISS);
         match self.esr.read_as_enum(ESR_EL1::EC) {
-            Some(ESR_EL1::EC::Value::BreakpointLowerEL) => ExceptionKind::Breakpoint,
-            Some(ESR_EL1::EC::Value::IllegalExecutionState) => ExceptionKind::IllegalInstruction,
-            Some(ESR_EL1::EC::Value::PCAlignmentFault)
-            | Some(ESR_EL1::EC::Value::SPAlignmentFault) => ExceptionKind::Misaligned,
+            Some(EC::Brk64) => ExceptionKind::Breakpoint,
+            Some(EC::BreakpointLowerEL)
+            | Some(EC::SoftwareStepLowerEL)
+            | Some(EC::WatchpointLowerEL) => ExceptionKind::Debug,
+            Some(EC::Unknown)
+            | Some(EC::IllegalExecutionState)
+            | Some(EC::BranchTarget)
+            | Some(EC::TrappedMsrMrs) => ExceptionKind::IllegalInstruction,
+            Some(EC::PCAlignmentFault) | Some(EC::SPAlignmentFault) => ExceptionKind::Misaligned,
+            Some(EC::DataAbortLowerEL) if is_alignment_fault(iss) => ExceptionKind::Misaligned,
+            Some(EC::TrappedFP64) => ExceptionKind::FloatingPoint,
             _ => ExceptionKind::Other,
         }
     }
+
+    /// Returns the signal to deliver for this exception, as
+    /// `(signo, si_code, si_addr)`, following Linux.
+    pub fn to_signal(&self) -> (usize, i32, usize) {
+        use ESR_EL1::EC::Value as EC;
+        let iss = self.esr.read(ESR_EL1::ISS);
+        match self.esr.read_as_enum(ESR_EL1::EC) {
+            Some(EC::Brk64) => (SIGTRAP, TRAP_BRKPT, self.elr),
+            Some(EC::BreakpointLowerEL) => (SIGTRAP, TRAP_HWBKPT, self.elr),
+            Some(EC::WatchpointLowerEL) => (SIGTRAP, TRAP_HWBKPT, self.far),
+            Some(EC::SoftwareStepLowerEL) => (SIGTRAP, TRAP_TRACE, self.elr),
+            Some(EC::PCAlignmentFault) | Some(EC::SPAlignmentFault) => {
+                (SIGBUS, BUS_ADRALN, self.far)
+            }
+            Some(EC::TrappedFP64) => (SIGFPE, fp_exception_code(iss), self.elr),
+            Some(EC::DataAbortLowerEL) | Some(EC::InstrAbortLowerEL) => {
+                let code = match iss & 0b111111 {
+                    0b100001 => return (SIGBUS, BUS_ADRALN, self.far),
+                    // Synchronous external abort or parity error
+                    0b010000..=0b011111 => return (SIGBUS, BUS_OBJERR, self.far),
+                    // Access flag fault or permission fault
+                    0b001000..=0b001111 => SEGV_ACCERR,
+                    // Address size fault, translation fault, etc.
+                    _ => SEGV_MAPERR,
+                };
+                (SIGSEGV, code, self.far)
+            }
+            _ => (SIGILL, ILL_ILLOPC, self.elr),
+        }
+    }
+}
+
+/// Returns the `si_code` of a trapped floating-point exception from the ISS.
+fn fp_exception_code(iss: u64) -> i32 {
+    const TFV: u64 = 1 << 23; // Trapped Fault Valid
+    if iss & TFV == 0 {
+        FPE_FLTUNK
+    } else if iss & (1 << 0) != 0 {
+        FPE_FLTINV // IOF: Invalid Operation
+    } else if iss & (1 << 1) != 0 {
+        FPE_FLTDIV // DZF: Divide by Zero
+    } else if iss & (1 << 2) != 0 {
+        FPE_FLTOVF // OFF: Overflow
+    } else if iss & (1 << 3) != 0 {
+        FPE_FLTUND // UFF: Underflow
+    } else if iss & (1 << 4) != 0 {
+        FPE_FLTRES // IXF: Inexact
+    } else {
+        FPE_FLTUNK
+    }
 }
@@ -0,0 +1,11 @@
+//! Layouts of the register notes in ELF core dumps of each architecture.
+//!
+//! They are plain data transforms independent of the CPU, so that they are
+//! built on every target and tested on the host. The notes of the current
+//! architecture are built from the CPU states by
+//! `UserContext::to_elf_gregset` and the like.
+
+pub mod aarch64;
+pub mod loongarch64;
+pub mod riscv;
+pub mod x86_64;
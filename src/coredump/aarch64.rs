@@ -0,0 +1,66 @@
+//! Register notes of AArch64.
+
+/// The number of registers in `elf_gregset_t`.
+pub const ELF_NGREG: usize = 34;
+
+/// The general-purpose registers in an `NT_PRSTATUS` note, laid out as
+/// `struct user_pt_regs`.
+pub type ElfGregset = [u64; ELF_NGREG];
+
+/// The size of the `NT_PRFPREG` note (`struct user_fpsimd_state`) in bytes.
+pub const ELF_FPREGSET_SIZE: usize = 528;
+
+/// Builds `elf_gregset_t` from `X0`-`X30`, `SP`, `PC` and `PSTATE`.
+pub fn gregset(x: &[u64; 31], sp: u64, pc: u64, pstate: u64) -> ElfGregset {
+    let mut regs = [0; ELF_NGREG];
+    regs[..31].copy_from_slice(x);
+    regs[31] = sp;
+    regs[32] = pc;
+    regs[33] = pstate;
+    regs
+}
+
+/// Builds the `NT_PRFPREG` note from `V0`-`V31`, `FPSR` and `FPCR`, followed
+/// by 8 reserved bytes.
+pub fn fpregset(v: &[u128; 32], fpsr: u32, fpcr: u32) -> [u8; ELF_FPREGSET_SIZE] {
+    let mut buf = [0; ELF_FPREGSET_SIZE];
+    for (chunk, reg) in buf[..512].chunks_exact_mut(16).zip(v) {
+        chunk.copy_from_slice(&reg.to_ne_bytes());
+    }
+    buf[512..516].copy_from_slice(&fpsr.to_ne_bytes());
+    buf[516..520].copy_from_slice(&fpcr.to_ne_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gregset_layout() {
+        let mut x = [0; 31];
+        x[0] = 0x1234;
+        x[30] = 0x40_2000;
+
+        let regs = gregset(&x, 0x7fff_0000, 0x40_1000, 0x3c5);
+        assert_eq!(regs[0], 0x1234); // x0
+        assert_eq!(regs[30], 0x40_2000); // x30
+        assert_eq!(regs[31], 0x7fff_0000); // sp
+        assert_eq!(regs[32], 0x40_1000); // pc
+        assert_eq!(regs[33], 0x3c5); // pstate
+    }
+
+    #[test]
+    fn fpregset_layout() {
+        let mut v = [0; 32];
+        v[0] = 0x0011_2233_4455_6677_8899_aabb_ccdd_eeff;
+        v[31] = 0x1234;
+
+        let buf = fpregset(&v, 0x1f, 0x0300_0000);
+        assert_eq!(buf[..16], v[0].to_ne_bytes());
+        assert_eq!(buf[496..512], 0x1234_u128.to_ne_bytes());
+        assert_eq!(buf[512..516], 0x1f_u32.to_ne_bytes()); // fpsr
+        assert_eq!(buf[516..520], 0x0300_0000_u32.to_ne_bytes()); // fpcr
+        assert_eq!(buf[520..], [0; 8]);
+    }
+}
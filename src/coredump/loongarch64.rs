@@ -0,0 +1,72 @@
+//! Register notes of LoongArch64.
+
+/// The number of registers in `elf_gregset_t`.
+pub const ELF_NGREG: usize = 45;
+
+/// The general-purpose registers in an `NT_PRSTATUS` note, laid out as
+/// `struct user_pt_regs`.
+pub type ElfGregset = [u64; ELF_NGREG];
+
+/// The size of the `NT_PRFPREG` note (`struct user_fp_state`) in bytes.
+pub const ELF_FPREGSET_SIZE: usize = 272;
+
+/// Builds `elf_gregset_t` from `r0`-`r31`, `orig_a0` and `era`, where `r0` is
+/// always 0, and `badv` and the reserved registers are set to 0.
+pub fn gregset(r: &[u64; 32], orig_a0: u64, era: u64) -> ElfGregset {
+    let mut regs = [0; ELF_NGREG];
+    regs[1..32].copy_from_slice(&r[1..]);
+    regs[32] = orig_a0;
+    regs[33] = era;
+    regs
+}
+
+/// Builds the `NT_PRFPREG` note from `f0`-`f31`, the condition flags
+/// `fcc0`-`fcc7` (one byte each) and `fcsr`, padded to 8 bytes.
+pub fn fpregset(f: &[u64; 32], fcc: &[u8; 8], fcsr: u32) -> [u8; ELF_FPREGSET_SIZE] {
+    let mut buf = [0; ELF_FPREGSET_SIZE];
+    for (chunk, reg) in buf[..256].chunks_exact_mut(8).zip(f) {
+        chunk.copy_from_slice(&reg.to_ne_bytes());
+    }
+    buf[256..264].copy_from_slice(fcc);
+    buf[264..268].copy_from_slice(&fcsr.to_ne_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gregset_layout() {
+        let mut r = [0; 32];
+        r[0] = 0xdead; // the kernel stack pointer
+        r[1] = 0x12_1000;
+        r[3] = 0x7fff_0000;
+        r[4] = 0x1234;
+        r[31] = 0x88;
+
+        let regs = gregset(&r, 0x5678, 0x12_0000);
+        assert_eq!(regs[0], 0);
+        assert_eq!(regs[1], 0x12_1000); // ra
+        assert_eq!(regs[3], 0x7fff_0000); // sp
+        assert_eq!(regs[4], 0x1234); // a0
+        assert_eq!(regs[31], 0x88); // s8
+        assert_eq!(regs[32], 0x5678); // orig_a0
+        assert_eq!(regs[33], 0x12_0000); // era
+        assert_eq!(regs[34..], [0; 11]);
+    }
+
+    #[test]
+    fn fpregset_layout() {
+        let mut f = [0; 32];
+        f[0] = 0x1122_3344_5566_7788;
+        f[31] = 0x1234;
+
+        let buf = fpregset(&f, &[1, 0, 0, 0, 0, 0, 0, 1], 0x1f);
+        assert_eq!(buf[..8], 0x1122_3344_5566_7788_u64.to_ne_bytes());
+        assert_eq!(buf[248..256], 0x1234_u64.to_ne_bytes());
+        assert_eq!(buf[256..264], [1, 0, 0, 0, 0, 0, 0, 1]); // fcc
+        assert_eq!(buf[264..268], 0x1f_u32.to_ne_bytes()); // fcsr
+        assert_eq!(buf[268..], [0; 4]);
+    }
+}
@@ -0,0 +1,63 @@
+//! Register notes of RISC-V.
+
+/// The number of registers in `elf_gregset_t`.
+pub const ELF_NGREG: usize = 32;
+
+/// The general-purpose registers in an `NT_PRSTATUS` note, laid out as
+/// `struct user_regs_struct`.
+pub type ElfGregset = [usize; ELF_NGREG];
+
+/// The size of the `NT_PRFPREG` note (`struct __riscv_d_ext_state`) in bytes.
+pub const ELF_FPREGSET_SIZE: usize = 264;
+
+/// Builds `elf_gregset_t` from `x0`-`x31` and `pc`, which replaces `x0`.
+pub fn gregset(x: &[usize; 32], pc: usize) -> ElfGregset {
+    let mut regs = *x;
+    regs[0] = pc;
+    regs
+}
+
+/// Builds the `NT_PRFPREG` note from `f0`-`f31` and `fcsr`, padded to 8 bytes.
+pub fn fpregset(f: &[u64; 32], fcsr: u32) -> [u8; ELF_FPREGSET_SIZE] {
+    let mut buf = [0; ELF_FPREGSET_SIZE];
+    for (chunk, reg) in buf[..256].chunks_exact_mut(8).zip(f) {
+        chunk.copy_from_slice(&reg.to_ne_bytes());
+    }
+    buf[256..260].copy_from_slice(&fcsr.to_ne_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gregset_layout() {
+        let mut x = [0; 32];
+        x[0] = 0xdead; // the kernel stack pointer
+        x[1] = 0x40_2000;
+        x[2] = 0x7fff_0000;
+        x[10] = 0x1234;
+        x[31] = 0x66;
+
+        let regs = gregset(&x, 0x40_1000);
+        assert_eq!(regs[0], 0x40_1000); // pc
+        assert_eq!(regs[1], 0x40_2000); // ra
+        assert_eq!(regs[2], 0x7fff_0000); // sp
+        assert_eq!(regs[10], 0x1234); // a0
+        assert_eq!(regs[31], 0x66); // t6
+    }
+
+    #[test]
+    fn fpregset_layout() {
+        let mut f = [0; 32];
+        f[0] = 0x1122_3344_5566_7788;
+        f[31] = 0x1234;
+
+        let buf = fpregset(&f, 0xe1);
+        assert_eq!(buf[..8], 0x1122_3344_5566_7788_u64.to_ne_bytes());
+        assert_eq!(buf[248..256], 0x1234_u64.to_ne_bytes());
+        assert_eq!(buf[256..260], 0xe1_u32.to_ne_bytes()); // fcsr
+        assert_eq!(buf[260..], [0; 4]);
+    }
+}
@@ -0,0 +1,171 @@
+//! Register notes of x86_64.
+
+/// The number of registers in `elf_gregset_t`.
+pub const ELF_NGREG: usize = 27;
+
+/// The general-purpose registers in an `NT_PRSTATUS` note, laid out as
+/// `struct user_regs_struct`.
+pub type ElfGregset = [u64; ELF_NGREG];
+
+/// The size of the `NT_PRFPREG` note (`struct user_i387_struct`) in bytes.
+pub const ELF_FPREGSET_SIZE: usize = 512;
+
+/// `struct user_regs_struct` in Linux, i.e., the registers of [`ElfGregset`]
+/// by name.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserRegs {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub orig_rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub eflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub ds: u64,
+    pub es: u64,
+    pub fs: u64,
+    pub gs: u64,
+}
+
+static_assertions::const_assert_eq!(size_of::<UserRegs>(), size_of::<ElfGregset>());
+
+impl UserRegs {
+    /// Converts the registers to `elf_gregset_t`.
+    pub fn to_gregset(&self) -> ElfGregset {
+        unsafe { core::mem::transmute(*self) }
+    }
+}
+
+/// `struct user_i387_struct` in Linux, i.e., the `NT_PRFPREG` note, which has
+/// the layout of the FXSAVE area.
+///
+/// The x87 registers `st_space` and the SSE registers `xmm_space` are split
+/// into 64-bit words, and the 96 reserved bytes at the end are zero.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserFpregs {
+    pub cwd: u16,
+    pub swd: u16,
+    pub ftw: u16,
+    pub fop: u16,
+    pub rip: u64,
+    pub rdp: u64,
+    pub mxcsr: u32,
+    pub mxcr_mask: u32,
+    pub st_space: [u64; 16],
+    pub xmm_space: [u64; 32],
+}
+
+impl UserFpregs {
+    /// Converts the registers to the `NT_PRFPREG` note.
+    pub fn to_bytes(&self) -> [u8; ELF_FPREGSET_SIZE] {
+        let mut buf = [0; ELF_FPREGSET_SIZE];
+        buf[0..2].copy_from_slice(&self.cwd.to_ne_bytes());
+        buf[2..4].copy_from_slice(&self.swd.to_ne_bytes());
+        buf[4..6].copy_from_slice(&self.ftw.to_ne_bytes());
+        buf[6..8].copy_from_slice(&self.fop.to_ne_bytes());
+        buf[8..16].copy_from_slice(&self.rip.to_ne_bytes());
+        buf[16..24].copy_from_slice(&self.rdp.to_ne_bytes());
+        buf[24..28].copy_from_slice(&self.mxcsr.to_ne_bytes());
+        buf[28..32].copy_from_slice(&self.mxcr_mask.to_ne_bytes());
+        let regs = self.st_space.iter().chain(&self.xmm_space);
+        for (chunk, reg) in buf[32..416].chunks_exact_mut(8).zip(regs) {
+            chunk.copy_from_slice(&reg.to_ne_bytes());
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::offset_of;
+
+    use super::*;
+
+    #[test]
+    fn gregset_layout() {
+        // The offsets in `struct user_regs_struct`.
+        assert_eq!(offset_of!(UserRegs, r15), 0);
+        assert_eq!(offset_of!(UserRegs, rax), 80);
+        assert_eq!(offset_of!(UserRegs, rdi), 112);
+        assert_eq!(offset_of!(UserRegs, orig_rax), 120);
+        assert_eq!(offset_of!(UserRegs, rip), 128);
+        assert_eq!(offset_of!(UserRegs, eflags), 144);
+        assert_eq!(offset_of!(UserRegs, rsp), 152);
+        assert_eq!(offset_of!(UserRegs, fs_base), 168);
+        assert_eq!(offset_of!(UserRegs, gs), 208);
+
+        let regs = UserRegs {
+            r15: 0xf,
+            rax: 0xa,
+            orig_rax: u64::MAX,
+            rip: 0x40_1000,
+            cs: 0x33,
+            eflags: 0x246,
+            rsp: 0x7fff_0000,
+            ss: 0x2b,
+            gs_base: 0x6000,
+            ..Default::default()
+        }
+        .to_gregset();
+        assert_eq!(regs[0], 0xf); // r15
+        assert_eq!(regs[10], 0xa); // rax
+        assert_eq!(regs[15], u64::MAX); // orig_rax
+        assert_eq!(regs[16..21], [0x40_1000, 0x33, 0x246, 0x7fff_0000, 0x2b]);
+        assert_eq!(regs[22], 0x6000); // gs_base
+        assert_eq!(regs[23..], [0; 4]);
+    }
+
+    #[test]
+    fn fpregset_layout() {
+        let mut fpregs = UserFpregs {
+            cwd: 0x37f,
+            swd: 0x1234,
+            ftw: 0xff,
+            fop: 0x5678,
+            rip: 0x1122_3344_5566_7788,
+            rdp: 0x99aa_bbcc_ddee_ff00,
+            mxcsr: 0x1f80,
+            mxcr_mask: 0xffff,
+            ..Default::default()
+        };
+        fpregs.st_space[0] = 0xaaaa;
+        fpregs.st_space[15] = 0xbbbb;
+        fpregs.xmm_space[0] = 0xcccc;
+        fpregs.xmm_space[31] = 0xdddd;
+
+        let buf = fpregs.to_bytes();
+        assert_eq!(buf[0..2], 0x37f_u16.to_ne_bytes()); // cwd
+        assert_eq!(buf[2..4], 0x1234_u16.to_ne_bytes()); // swd
+        assert_eq!(buf[4..6], 0xff_u16.to_ne_bytes()); // ftw
+        assert_eq!(buf[6..8], 0x5678_u16.to_ne_bytes()); // fop
+        assert_eq!(buf[8..16], 0x1122_3344_5566_7788_u64.to_ne_bytes()); // rip
+        assert_eq!(buf[16..24], 0x99aa_bbcc_ddee_ff00_u64.to_ne_bytes()); // rdp
+        assert_eq!(buf[24..28], 0x1f80_u32.to_ne_bytes()); // mxcsr
+        assert_eq!(buf[28..32], 0xffff_u32.to_ne_bytes()); // mxcr_mask
+        assert_eq!(buf[32..40], 0xaaaa_u64.to_ne_bytes()); // st_space
+        assert_eq!(buf[152..160], 0xbbbb_u64.to_ne_bytes());
+        assert_eq!(buf[160..168], 0xcccc_u64.to_ne_bytes()); // xmm_space
+        assert_eq!(buf[408..416], 0xdddd_u64.to_ne_bytes());
+        assert_eq!(buf[416..], [0; 96]); // padding
+    }
+}
@@ -0,0 +1,91 @@
+//! Hardware breakpoints and watchpoints.
+//!
+//! This module programs the debug registers of the current CPU to trap on the
+//! execution of an instruction (breakpoints) or on an access to data
+//! (watchpoints) in user space:
+//!
+//! - x86_64: `DR0`–`DR3` and `DR7`. Breakpoints and watchpoints share the
+//!   four slots, so breakpoint `i` and watchpoint `i` are the same slot.
+//! - AArch64: `DBGBVR<n>_EL1`/`DBGBCR<n>_EL1` and
+//!   `DBGWVR<n>_EL1`/`DBGWCR<n>_EL1`.
+//! - RISC-V: the Sdtrig triggers, selected by `tselect` and configured by
+//!   `tdata1`/`tdata2`. They are also shared by breakpoints and watchpoints.
+//!   The M-mode firmware must allow the supervisor to access these CSRs.
+//! - LoongArch64: the fetch (`IB<n>*`) and memory (`DB<n>*`) watchpoint CSRs.
+//!   The fetch watchpoint 0 is reserved for single-stepping.
+//!
+//! The settings are per-task: [`DebugState`] is saved and restored by
+//! [`TaskContext::switch_to`](crate::TaskContext::switch_to). A hit in user
+//! space is reported as [`ReturnReason::Watchpoint`] by
+//! [`UserContext::run`](crate::uspace::UserContext::run).
+//!
+//! The instruction or access that hits is not completed when the exception is
+//! taken (except for data watchpoints on x86_64), so the breakpoint should be
+//! cleared, or stepped over with it disabled, before resuming.
+//!
+//! [`ReturnReason::Watchpoint`]: crate::uspace::ReturnReason::Watchpoint
+
+use core::fmt;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        pub use crate::x86_64::debug::*;
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        pub use crate::riscv::debug::*;
+    } else if #[cfg(target_arch = "aarch64")] {
+        pub use crate::aarch64::debug::*;
+    } else if #[cfg(target_arch = "loongarch64")] {
+        pub use crate::loongarch64::debug::*;
+    }
+}
+
+/// The accesses that trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Data reads.
+    Read,
+    /// Data writes.
+    Write,
+    /// Both data reads and writes.
+    ReadWrite,
+}
+
+/// Errors returned when programming a breakpoint or watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugError {
+    /// The slot index is out of range.
+    InvalidIndex(usize),
+    /// The watched length is not supported, or the address is not aligned to
+    /// it.
+    InvalidRange {
+        /// The watched address.
+        addr: usize,
+        /// The watched length in bytes.
+        len: usize,
+    },
+    /// The kind of watchpoint is not supported by the hardware.
+    Unsupported(WatchKind),
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidIndex(idx) => write!(f, "invalid breakpoint index {idx}"),
+            Self::InvalidRange { addr, len } => {
+                write!(f, "invalid watchpoint range {addr:#x} (len={len})")
+            }
+            Self::Unsupported(kind) => write!(f, "unsupported watchpoint kind {kind:?}"),
+        }
+    }
+}
+
+impl core::error::Error for DebugError {}
+
+/// Checks that `len` is 1, 2, 4 or 8, and `addr` is aligned to it.
+pub(crate) fn check_range(addr: usize, len: usize) -> Result<(), DebugError> {
+    if matches!(len, 1 | 2 | 4 | 8) && addr.is_multiple_of(len) {
+        Ok(())
+    } else {
+        Err(DebugError::InvalidRange { addr, len })
+    }
+}
@@ -17,6 +17,14 @@ pub mod trap;
 #[cfg(feature = "uspace")]
 mod uspace_common;
 
+#[cfg(feature = "hw-breakpoint")]
+pub mod debug;
+
+#[cfg(feature = "uspace")]
+pub mod coredump;
+
+pub mod loongarch64_decode;
+
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
         mod x86_64;
@@ -179,6 +179,20 @@ pub fn enable_fp() {
     loongArch64::register::euen::set_fpe(true);
 }
 
+/// Disables floating-point instructions by clearing `EUEN.FPE`.
+///
+/// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
+#[inline]
+pub fn disable_fp() {
+    loongArch64::register::euen::set_fpe(false);
+}
+
+/// Returns whether floating-point instructions are enabled (`EUEN.FPE`).
+#[inline]
+pub fn fp_enabled() -> bool {
+    loongArch64::register::euen::read().fpe()
+}
+
 /// Enables LSX extension by setting `EUEN.LSX`.
 ///
 /// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
@@ -186,6 +200,44 @@ pub fn enable_lsx() {
     loongArch64::register::euen::set_sxe(true);
 }
 
+/// Disables LSX extension by clearing `EUEN.SXE`.
+///
+/// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
+#[inline]
+pub fn disable_lsx() {
+    loongArch64::register::euen::set_sxe(false);
+}
+
+/// Returns whether LSX extension is enabled (`EUEN.SXE`).
+#[inline]
+pub fn lsx_enabled() -> bool {
+    loongArch64::register::euen::read().sxe()
+}
+
+/// Enables LASX extension by setting `EUEN.ASXE`.
+///
+/// LASX instructions also require LSX extension to be enabled.
+///
+/// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
+#[inline]
+pub fn enable_lasx() {
+    loongArch64::register::euen::set_asxe(true);
+}
+
+/// Disables LASX extension by clearing `EUEN.ASXE`.
+///
+/// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
+#[inline]
+pub fn disable_lasx() {
+    loongArch64::register::euen::set_asxe(false);
+}
+
+/// Returns whether LASX extension is enabled (`EUEN.ASXE`).
+#[inline]
+pub fn lasx_enabled() -> bool {
+    loongArch64::register::euen::read().asxe()
+}
+
 #[cfg(feature = "uspace")]
 core::arch::global_asm!(include_asm_macros!(), include_str!("user_copy.S"));
 
@@ -1,8 +1,16 @@
 use core::arch::naked_asm;
 #[cfg(feature = "fp-simd")]
 use core::mem::offset_of;
+#[cfg(feature = "vector")]
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use memory_addr::VirtAddr;
 
+#[cfg(feature = "vector")]
+use super::trap::{ECODE_ASXD, ECODE_SXD};
+
 /// General registers of Loongarch64.
 #[allow(missing_docs)]
 #[repr(C)]
@@ -69,8 +77,169 @@ impl FpuState {
     }
 }
 
+/// The FPU states of the task running on the current CPU, which are restored
+/// on its first floating-point instruction after being switched in.
+#[cfg(feature = "lazy-fp")]
+#[percpu::def_percpu]
+static CURRENT_FPU_STATE: usize = 0;
+
+/// Handles the floating-point instruction disable exception (FPD) raised while
+/// `EUEN.FPE` is cleared by lazy switching, by enabling the FPU and restoring
+/// the states of the current task.
+///
+/// Returns `false` if the FPU is already enabled, i.e., the trap is not caused
+/// by lazy switching.
+#[cfg(feature = "lazy-fp")]
+pub(crate) fn handle_lazy_fp_trap() -> bool {
+    if crate::asm::fp_enabled() {
+        return false;
+    }
+    crate::asm::enable_fp();
+    let state = CURRENT_FPU_STATE.read_current() as *const FpuState;
+    if let Some(state) = unsafe { state.as_ref() } {
+        state.restore();
+    }
+    true
+}
+
+/// The width in bytes of LSX (128-bit) vector registers.
+#[cfg(feature = "vector")]
+const LSX_WIDTH: usize = 16;
+/// The width in bytes of LASX (256-bit) vector registers.
+#[cfg(feature = "vector")]
+const LASX_WIDTH: usize = 32;
+
+/// The width in bytes of the widest vector registers supported by the CPU, or
+/// 0 if neither LSX nor LASX is supported.
+#[cfg(feature = "vector")]
+static MAX_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// LSX/LASX vector registers of LoongArch64.
+///
+/// The registers are only saved and restored if the task has used LSX or LASX,
+/// as indicated by non-zero [`width`](Self::width). Otherwise, `EUEN.SXE` and
+/// `EUEN.ASXE` are cleared, so that the first vector instruction of the task
+/// traps and enables them. `FCSR` and the condition flags are saved in
+/// [`FpuState`].
+#[cfg(feature = "vector")]
+#[repr(C, align(32))]
+#[derive(Default, Clone, Copy)]
+pub struct VectorState {
+    /// Vector registers (xr0-xr31), whose low 128 bits are vr0-vr31 and whose
+    /// low 64 bits are f0-f31.
+    pub xr: [[u8; 32]; 32],
+    /// The width in bytes of the registers in use: 32 for LASX, 16 for LSX, or
+    /// 0 if the task has used neither.
+    pub width: usize,
+}
+
+#[cfg(feature = "vector")]
+impl VectorState {
+    /// Returns the width in bytes of the widest vector registers supported by
+    /// the CPU: 32 with LASX, 16 with LSX only, or 0 if neither is supported.
+    pub fn max_width() -> usize {
+        MAX_WIDTH.load(Ordering::Relaxed)
+    }
+
+    /// Detects LSX and LASX by `CPUCFG`, and disables them until the first
+    /// vector instruction of a task.
+    pub(crate) fn init() {
+        let cfg = loongArch64::cpu::CPUCFG::read(2);
+        let width = if cfg.get_bit(7) {
+            LASX_WIDTH
+        } else if cfg.get_bit(6) {
+            LSX_WIDTH
+        } else {
+            0
+        };
+        MAX_WIDTH.store(width, Ordering::Relaxed);
+        crate::asm::disable_lasx();
+        crate::asm::disable_lsx();
+    }
+
+    /// Saves the vector registers in use from CPU to this structure, and
+    /// disables LSX and LASX.
+    pub(super) fn save(&mut self) {
+        if crate::asm::lasx_enabled() {
+            unsafe { save_lasx_registers(self) };
+            self.width = LASX_WIDTH;
+            crate::asm::disable_lasx();
+            crate::asm::disable_lsx();
+        } else if crate::asm::lsx_enabled() {
+            unsafe { save_lsx_registers(self) };
+            self.width = LSX_WIDTH;
+            crate::asm::disable_lsx();
+        }
+    }
+
+    /// Enables LSX and LASX as used by the task, and restores the vector
+    /// registers in use from this structure to CPU.
+    pub(super) fn restore(&self) {
+        match self.width {
+            LASX_WIDTH => {
+                crate::asm::enable_lsx();
+                crate::asm::enable_lasx();
+                unsafe { restore_lasx_registers(self) };
+            }
+            LSX_WIDTH => {
+                crate::asm::enable_lsx();
+                unsafe { restore_lsx_registers(self) };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(feature = "vector")]
+impl fmt::Debug for VectorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VectorState")
+            .field("width", &self.width)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Handles the LSX or LASX instruction disable exception (SXD or ASXD) raised
+/// by the first vector instruction of the current task, by enabling the
+/// extension with the bits above the FP registers (or the LSX registers)
+/// cleared.
+///
+/// Returns `false` if the trap is not caused by the first use, e.g., the
+/// extension is already enabled or not supported.
+#[cfg(feature = "vector")]
+pub(crate) fn handle_vector_trap(estat: usize) -> bool {
+    let lasx = match (estat >> 16) & 0x3f {
+        ECODE_SXD if VectorState::max_width() >= LSX_WIDTH && !crate::asm::lsx_enabled() => false,
+        ECODE_ASXD if VectorState::max_width() >= LASX_WIDTH && !crate::asm::lasx_enabled() => true,
+        _ => return false,
+    };
+    // The low 64 bits of the vector registers are the FP registers.
+    #[cfg(feature = "lazy-fp")]
+    handle_lazy_fp_trap();
+    if !crate::asm::lsx_enabled() {
+        crate::asm::enable_lsx();
+        unsafe { clear_lsx_upper() };
+    }
+    if lasx {
+        crate::asm::enable_lasx();
+        unsafe { clear_lasx_upper() };
+    }
+    true
+}
+
+/// Raw information about the cause of a trap.
+#[derive(Debug, Clone, Copy)]
+pub struct RawTrapCause {
+    /// Exception Status register.
+    pub estat: usize,
+    /// Bad Virtual Address register.
+    pub badv: usize,
+    /// Bad Instruction register.
+    pub badi: u32,
+}
+
 /// Saved registers when a trap (interrupt or exception) occurs.
-#[repr(C)]
+#[repr(C, align(16))]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct TrapFrame {
     /// All general registers.
@@ -79,6 +248,9 @@ pub struct TrapFrame {
     pub prmd: usize,
     /// Exception Return Address
     pub era: usize,
+    /// The original `a0` of a syscall, which is overwritten by the return
+    /// value.
+    pub orig_arg0: usize,
 }
 
 impl TrapFrame {
@@ -182,6 +354,16 @@ impl TrapFrame {
         self.regs.a0 = a0;
     }
 
+    /// Rewinds the syscall that has just returned, so that it is executed
+    /// again when returning to user space.
+    ///
+    /// It moves the PC back to the `syscall` instruction and restores `a0` from
+    /// [`orig_arg0`](Self::orig_arg0).
+    pub const fn rewind_syscall(&mut self) {
+        self.era -= 4;
+        self.regs.a0 = self.orig_arg0;
+    }
+
     /// Sets the return address.
     pub const fn set_ra(&mut self, ra: usize) {
         self.regs.ra = ra;
@@ -232,6 +414,12 @@ pub struct TaskContext {
     #[cfg(feature = "fp-simd")]
     /// Floating Point Unit states
     pub fpu: FpuState,
+    #[cfg(feature = "vector")]
+    /// LSX/LASX vector registers
+    pub vector: VectorState,
+    #[cfg(feature = "hw-breakpoint")]
+    /// Hardware breakpoints and watchpoints
+    pub debug_state: crate::debug::DebugState,
 }
 
 impl TaskContext {
@@ -261,6 +449,16 @@ impl TaskContext {
     ///
     /// It first saves the current task's context from CPU to this place, and then
     /// restores the next task's context from `next_ctx` to CPU.
+    ///
+    /// With the `lazy-fp` feature, the FPU states are only saved if the current
+    /// task has used the FPU since it was switched in, and are restored on the
+    /// first floating-point instruction of the next task, so `next_ctx` must
+    /// not be moved while the task is running. It requires the [`percpu`]
+    /// crate to be initialized.
+    ///
+    /// With the `vector` feature, the LSX/LASX registers are only saved and
+    /// restored for the tasks that have used them, and the FPU states of such
+    /// tasks are restored eagerly.
     pub fn switch_to(&mut self, next_ctx: &Self) {
         #[cfg(feature = "tls")]
         {
@@ -274,11 +472,37 @@ impl TaskContext {
                 crate::asm::flush_tlb(None); // currently flush the entire TLB
             }
         }
-        #[cfg(feature = "fp-simd")]
+        // The vector registers are saved before the FPU is disabled.
+        #[cfg(feature = "vector")]
+        self.vector.save();
+        #[cfg(all(feature = "fp-simd", not(feature = "lazy-fp")))]
         {
             self.fpu.save();
             next_ctx.fpu.restore();
         }
+        #[cfg(feature = "lazy-fp")]
+        {
+            if crate::asm::fp_enabled() {
+                self.fpu.save();
+                crate::asm::disable_fp();
+            }
+            CURRENT_FPU_STATE.write_current(&next_ctx.fpu as *const _ as usize);
+        }
+        #[cfg(feature = "vector")]
+        if next_ctx.vector.width != 0 {
+            // The FP registers are part of the vector registers.
+            #[cfg(feature = "lazy-fp")]
+            {
+                crate::asm::enable_fp();
+                next_ctx.fpu.restore();
+            }
+            next_ctx.vector.restore();
+        }
+        #[cfg(feature = "hw-breakpoint")]
+        {
+            self.debug_state.save();
+            next_ctx.debug_state.restore();
+        }
         unsafe { context_switch(self, next_ctx) }
     }
 }
@@ -317,6 +541,81 @@ unsafe extern "C" fn restore_fp_registers(fpu: &FpuState) {
     )
 }
 
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn save_lsx_registers(_vector: &mut VectorState) {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        vst     $vr\i, $a0, \i * 32
+        .endr
+        ret"
+    )
+}
+
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn restore_lsx_registers(_vector: &VectorState) {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        vld     $vr\i, $a0, \i * 32
+        .endr
+        ret"
+    )
+}
+
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn save_lasx_registers(_vector: &mut VectorState) {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        xvst    $xr\i, $a0, \i * 32
+        .endr
+        ret"
+    )
+}
+
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn restore_lasx_registers(_vector: &VectorState) {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        xvld    $xr\i, $a0, \i * 32
+        .endr
+        ret"
+    )
+}
+
+/// Clears bits 127:64 of vr0-vr31, i.e., the bits above the FP registers.
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn clear_lsx_upper() {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        vinsgr2vr.d $vr\i, $zero, 1
+        .endr
+        ret"
+    )
+}
+
+/// Clears bits 255:128 of xr0-xr31, i.e., the bits above the LSX registers.
+#[cfg(feature = "vector")]
+#[unsafe(naked)]
+unsafe extern "C" fn clear_lasx_upper() {
+    naked_asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        xvinsgr2vr.d $xr\i, $zero, 2
+        xvinsgr2vr.d $xr\i, $zero, 3
+        .endr
+        ret"
+    )
+}
+
 #[unsafe(naked)]
 unsafe extern "C" fn context_switch(_current_task: &mut TaskContext, _next_task: &TaskContext) {
     naked_asm!(
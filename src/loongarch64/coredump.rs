@@ -0,0 +1,28 @@
+//! Register notes of ELF core dumps.
+
+pub use crate::coredump::loongarch64::{ElfGregset, ELF_FPREGSET_SIZE, ELF_NGREG};
+
+use super::{uspace::UserContext, FpuState};
+use crate::coredump::loongarch64 as layout;
+
+impl UserContext {
+    /// Converts the registers to `elf_gregset_t`: `r0`-`r31`, `orig_a0`,
+    /// `era`, `badv` and 10 reserved registers.
+    ///
+    /// `r0` holds the kernel stack pointer while in user space and is set to 0.
+    /// `badv` is not recorded in the context and is set to 0.
+    pub fn to_elf_gregset(&self) -> ElfGregset {
+        // `GeneralRegisters` has the same layout as `[usize; 32]`.
+        let regs: [u64; 32] = unsafe { core::mem::transmute(self.regs) };
+        layout::gregset(&regs, self.orig_arg0 as u64, self.era as u64)
+    }
+}
+
+impl FpuState {
+    /// Converts the FPU registers to the `NT_PRFPREG` note: `f0`-`f31`, the
+    /// condition flags `fcc0`-`fcc7` (one byte each) and `fcsr`, padded to 8
+    /// bytes.
+    pub fn to_elf_fpregset(&self) -> [u8; ELF_FPREGSET_SIZE] {
+        layout::fpregset(&self.fp, &self.fcc, self.fcsr)
+    }
+}
@@ -0,0 +1,224 @@
+//! Instruction fetch and memory access watchpoints.
+//!
+//! The registers of the fetch watchpoint `n` are `0x390 + 8n` to `0x393 + 8n`
+//! (`IB<n>ADDR`, `IB<n>MASK`, `IB<n>CTRL` and `IB<n>ASID`), and those of the
+//! memory watchpoint `n` are `0x310 + 8n` to `0x313 + 8n`. The fetch
+//! watchpoint 0 is used for single-stepping, so breakpoint `i` is the fetch
+//! watchpoint `i + 1`.
+
+use core::arch::asm;
+
+use crate::debug::{check_range, DebugError, WatchKind};
+
+/// The maximum number of watchpoints of each type.
+const MAX_WATCHPOINTS: usize = 8;
+
+const CSR_MWPC: usize = 0x300;
+const CSR_MWPS: usize = 0x301;
+const CSR_DB0ADDR: usize = 0x310;
+const CSR_FWPC: usize = 0x380;
+const CSR_FWPS: usize = 0x381;
+const CSR_IB0ADDR: usize = 0x390;
+
+/// Offsets of the registers of a watchpoint from its address register.
+const ADDR: usize = 0;
+const MASK: usize = 1;
+const CTRL: usize = 2;
+const ASID: usize = 3;
+
+/// Enables the watchpoint in PLV3.
+const CTRL_PLV3_ENABLE: usize = 1 << 4;
+const CTRL_LOAD: usize = 1 << 8;
+const CTRL_STORE: usize = 1 << 9;
+const CTRL_LEN_SHIFT: usize = 10;
+
+/// The mask of the number of watchpoints in `FWPC` and `MWPC`.
+const WPC_NUM_MASK: usize = 0x3f;
+
+fn read_csr<const CSR: usize>() -> usize {
+    let val;
+    unsafe { asm!("csrrd {}, {}", out(reg) val, const CSR) };
+    val
+}
+
+fn write_csr<const CSR: usize>(val: usize) {
+    unsafe { asm!("csrwr {}, {}", inout(reg) val => _, const CSR) };
+}
+
+/// Calls `$f` on the register `$reg` of the watchpoint `$idx` whose registers
+/// start at `$base`.
+macro_rules! wp_csr {
+    ($f:ident, $base:ident, $idx:expr, $reg:ident $(, $val:expr)?) => {
+        match $idx {
+            0 => $f::<{ $base + $reg }>($($val)?),
+            1 => $f::<{ $base + 8 + $reg }>($($val)?),
+            2 => $f::<{ $base + 16 + $reg }>($($val)?),
+            3 => $f::<{ $base + 24 + $reg }>($($val)?),
+            4 => $f::<{ $base + 32 + $reg }>($($val)?),
+            5 => $f::<{ $base + 40 + $reg }>($($val)?),
+            6 => $f::<{ $base + 48 + $reg }>($($val)?),
+            _ => $f::<{ $base + 56 + $reg }>($($val)?),
+        }
+    };
+}
+
+/// Registers of a single watchpoint.
+#[derive(Debug, Default, Clone, Copy)]
+struct WatchRegs {
+    addr: usize,
+    mask: usize,
+    ctrl: usize,
+}
+
+impl WatchRegs {
+    fn read_fetch(idx: usize) -> Self {
+        Self {
+            addr: wp_csr!(read_csr, CSR_IB0ADDR, idx, ADDR),
+            mask: wp_csr!(read_csr, CSR_IB0ADDR, idx, MASK),
+            ctrl: wp_csr!(read_csr, CSR_IB0ADDR, idx, CTRL),
+        }
+    }
+
+    fn write_fetch(&self, idx: usize) {
+        wp_csr!(write_csr, CSR_IB0ADDR, idx, CTRL, 0);
+        wp_csr!(write_csr, CSR_IB0ADDR, idx, ADDR, self.addr);
+        wp_csr!(write_csr, CSR_IB0ADDR, idx, MASK, self.mask);
+        wp_csr!(write_csr, CSR_IB0ADDR, idx, ASID, 0);
+        wp_csr!(write_csr, CSR_IB0ADDR, idx, CTRL, self.ctrl);
+    }
+
+    fn read_memory(idx: usize) -> Self {
+        Self {
+            addr: wp_csr!(read_csr, CSR_DB0ADDR, idx, ADDR),
+            mask: wp_csr!(read_csr, CSR_DB0ADDR, idx, MASK),
+            ctrl: wp_csr!(read_csr, CSR_DB0ADDR, idx, CTRL),
+        }
+    }
+
+    fn write_memory(&self, idx: usize) {
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, CTRL, 0);
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, ADDR, self.addr);
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, MASK, self.mask);
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, ASID, 0);
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, CTRL, self.ctrl);
+    }
+}
+
+/// Returns the number of hardware breakpoints.
+pub fn num_breakpoints() -> usize {
+    let num = read_csr::<CSR_FWPC>() & WPC_NUM_MASK;
+    num.min(MAX_WATCHPOINTS).saturating_sub(1)
+}
+
+/// Returns the number of hardware watchpoints.
+pub fn num_watchpoints() -> usize {
+    let num = read_csr::<CSR_MWPC>() & WPC_NUM_MASK;
+    num.min(MAX_WATCHPOINTS)
+}
+
+/// Sets the hardware breakpoint `idx` on the instruction at `addr`.
+pub fn set_breakpoint(idx: usize, addr: usize) -> Result<(), DebugError> {
+    if idx >= num_breakpoints() {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    let regs = WatchRegs {
+        addr,
+        mask: 0,
+        ctrl: CTRL_PLV3_ENABLE,
+    };
+    regs.write_fetch(idx + 1);
+    Ok(())
+}
+
+/// Clears the hardware breakpoint `idx`.
+pub fn clear_breakpoint(idx: usize) {
+    if idx < num_breakpoints() {
+        wp_csr!(write_csr, CSR_IB0ADDR, idx + 1, CTRL, 0);
+    }
+}
+
+/// Sets the hardware watchpoint `idx` on `len` bytes at `addr`.
+pub fn set_watchpoint(
+    idx: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), DebugError> {
+    check_range(addr, len)?;
+    if idx >= num_watchpoints() {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    let access = match kind {
+        WatchKind::Read => CTRL_LOAD,
+        WatchKind::Write => CTRL_STORE,
+        WatchKind::ReadWrite => CTRL_LOAD | CTRL_STORE,
+    };
+    let len = match len {
+        1 => 0b11,
+        2 => 0b10,
+        4 => 0b01,
+        _ => 0b00,
+    };
+    let regs = WatchRegs {
+        addr,
+        mask: 0,
+        ctrl: CTRL_PLV3_ENABLE | access | len << CTRL_LEN_SHIFT,
+    };
+    regs.write_memory(idx);
+    Ok(())
+}
+
+/// Clears the hardware watchpoint `idx`.
+pub fn clear_watchpoint(idx: usize) {
+    if idx < num_watchpoints() {
+        wp_csr!(write_csr, CSR_DB0ADDR, idx, CTRL, 0);
+    }
+}
+
+/// Takes the hit breakpoint or watchpoint recorded in `FWPS` and `MWPS`, and
+/// returns the address of the instruction (`era`) or the data (`badv`).
+///
+/// The status of the fetch watchpoint 0 (single-stepping) is ignored.
+pub(crate) fn take_hit(era: usize, badv: usize) -> Option<usize> {
+    let status_mask = (1 << MAX_WATCHPOINTS) - 1;
+    let fetch = read_csr::<CSR_FWPS>() & status_mask & !1;
+    if fetch != 0 {
+        write_csr::<CSR_FWPS>(fetch);
+        return Some(era);
+    }
+    let memory = read_csr::<CSR_MWPS>() & status_mask;
+    if memory != 0 {
+        write_csr::<CSR_MWPS>(memory);
+        return Some(badv);
+    }
+    None
+}
+
+/// Per-task states of the watchpoints.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugState {
+    fetch: [WatchRegs; MAX_WATCHPOINTS],
+    memory: [WatchRegs; MAX_WATCHPOINTS],
+}
+
+impl DebugState {
+    /// Saves the watchpoints of the current CPU to this structure.
+    pub fn save(&mut self) {
+        for i in 0..num_breakpoints() {
+            self.fetch[i] = WatchRegs::read_fetch(i + 1);
+        }
+        for i in 0..num_watchpoints() {
+            self.memory[i] = WatchRegs::read_memory(i);
+        }
+    }
+
+    /// Restores the watchpoints of the current CPU from this structure.
+    pub fn restore(&self) {
+        for i in 0..num_breakpoints() {
+            self.fetch[i].write_fetch(i + 1);
+        }
+        for i in 0..num_watchpoints() {
+            self.memory[i].write_memory(i);
+        }
+    }
+}
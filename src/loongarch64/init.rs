@@ -39,6 +39,8 @@ pub fn init_mmu(root_paddr: PhysAddr, phys_virt_offset: usize) {
 /// Initializes trap handling on the current CPU.
 ///
 /// In detail, it initializes the exception vector on LoongArch64 platforms.
+/// With the `vector` feature, it also detects LSX and LASX, which are enabled
+/// on the first vector instruction of each task.
 pub fn init_trap() {
     #[cfg(feature = "uspace")]
     crate::uspace_common::init_exception_table();
@@ -49,4 +51,6 @@ pub fn init_trap() {
         core::arch::asm!(include_asm_macros!(), "csrwr $r0, KSAVE_KSP");
         crate::asm::write_exception_entry_base(exception_entry_base as usize);
     }
+    #[cfg(feature = "vector")]
+    super::context::VectorState::init();
 }
@@ -5,6 +5,7 @@ macro_rules! include_asm_macros {
         .equ REGS_MACROS_FLAG, 1
 
         // CSR list
+        .equ LA_CSR_CRMD,          0x0
         .equ LA_CSR_PRMD,          0x1
         .equ LA_CSR_EUEN,          0x2
         .equ LA_CSR_ERA,           0x6
@@ -22,6 +23,11 @@ macro_rules! include_asm_macros {
         .equ LA_CSR_TLBREHI,       0x8e    // TLB refill entryhi
         .equ LA_CSR_DMW0,          0x180
         .equ LA_CSR_DMW1,          0x181
+        .equ LA_CSR_FWPS,          0x381   // Fetch watchpoint status
+        .equ LA_CSR_IB0ADDR,       0x390   // Fetch watchpoint 0 address
+        .equ LA_CSR_IB0MASK,       0x391   // Fetch watchpoint 0 address mask
+        .equ LA_CSR_IB0CTRL,       0x392   // Fetch watchpoint 0 control
+        .equ LA_CSR_IB0ASID,       0x393   // Fetch watchpoint 0 ASID
 
         .equ KSAVE_KSP,            0x30
 
@@ -5,11 +5,21 @@ mod context;
 mod trap;
 mod unaligned;
 
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) mod debug;
+
 pub mod asm;
 pub mod init;
 
+#[cfg(feature = "uspace")]
+mod coredump;
+#[cfg(feature = "uspace")]
+mod signal;
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
-pub use self::context::{FpuState, GeneralRegisters, TaskContext, TrapFrame};
+pub use self::context::{FpuState, GeneralRegisters, RawTrapCause, TaskContext, TrapFrame};
 pub use self::unaligned::UnalignedError;
+
+#[cfg(feature = "vector")]
+pub use self::context::VectorState;
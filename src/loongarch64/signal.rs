@@ -0,0 +1,215 @@
+//! Signal frames compatible with the Linux ABI.
+
+use core::mem::{offset_of, size_of};
+
+use super::uspace::UserContext;
+#[cfg(feature = "fp-simd")]
+use super::FpuState;
+use crate::{
+    uspace_common::{
+        read_user, write_user, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+        SIGINFO_SIZE,
+    },
+    GeneralRegisters,
+};
+
+#[cfg(feature = "fp-simd")]
+const FPU_CTX_MAGIC: u32 = 0x4650_5501;
+
+/// `sc_flags` bit indicating that the FPU has been used.
+#[cfg(feature = "fp-simd")]
+const SC_USED_FP: u32 = 1 << 0;
+
+/// `struct sctx_info` in Linux, the header of an extended context record.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SctxInfo {
+    magic: u32,
+    size: u32,
+    padding: u64,
+}
+
+/// `struct fpu_context` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpuContext {
+    regs: [u64; 32],
+    fcc: u64,
+    fcsr: u32,
+}
+
+/// Extended context records following `struct sigcontext`: a FPU record and
+/// a terminator record.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct ExtContext {
+    fpu_info: SctxInfo,
+    fpu: FpuContext,
+    end: SctxInfo,
+}
+
+/// `struct sigcontext` in Linux.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    pc: u64,
+    regs: [u64; 32],
+    flags: u32,
+}
+
+/// `struct ucontext` in Linux, followed by the extended context records.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    uc_flags: u64,
+    uc_link: u64,
+    uc_stack: SignalStack,
+    uc_sigmask: u64,
+    unused: [u8; 128 - size_of::<u64>()],
+    uc_mcontext: SigContext,
+    extcontext: ExtContext,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+struct RtSigFrame {
+    info: [u8; SIGINFO_SIZE],
+    uc: UContext,
+}
+
+static_assertions::const_assert_eq!(size_of::<SigContext>(), 272);
+static_assertions::const_assert_eq!(size_of::<FpuContext>(), 272);
+static_assertions::const_assert_eq!(offset_of!(UContext, uc_mcontext), 176);
+static_assertions::const_assert_eq!(offset_of!(UContext, extcontext), 448);
+static_assertions::const_assert_eq!(size_of::<GeneralRegisters>(), size_of::<[u64; 32]>());
+
+impl ExtContext {
+    /// Saves the current FPU states into the records, and returns them along
+    /// with the `sc_flags`.
+    #[cfg(feature = "fp-simd")]
+    fn save() -> (Self, u32) {
+        let mut fpu_state = FpuState::default();
+        fpu_state.save();
+        let ctx = Self {
+            fpu_info: SctxInfo {
+                magic: FPU_CTX_MAGIC,
+                size: (size_of::<SctxInfo>() + size_of::<FpuContext>()) as _,
+                padding: 0,
+            },
+            fpu: FpuContext {
+                regs: fpu_state.fp,
+                fcc: u64::from_le_bytes(fpu_state.fcc),
+                fcsr: fpu_state.fcsr,
+            },
+            end: SctxInfo {
+                magic: 0,
+                size: 0,
+                padding: 0,
+            },
+        };
+        (ctx, SC_USED_FP)
+    }
+
+    /// Returns empty records. The zeroed FPU record also terminates the list.
+    #[cfg(not(feature = "fp-simd"))]
+    fn save() -> (Self, u32) {
+        let empty = SctxInfo {
+            magic: 0,
+            size: 0,
+            padding: 0,
+        };
+        let ctx = Self {
+            fpu_info: empty,
+            fpu: FpuContext {
+                regs: [0; 32],
+                fcc: 0,
+                fcsr: 0,
+            },
+            end: empty,
+        };
+        (ctx, 0)
+    }
+}
+
+impl UserContext {
+    /// Pushes a signal frame onto the user stack, and redirects the execution
+    /// to the signal handler.
+    ///
+    /// The frame has the same layout as `struct rt_sigframe` in Linux. The
+    /// FPU record is saved only with the `fp-simd` feature. The handler is
+    /// called with the signal number, the address of `siginfo_t` and the
+    /// address of `ucontext_t`, and returns to [`SignalFrameParams::restorer`].
+    pub fn push_signal_frame(
+        &mut self,
+        params: &SignalFrameParams<'_>,
+    ) -> Result<(), UserAccessError> {
+        let sp = params.stack_top.unwrap_or(self.regs.sp);
+        let frame_addr = sp.wrapping_sub(size_of::<RtSigFrame>()) & !15;
+
+        // `GeneralRegisters` has the same layout as `sc_regs`. `zero` is used
+        // to hold the kernel stack pointer, so it should not be exposed.
+        let mut regs: [u64; 32] = unsafe { core::mem::transmute(self.regs) };
+        regs[0] = 0;
+        let (extcontext, flags) = ExtContext::save();
+        let frame = RtSigFrame {
+            info: *params.siginfo,
+            uc: UContext {
+                uc_flags: 0,
+                uc_link: 0,
+                uc_stack: params.uc_stack,
+                uc_sigmask: params.sigmask,
+                unused: [0; _],
+                uc_mcontext: SigContext {
+                    pc: self.era as _,
+                    regs,
+                    flags,
+                },
+                extcontext,
+            },
+        };
+        write_user(frame_addr, &frame)?;
+
+        self.regs.a0 = params.signo;
+        self.regs.a1 = frame_addr + offset_of!(RtSigFrame, info);
+        self.regs.a2 = frame_addr + offset_of!(RtSigFrame, uc);
+        self.regs.ra = params.restorer;
+        self.regs.sp = frame_addr;
+        self.era = params.handler;
+        Ok(())
+    }
+
+    /// Restores the context saved by [`push_signal_frame`], on the
+    /// `rt_sigreturn` syscall.
+    ///
+    /// `prmd` is not restored, so the privilege level cannot be changed.
+    ///
+    /// [`push_signal_frame`]: UserContext::push_signal_frame
+    pub fn restore_signal_frame(&mut self) -> Result<SignalReturn, UserAccessError> {
+        let frame_addr = self.regs.sp;
+        let uc: UContext = unsafe { read_user(frame_addr + offset_of!(RtSigFrame, uc))? };
+        let mc = &uc.uc_mcontext;
+
+        #[cfg(feature = "fp-simd")]
+        {
+            let ext = &uc.extcontext;
+            if mc.flags & SC_USED_FP != 0 && ext.fpu_info.magic == FPU_CTX_MAGIC {
+                FpuState {
+                    fp: ext.fpu.regs,
+                    fcc: ext.fpu.fcc.to_le_bytes(),
+                    fcsr: ext.fpu.fcsr,
+                }
+                .restore();
+            }
+        }
+
+        let mut regs = mc.regs;
+        regs[0] = 0;
+        self.regs = unsafe { core::mem::transmute::<[u64; 32], GeneralRegisters>(regs) };
+        self.era = mc.pc as _;
+
+        Ok(SignalReturn {
+            sigmask: uc.uc_sigmask,
+            uc_stack: uc.uc_stack,
+        })
+    }
+}
@@ -1,10 +1,14 @@
 use loongArch64::register::{
-    badv,
+    badi, badv,
     estat::{self, Exception, Trap},
 };
+use memory_addr::VirtAddr;
 
-use super::context::TrapFrame;
-use crate::trap::PageFaultFlags;
+use super::context::{RawTrapCause, TrapFrame};
+use crate::trap::{
+    handle_exception, handle_syscall, handle_unhandled_trap, trap_enter, trap_exit, PageFaultFlags,
+    PageFaultInfo, PageFaultKind, TrapCause,
+};
 
 core::arch::global_asm!(
     include_asm_macros!(),
@@ -12,14 +16,75 @@ core::arch::global_asm!(
     trapframe_size = const (core::mem::size_of::<TrapFrame>()),
 );
 
+/// Floating-Point Exception.
+pub(super) const ECODE_FPE: usize = 0x12;
+/// 128-bit vector (LSX) instructions Disabled exception.
+pub(super) const ECODE_SXD: usize = 0x10;
+/// 256-bit vector (LASX) instructions Disabled exception.
+pub(super) const ECODE_ASXD: usize = 0x11;
+
 fn handle_breakpoint(era: &mut usize) {
     debug!("Exception(Breakpoint) @ {era:#x} ");
     *era += 4;
 }
 
-fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
-    let vaddr = va!(badv::read().vaddr());
-    if handle_trap!(PAGE_FAULT, vaddr, access_flags) {
+/// Decodes the cause of the trap from `ESTAT`, `BADV` and `BADI`.
+pub(super) fn trap_cause() -> TrapCause {
+    let estat = estat::read();
+    let badv = badv::read().vaddr();
+    let raw = RawTrapCause {
+        estat: estat.raw(),
+        badv,
+        badi: badi::read().inst(),
+    };
+    match estat.cause() {
+        Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::PageNonReadableFault) => {
+            TrapCause::PageFault(va!(badv), PageFaultFlags::READ, raw)
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::PageModifyFault) => {
+            TrapCause::PageFault(va!(badv), PageFaultFlags::WRITE, raw)
+        }
+        Trap::Exception(Exception::FetchPageFault)
+        | Trap::Exception(Exception::PageNonExecutableFault) => {
+            TrapCause::PageFault(va!(badv), PageFaultFlags::EXECUTE, raw)
+        }
+        Trap::Exception(Exception::Breakpoint) => TrapCause::Breakpoint(raw),
+        Trap::Exception(Exception::AddressNotAligned) => {
+            TrapCause::Misaligned(Some(va!(badv)), raw)
+        }
+        Trap::Exception(Exception::InstructionNotExist)
+        | Trap::Exception(Exception::InstructionPrivilegeIllegal) => {
+            TrapCause::IllegalInstruction(raw)
+        }
+        Trap::Exception(Exception::FloatingPointUnavailable) => TrapCause::FpFault(raw),
+        Trap::Exception(Exception::Syscall) => TrapCause::Syscall(raw),
+        Trap::Interrupt(_) => TrapCause::Irq(estat.is().trailing_zeros() as usize, raw),
+        Trap::Unknown if matches!(estat.ecode(), ECODE_FPE | ECODE_SXD | ECODE_ASXD) => {
+            TrapCause::FpFault(raw)
+        }
+        _ => TrapCause::Other(raw),
+    }
+}
+
+fn page_fault_kind(raw: &RawTrapCause) -> PageFaultKind {
+    match (raw.estat >> 16) & 0x3f {
+        0x1..=0x3 => PageFaultKind::Translation, // PIL, PIS, PIF
+        0x4 => PageFaultKind::Dirty,             // PME
+        0x5 | 0x6 => PageFaultKind::Permission,  // PNR, PNX
+        _ => PageFaultKind::Unknown,
+    }
+}
+
+fn handle_page_fault(
+    tf: &mut TrapFrame,
+    vaddr: VirtAddr,
+    access_flags: PageFaultFlags,
+    raw: RawTrapCause,
+) {
+    let info = PageFaultInfo::new(tf, vaddr, access_flags, page_fault_kind(&raw), raw);
+    if handle_trap!(PAGE_FAULT, tf, &info) {
         return;
     }
     #[cfg(feature = "uspace")]
@@ -27,6 +92,9 @@ fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
         return;
     }
     core::hint::cold_path();
+    if handle_unhandled_trap(tf, TrapCause::PageFault(vaddr, access_flags, raw)) {
+        return;
+    }
     panic!(
         "Unhandled PLV0 Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
         tf.era,
@@ -37,39 +105,69 @@ fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
     );
 }
 
+fn handle_misaligned(tf: &mut TrapFrame, cause: TrapCause) {
+    let err = match unsafe { tf.emulate_unaligned() } {
+        Ok(()) => return,
+        Err(err) => err,
+    };
+    #[cfg(feature = "uspace")]
+    if tf.fixup_exception() {
+        return;
+    }
+    if handle_exception(tf, cause) {
+        return;
+    }
+    core::hint::cold_path();
+    if handle_unhandled_trap(tf, cause) {
+        return;
+    }
+    panic!(
+        "Unhandled PLV0 misaligned access @ {:#x}: {}:\n{:#x?}\n{}",
+        tf.era,
+        err,
+        tf,
+        tf.backtrace()
+    );
+}
+
+fn handle_kernel_syscall(tf: &mut TrapFrame) -> bool {
+    tf.era += 4;
+    if handle_syscall(tf) {
+        return true;
+    }
+    tf.era -= 4;
+    false
+}
+
 #[unsafe(no_mangle)]
 fn loongarch64_trap_handler(tf: &mut TrapFrame) {
-    let estat = estat::read();
-
-    match estat.cause() {
-        Trap::Exception(Exception::LoadPageFault)
-        | Trap::Exception(Exception::PageNonReadableFault) => {
-            handle_page_fault(tf, PageFaultFlags::READ)
-        }
-        Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::PageModifyFault) => {
-            handle_page_fault(tf, PageFaultFlags::WRITE)
-        }
-        Trap::Exception(Exception::FetchPageFault)
-        | Trap::Exception(Exception::PageNonExecutableFault) => {
-            handle_page_fault(tf, PageFaultFlags::EXECUTE);
+    let cause = trap_cause();
+    trap_enter(tf, cause, false);
+    match cause {
+        TrapCause::PageFault(vaddr, access_flags, raw) => {
+            handle_page_fault(tf, vaddr, access_flags, raw)
         }
-        Trap::Exception(Exception::Breakpoint) => handle_breakpoint(&mut tf.era),
-        Trap::Exception(Exception::AddressNotAligned) => unsafe {
-            tf.emulate_unaligned().unwrap();
-        },
-        Trap::Interrupt(_) => {
-            let irq_num: usize = estat.is().trailing_zeros() as usize;
-            handle_trap!(IRQ, irq_num);
+        TrapCause::Misaligned(..) => handle_misaligned(tf, cause),
+        TrapCause::Irq(irq, _) => {
+            handle_trap!(IRQ, irq);
         }
-        trap => {
+        TrapCause::Syscall(_) if handle_kernel_syscall(tf) => {}
+        #[cfg(feature = "lazy-fp")]
+        TrapCause::FpFault(_) if super::context::handle_lazy_fp_trap() => {}
+        #[cfg(feature = "vector")]
+        TrapCause::FpFault(raw) if super::context::handle_vector_trap(raw.estat) => {}
+        _ if handle_exception(tf, cause) => {}
+        TrapCause::Breakpoint(_) => handle_breakpoint(&mut tf.era),
+        _ if handle_unhandled_trap(tf, cause) => {}
+        _ => {
             panic!(
                 "Unhandled trap {:?} @ {:#x}:\n{:#x?}\n{}",
-                trap,
+                estat::read().cause(),
                 tf.era,
                 tf,
                 tf.backtrace()
             );
         }
     }
+    trap_exit(tf, cause, false);
 }
@@ -4,6 +4,18 @@ use core::{arch::asm, fmt};
 
 use loongArch64::register::badv;
 
+#[cfg(feature = "uspace")]
+use super::uspace::UserContext;
+#[cfg(feature = "uspace")]
+use crate::loongarch64_decode::AddrMode;
+use crate::loongarch64_decode::{
+    decode, extend, merge_partial, partial_range, split_partial, OpKind, UnalignedOp, VLD_OP,
+    VST_OP, XVLD_OP, XVST_OP,
+};
+#[cfg(feature = "uspace")]
+use crate::uspace_common::{
+    read_user, read_user_int, write_user_int, MisalignedError, UserAccessError,
+};
 use crate::{GeneralRegisters, TrapFrame};
 
 core::arch::global_asm!(include_asm_macros!(), include_str!("unaligned.S"));
@@ -512,101 +524,268 @@ pub fn read_fpr(fd: usize) -> u64 {
     value
 }
 
-const LDH_OP: u32 = 0xa1;
-const LDHU_OP: u32 = 0xa9;
-const LDW_OP: u32 = 0xa2;
-const LDWU_OP: u32 = 0xaa;
-const LDD_OP: u32 = 0xa3;
-const STH_OP: u32 = 0xa5;
-const STW_OP: u32 = 0xa6;
-const STD_OP: u32 = 0xa7;
-
-const LDPTRW_OP: u32 = 0x24;
-const LDPTRD_OP: u32 = 0x26;
-const STPTRW_OP: u32 = 0x25;
-const STPTRD_OP: u32 = 0x27;
-
-const LDXH_OP: u32 = 0x7048;
-const LDXHU_OP: u32 = 0x7008;
-const LDXW_OP: u32 = 0x7010;
-const LDXWU_OP: u32 = 0x7050;
-const LDXD_OP: u32 = 0x7018;
-const STXH_OP: u32 = 0x7028;
-const STXW_OP: u32 = 0x7030;
-const STXD_OP: u32 = 0x7038;
-
-const FLDS_OP: u32 = 0xac;
-const FLDD_OP: u32 = 0xae;
-const FSTS_OP: u32 = 0xad;
-const FSTD_OP: u32 = 0xaf;
-
-const FSTXS_OP: u32 = 0x7070;
-const FSTXD_OP: u32 = 0x7078;
-const FLDXS_OP: u32 = 0x7060;
-const FLDXD_OP: u32 = 0x7068;
+/// The `rj` field of the emulated vector loads and stores, whose base address
+/// register is fixed to `$t0` (`$r12`).
+const VEC_BASE_T0: u32 = 12 << 5;
+
+/// A buffer for the contents of an LSX or LASX register.
+#[repr(C, align(32))]
+struct VectorBuf([u64; 4]);
+
+/// Executes the vector load or store `INSN` whose base address is `buf`.
+///
+/// The instruction is emitted as a raw word, so that it can be assembled
+/// without the LSX and LASX target features.
+#[inline]
+unsafe fn vector_transfer<const INSN: u32>(buf: *mut VectorBuf) {
+    unsafe { asm!(".word {}", const INSN, in("$r12") buf) }
+}
+
+/// Executes `vector_transfer` for the vector register `$vd` with the opcode
+/// `$op` (`badi >> 22` of `vld`, `vst`, `xvld` or `xvst`).
+macro_rules! vector_transfer {
+    ($op:ident, $vd:expr, $buf:expr) => {
+        vector_transfer!(@arms $op, $vd, $buf,
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+            16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31)
+    };
+    (@arms $op:ident, $vd:expr, $buf:expr, $($n:literal)*) => {
+        match $vd {
+            $($n => vector_transfer::<{ $op << 22 | VEC_BASE_T0 | $n }>($buf),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// Memory accessed by the emulated instructions.
+trait Memory {
+    type Error;
+
+    /// Reads `n` bytes at `addr`, zero-extended to 64 bits.
+    fn read(&self, addr: usize, n: usize) -> Result<u64, Self::Error>;
+
+    /// Writes the low `n` bytes of `val` to `addr`.
+    fn write(&self, addr: usize, val: u64, n: usize) -> Result<(), Self::Error>;
+}
+
+/// Memory accessed by `_unaligned_read` and `_unaligned_write`, whose faults
+/// are fixed up to return an error.
+struct KernelMemory;
+
+impl Memory for KernelMemory {
+    type Error = UnalignedError;
+
+    fn read(&self, addr: usize, n: usize) -> Result<u64, UnalignedError> {
+        let mut value = 0;
+        unaligned_read(addr as u64, &mut value, n as u64, false)?;
+        Ok(value)
+    }
+
+    fn write(&self, addr: usize, val: u64, n: usize) -> Result<(), UnalignedError> {
+        unaligned_write(addr as u64, val, n as u64)
+    }
+}
+
+/// User memory accessed by [`user_copy`](crate::asm::user_copy).
+#[cfg(feature = "uspace")]
+struct UserMemory;
+
+#[cfg(feature = "uspace")]
+impl Memory for UserMemory {
+    type Error = UserAccessError;
+
+    fn read(&self, addr: usize, n: usize) -> Result<u64, UserAccessError> {
+        read_user_int(addr, n, false)
+    }
+
+    fn write(&self, addr: usize, val: u64, n: usize) -> Result<(), UserAccessError> {
+        write_user_int(addr, val, n)
+    }
+}
+
+/// Reads the GPR `i`, where `r0` is always 0.
+fn read_gpr(regs: &[usize; 32], i: usize) -> u64 {
+    if i == 0 {
+        0
+    } else {
+        regs[i] as u64
+    }
+}
+
+/// Writes the GPR `i`, ignoring writes to `r0`.
+fn write_gpr(regs: &mut [usize; 32], i: usize, val: u64) {
+    if i != 0 {
+        regs[i] = val as usize;
+    }
+}
+
+impl UnalignedOp {
+    /// Returns the address accessed by this instruction.
+    #[cfg(feature = "uspace")]
+    fn address(&self, regs: &[usize; 32]) -> usize {
+        match self.addr {
+            AddrMode::Offset { rj, offset } => {
+                (read_gpr(regs, rj) as usize).wrapping_add_signed(offset)
+            }
+            AddrMode::Indexed { rj, rk } => {
+                (read_gpr(regs, rj) as usize).wrapping_add(read_gpr(regs, rk) as usize)
+            }
+        }
+    }
+
+    /// Executes this instruction at `addr` of `mem`, updating the GPRs in
+    /// `regs`, or the FPRs and vector registers of the current CPU.
+    fn execute<M: Memory>(
+        &self,
+        addr: usize,
+        regs: &mut [usize; 32],
+        mem: &M,
+    ) -> Result<(), M::Error> {
+        let Self { kind, rd, size, .. } = *self;
+        match kind {
+            OpKind::Load { signed } => {
+                write_gpr(regs, rd, extend(mem.read(addr, size)?, size, signed));
+            }
+            OpKind::Store => mem.write(addr, read_gpr(regs, rd), size)?,
+            OpKind::FpLoad => write_fpr(rd, mem.read(addr, size)?),
+            OpKind::FpStore => mem.write(addr, read_fpr(rd), size)?,
+            OpKind::VectorLoad => {
+                let mut buf = VectorBuf([0; 4]);
+                for (i, val) in buf.0.iter_mut().take(size / 8).enumerate() {
+                    *val = mem.read(addr + i * 8, 8)?;
+                }
+                unsafe {
+                    if size == 16 {
+                        vector_transfer!(VLD_OP, rd, &mut buf)
+                    } else {
+                        vector_transfer!(XVLD_OP, rd, &mut buf)
+                    }
+                }
+            }
+            OpKind::VectorStore => {
+                let mut buf = VectorBuf([0; 4]);
+                unsafe {
+                    if size == 16 {
+                        vector_transfer!(VST_OP, rd, &mut buf)
+                    } else {
+                        vector_transfer!(XVST_OP, rd, &mut buf)
+                    }
+                }
+                for (i, val) in buf.0.iter().take(size / 8).enumerate() {
+                    mem.write(addr + i * 8, *val, 8)?;
+                }
+            }
+            OpKind::LoadLeft | OpKind::LoadRight => {
+                let left = kind == OpKind::LoadLeft;
+                let (start, n) = partial_range(addr, size, left);
+                let val = mem.read(start, n)?;
+                let merged = merge_partial(read_gpr(regs, rd), val, n, size, left);
+                write_gpr(regs, rd, merged);
+            }
+            OpKind::StoreLeft | OpKind::StoreRight => {
+                let left = kind == OpKind::StoreLeft;
+                let (start, n) = partial_range(addr, size, left);
+                mem.write(start, split_partial(read_gpr(regs, rd), n, size, left), n)?;
+            }
+            OpKind::LoadLinked => {
+                write_gpr(regs, rd, extend(mem.read(addr, size)?, size, true));
+            }
+            OpKind::StoreConditional => {
+                mem.write(addr, read_gpr(regs, rd), size)?;
+                write_gpr(regs, rd, 1);
+            }
+            OpKind::Swap { rk } => {
+                let new = read_gpr(regs, rk);
+                let old = mem.read(addr, size)?;
+                mem.write(addr, new, size)?;
+                write_gpr(regs, rd, extend(old, size, true));
+            }
+        }
+        Ok(())
+    }
+}
 
 impl TrapFrame {
     /// Emulates an unaligned memory access triggered by a trap.
     ///
+    /// Besides the integer and floating-point loads and stores, the LSX/LASX
+    /// vector loads and stores, `ldl`/`ldr`/`stl`/`str`, `ll`/`sc` and
+    /// `amswap` are supported. The atomic instructions are emulated as a plain
+    /// load and store, and `sc` always succeeds. So they are not atomic with
+    /// respect to other CPUs, and are only atomic on the current CPU if
+    /// interrupts stay disabled, as in the trap handler.
+    ///
+    /// An [`UnalignedError`] is returned if the instruction is not supported,
+    /// or if the memory cannot be accessed.
+    ///
     /// # Safety
     /// This function uses raw pointers and inline assembly to handle unaligned memory accesses,
     /// so it must only be called in a valid trap context with a properly initialized TrapFrame.
     pub unsafe fn emulate_unaligned(&mut self) -> Result<(), UnalignedError> {
-        let mut value: u64 = 0;
-
-        let badv = badv::read().vaddr() as u64;
-        let badi = core::ptr::read(self.era as *const u32);
-        let rd = (badi & 0x1f) as usize;
+        let badv = badv::read().vaddr();
+        let badi = unsafe { core::ptr::read(self.era as *const u32) };
+        let op = decode(badi).ok_or(UnalignedError {
+            addr: badv as u64,
+            n: None,
+        })?;
 
         let regs = unsafe {
             core::mem::transmute::<&mut GeneralRegisters, &mut [usize; 32]>(&mut self.regs)
         };
+        op.execute(badv, regs, &KernelMemory)?;
 
-        if (badi >> 22) == LDD_OP || (badi >> 24) == LDPTRD_OP || (badi >> 15) == LDXD_OP {
-            unaligned_read(badv, &mut value, 8, true)?;
-            regs[rd] = value as usize;
-        } else if (badi >> 22) == LDW_OP || (badi >> 24) == LDPTRW_OP || (badi >> 15) == LDXW_OP {
-            unaligned_read(badv, &mut value, 4, true)?;
-            regs[rd] = value as usize;
-        } else if (badi >> 22) == LDWU_OP || (badi >> 15) == LDXWU_OP {
-            unaligned_read(badv, &mut value, 4, false)?;
-            regs[rd] = value as usize;
-        } else if (badi >> 22) == LDH_OP || (badi >> 15) == LDXH_OP {
-            unaligned_read(badv, &mut value, 2, true)?;
-            regs[rd] = value as usize;
-        } else if (badi >> 22) == LDHU_OP || (badi >> 15) == LDXHU_OP {
-            unaligned_read(badv, &mut value, 2, false)?;
-            regs[rd] = value as usize;
-        } else if (badi >> 22) == STD_OP || (badi >> 24) == STPTRD_OP || (badi >> 15) == STXD_OP {
-            value = regs[rd] as u64;
-            unaligned_write(badv, value, 8)?;
-        } else if (badi >> 22) == STW_OP || (badi >> 24) == STPTRW_OP || (badi >> 15) == STXW_OP {
-            value = regs[rd] as u64;
-            unaligned_write(badv, value, 4)?;
-        } else if (badi >> 22) == STH_OP || (badi >> 15) == STXH_OP {
-            value = regs[rd] as u64;
-            unaligned_write(badv, value, 2)?;
-        } else if (badi >> 22) == FLDD_OP || (badi >> 15) == FLDXD_OP {
-            unaligned_read(badv, &mut value, 8, true)?;
-            write_fpr(rd, value);
-        } else if (badi >> 22) == FLDS_OP || (badi >> 15) == FLDXS_OP {
-            unaligned_read(badv, &mut value, 4, true)?;
-            write_fpr(rd, value);
-        } else if (badi >> 22) == FSTD_OP || (badi >> 15) == FSTXD_OP {
-            value = read_fpr(rd);
-            unaligned_write(badv, value, 8)?;
-        } else if (badi >> 22) == FSTS_OP || (badi >> 15) == FSTXS_OP {
-            value = read_fpr(rd);
-            unaligned_write(badv, value, 4)?;
-        } else {
-            return Err(UnalignedError {
-                addr: badv,
-                n: None,
-            });
+        self.era += 4;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "uspace")]
+impl UserContext {
+    /// Emulates the misaligned load or store at `era`, which causes a
+    /// [`ExceptionKind::Misaligned`](super::uspace::ExceptionKind::Misaligned)
+    /// exception.
+    ///
+    /// Unlike [`TrapFrame::emulate_unaligned`], the instruction is fetched from
+    /// user memory and the data is accessed with
+    /// [`user_copy`](crate::asm::user_copy), so a bad user address results in
+    /// an error instead of a kernel fault. On success, the destination
+    /// register is updated and `era` is advanced to the next instruction.
+    ///
+    /// The same instructions as [`TrapFrame::emulate_unaligned`] are
+    /// supported, except for the atomic ones (`ll`/`sc` and `amswap`), which
+    /// cannot be emulated atomically and should be reported as `SIGBUS`. The
+    /// floating-point and vector loads and stores are only supported with the
+    /// `fp-simd` feature.
+    pub fn emulate_misaligned(&mut self) -> Result<(), MisalignedError> {
+        let badi: u32 = unsafe { read_user(self.era)? };
+        let unsupported = MisalignedError::UnsupportedInstruction(badi);
+        let op = decode(badi).ok_or(unsupported)?;
+        // Other threads may access the memory concurrently, and the user
+        // memory accesses may sleep on page faults.
+        if matches!(
+            op.kind,
+            OpKind::LoadLinked | OpKind::StoreConditional | OpKind::Swap { .. }
+        ) {
+            return Err(unsupported);
+        }
+        let uses_fp = matches!(
+            op.kind,
+            OpKind::FpLoad | OpKind::FpStore | OpKind::VectorLoad | OpKind::VectorStore
+        );
+        if uses_fp && !cfg!(feature = "fp-simd") {
+            return Err(unsupported);
         }
 
-        self.era += 4;
+        // `r0` holds the kernel stack pointer while in user space, which is
+        // never accessed by `execute`.
+        let regs = unsafe {
+            core::mem::transmute::<&mut GeneralRegisters, &mut [usize; 32]>(&mut self.regs)
+        };
+        let addr = op.address(regs);
+        op.execute(addr, regs, &UserMemory)?;
 
+        self.era += 4;
         Ok(())
     }
 }
@@ -8,14 +8,30 @@ use loongArch64::register::{
 };
 use memory_addr::VirtAddr;
 
-use crate::{trap::PageFaultFlags, TrapFrame};
+use super::trap::{trap_cause, ECODE_ASXD, ECODE_FPE, ECODE_SXD};
+use crate::{
+    trap::{trap_enter, trap_exit, PageFaultFlags},
+    uspace_common::siginfo::*,
+    TrapFrame,
+};
+
+pub use super::coredump::*;
+pub use crate::uspace_common::{
+    ExceptionKind, MisalignedError, ReturnReason, SignalFrameParams, SignalReturn, SignalStack,
+    UserAccessError, NT_PRFPREG, NT_PRSTATUS, SIGINFO_SIZE,
+};
 
-pub use crate::uspace_common::{ExceptionKind, ReturnReason};
+/// The end of the user address space (the lower half of the 48-bit virtual
+/// address space).
+pub(crate) const USER_SPACE_END: usize = 1 << 47;
 
 /// Context to enter user space.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
-pub struct UserContext(TrapFrame);
+pub struct UserContext {
+    tf: TrapFrame,
+    single_step: bool,
+}
 
 impl UserContext {
     /// Creates a new context with the given entry point, user stack pointer,
@@ -28,7 +44,20 @@ impl UserContext {
         trap_frame.era = entry;
         trap_frame.prmd = PPLV_UMODE | PIE;
         trap_frame.regs.a0 = arg0;
-        Self(trap_frame)
+        Self {
+            tf: trap_frame,
+            single_step: false,
+        }
+    }
+
+    /// Enables or disables single-stepping.
+    ///
+    /// When enabled, [`run`](Self::run) returns [`ReturnReason::SingleStep`]
+    /// after a single user instruction is executed. It is implemented with the
+    /// instruction fetch watchpoint 0, which matches any user address and skips
+    /// the first match after returning to user space.
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
     }
 
     /// Enter user space.
@@ -37,13 +66,52 @@ impl UserContext {
     /// (saved in `sepc`).
     ///
     /// This function returns when an exception or syscall occurs.
+    /// With the `lazy-fp` feature, the trap of the first FP/SIMD instruction
+    /// after a context switch is handled internally, without returning, and so
+    /// is the trap of the first LSX or LASX instruction with the `vector`
+    /// feature.
     pub fn run(&mut self) -> ReturnReason {
+        loop {
+            if let Some(ret) = self.run_once() {
+                return ret;
+            }
+        }
+    }
+
+    /// Enters user space once, and returns [`None`] if the trap is handled
+    /// internally, so that user space should be entered again.
+    fn run_once(&mut self) -> Option<ReturnReason> {
         extern "C" {
             fn enter_user(uctx: &mut UserContext);
         }
 
         crate::asm::disable_irqs();
+        if self.single_step {
+            arm_single_step(self.era);
+        }
         unsafe { enter_user(self) };
+        if self.single_step {
+            disarm_single_step();
+        }
+        let cause = trap_cause();
+        trap_enter(self, cause, true);
+
+        #[cfg(feature = "lazy-fp")]
+        if matches!(cause, crate::trap::TrapCause::FpFault(_))
+            && super::context::handle_lazy_fp_trap()
+        {
+            // Re-execute the trapped instruction with FP/SIMD enabled.
+            trap_exit(self, cause, true);
+            return None;
+        }
+        #[cfg(feature = "vector")]
+        if let crate::trap::TrapCause::FpFault(raw) = cause {
+            if super::context::handle_vector_trap(raw.estat) {
+                // Re-execute the trapped instruction with LSX or LASX enabled.
+                trap_exit(self, cause, true);
+                return None;
+            }
+        }
 
         let estat = estat::read();
         let badv = badv::read().vaddr();
@@ -56,6 +124,7 @@ impl UserContext {
                 ReturnReason::Interrupt
             }
             Trap::Exception(Exception::Syscall) => {
+                self.orig_arg0 = self.regs.a0;
                 self.era += 4;
                 ReturnReason::Syscall
             }
@@ -71,12 +140,99 @@ impl UserContext {
             | Trap::Exception(Exception::PageNonExecutableFault) => {
                 ReturnReason::PageFault(va!(badv), PageFaultFlags::EXECUTE | PageFaultFlags::USER)
             }
-            Trap::Exception(e) => ReturnReason::Exception(ExceptionInfo { e, badv, badi }),
+            Trap::Exception(e) => ReturnReason::Exception(ExceptionInfo {
+                e: Some(e),
+                ecode: estat.ecode(),
+                badv,
+                badi,
+                era: self.era,
+                fcsr: 0,
+            }),
+            Trap::MachineError(_) => ReturnReason::MachineCheck,
+            // Not decoded by `estat::cause`.
+            Trap::Unknown if matches!(estat.ecode(), ECODE_FPE | ECODE_SXD | ECODE_ASXD) => {
+                let fcsr = if estat.ecode() == ECODE_FPE {
+                    take_fp_exception()
+                } else {
+                    0
+                };
+                ReturnReason::Exception(ExceptionInfo {
+                    e: None,
+                    ecode: estat.ecode(),
+                    badv,
+                    badi,
+                    era: self.era,
+                    fcsr,
+                })
+            }
+            #[cfg(feature = "hw-breakpoint")]
+            _ if let Some(addr) = crate::debug::take_hit(self.era, badv) => {
+                ReturnReason::Watchpoint(va!(addr))
+            }
+            _ if self.single_step && estat.ecode() == ECODE_WPE => ReturnReason::SingleStep,
             _ => ReturnReason::Unknown,
         };
 
+        trap_exit(self, cause, true);
         crate::asm::enable_irqs();
-        ret
+        Some(ret)
+    }
+}
+
+/// The exception code of watchpoint exceptions.
+const ECODE_WPE: usize = 0x13;
+
+/// The `Cause` field of `FCSR0`, i.e., the exceptions raised by the last
+/// floating-point instruction.
+const FCSR_CAUSE: u32 = 0x1f << 24;
+
+/// Reads `FCSR0` on a floating-point exception, and clears its `Cause` field
+/// as Linux does, so that it is not raised again by restoring `FCSR0`.
+fn take_fp_exception() -> u32 {
+    let fcsr: u32;
+    unsafe {
+        core::arch::asm!(
+            "movfcsr2gr {fcsr}, $fcsr0",
+            "and {tmp}, {fcsr}, {mask}",
+            "movgr2fcsr $fcsr0, {tmp}",
+            fcsr = out(reg) fcsr,
+            tmp = out(reg) _,
+            mask = in(reg) !FCSR_CAUSE,
+        )
+    };
+    fcsr
+}
+
+/// Arms the instruction fetch watchpoint 0 to trap on the next user
+/// instruction after the one at `era`.
+fn arm_single_step(era: usize) {
+    const CTRL_PLV3_ENABLE: usize = 1 << 4;
+    const FWPS_SKIP: usize = 1 << 16;
+    unsafe {
+        core::arch::asm!(
+            include_asm_macros!(),
+            "csrwr {addr}, LA_CSR_IB0ADDR",
+            "csrwr {mask}, LA_CSR_IB0MASK",
+            "csrwr $zero, LA_CSR_IB0ASID",
+            "csrwr {ctrl}, LA_CSR_IB0CTRL",
+            "csrwr {skip}, LA_CSR_FWPS",
+            addr = inout(reg) era => _,
+            mask = inout(reg) USER_SPACE_END - 1 => _,
+            ctrl = inout(reg) CTRL_PLV3_ENABLE => _,
+            skip = inout(reg) FWPS_SKIP => _,
+        )
+    }
+}
+
+/// Disarms the instruction fetch watchpoint 0 and clears its status.
+fn disarm_single_step() {
+    unsafe {
+        core::arch::asm!(
+            include_asm_macros!(),
+            "csrwr $zero, LA_CSR_IB0CTRL",
+            "csrwr {status}, LA_CSR_FWPS",
+            status = inout(reg) 1usize => _,
+        )
     }
 }
 
@@ -84,37 +240,107 @@ impl Deref for UserContext {
     type Target = TrapFrame;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.tf
     }
 }
 
 impl DerefMut for UserContext {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.tf
     }
 }
 
 /// Information about an exception that occurred in user space.
 #[derive(Debug, Clone, Copy)]
 pub struct ExceptionInfo {
-    /// The raw exception.
-    pub e: Exception,
+    /// The raw exception, or [`None`] if it is not decoded by the
+    /// `loongArch64` crate (see [`ecode`](Self::ecode)).
+    pub e: Option<Exception>,
+    /// The exception code (`ESTAT.Ecode`).
+    pub ecode: usize,
     /// The faulting address (from `badv`).
     pub badv: usize,
     /// The instruction causing the fault (from `badi`).
     pub badi: u32,
+    /// The address of the faulting instruction (from `era`).
+    pub era: usize,
+    /// `FCSR0` of floating-point exceptions, whose `Cause` field tells the
+    /// exceptions raised, or 0 for other exceptions.
+    pub fcsr: u32,
 }
 
+/// The code of the `break` instruction inserted by compilers on integer
+/// overflows.
+const BRK_OVERFLOW: u32 = 6;
+/// The code of the `break` instruction inserted by compilers on integer
+/// divisions by zero.
+const BRK_DIVZERO: u32 = 7;
+
 impl ExceptionInfo {
     /// Returns a generalized kind of this exception.
     pub fn kind(&self) -> ExceptionKind {
         match self.e {
-            Exception::Breakpoint => ExceptionKind::Breakpoint,
-            Exception::InstructionNotExist | Exception::InstructionPrivilegeIllegal => {
+            Some(Exception::Breakpoint) if self.break_code() == BRK_DIVZERO => {
+                ExceptionKind::DivideByZero
+            }
+            Some(Exception::Breakpoint) => ExceptionKind::Breakpoint,
+            Some(Exception::InstructionNotExist | Exception::InstructionPrivilegeIllegal) => {
+                ExceptionKind::IllegalInstruction
+            }
+            Some(Exception::AddressNotAligned) => ExceptionKind::Misaligned,
+            None if self.ecode == ECODE_FPE => ExceptionKind::FloatingPoint,
+            // LSX or LASX instructions that are not supported.
+            None if matches!(self.ecode, ECODE_SXD | ECODE_ASXD) => {
                 ExceptionKind::IllegalInstruction
             }
-            Exception::AddressNotAligned => ExceptionKind::Misaligned,
             _ => ExceptionKind::Other,
         }
     }
+
+    /// Returns the signal to deliver for this exception, as
+    /// `(signo, si_code, si_addr)`, following Linux.
+    pub fn to_signal(&self) -> (usize, i32, usize) {
+        match self.e {
+            Some(Exception::Breakpoint) => match self.break_code() {
+                BRK_OVERFLOW => (SIGFPE, FPE_INTOVF, self.era),
+                BRK_DIVZERO => (SIGFPE, FPE_INTDIV, self.era),
+                _ => (SIGTRAP, TRAP_BRKPT, self.era),
+            },
+            Some(Exception::InstructionNotExist) => (SIGILL, ILL_ILLOPC, self.era),
+            Some(Exception::InstructionPrivilegeIllegal) => (SIGILL, ILL_PRVOPC, self.era),
+            Some(Exception::AddressNotAligned) => (SIGBUS, BUS_ADRALN, self.badv),
+            Some(Exception::BoundsCheckFault) => (SIGSEGV, SEGV_BNDERR, self.badv),
+            Some(Exception::FetchInstructionAddressError | Exception::MemoryAccessAddressError) => {
+                (SIGBUS, SI_KERNEL, 0)
+            }
+            Some(Exception::PagePrivilegeIllegal) => (SIGSEGV, SEGV_ACCERR, self.badv),
+            None if self.ecode == ECODE_FPE => (SIGFPE, fp_exception_code(self.fcsr), self.era),
+            None if matches!(self.ecode, ECODE_SXD | ECODE_ASXD) => (SIGILL, ILL_ILLOPC, self.era),
+            _ => (SIGSEGV, SEGV_MAPERR, self.badv),
+        }
+    }
+
+    /// Returns the code of the `break` instruction in `badi`.
+    fn break_code(&self) -> u32 {
+        self.badi & 0x7fff
+    }
+}
+
+/// Returns the `si_code` of a floating-point exception from the `Cause` field
+/// of `FCSR0`.
+fn fp_exception_code(fcsr: u32) -> i32 {
+    let cause = (fcsr & FCSR_CAUSE) >> 24;
+    if cause & (1 << 4) != 0 {
+        FPE_FLTINV // V: Invalid Operation
+    } else if cause & (1 << 3) != 0 {
+        FPE_FLTDIV // Z: Divide by Zero
+    } else if cause & (1 << 2) != 0 {
+        FPE_FLTOVF // O: Overflow
+    } else if cause & (1 << 1) != 0 {
+        FPE_FLTUND // U: Underflow
+    } else if cause & (1 << 0) != 0 {
+        FPE_FLTRES // I: Inexact
+    } else {
+        FPE_FLTUNK
+    }
 }
@@ -0,0 +1,581 @@
+//! Decoder of the LoongArch instructions emulated on unaligned memory accesses.
+//!
+//! It contains no inline assembly, so that it is built on every target and
+//! tested on the host.
+
+const LDH_OP: u32 = 0xa1;
+const LDHU_OP: u32 = 0xa9;
+const LDW_OP: u32 = 0xa2;
+const LDWU_OP: u32 = 0xaa;
+const LDD_OP: u32 = 0xa3;
+const STH_OP: u32 = 0xa5;
+const STW_OP: u32 = 0xa6;
+const STD_OP: u32 = 0xa7;
+
+const LDPTRW_OP: u32 = 0x24;
+const LDPTRD_OP: u32 = 0x26;
+const STPTRW_OP: u32 = 0x25;
+const STPTRD_OP: u32 = 0x27;
+
+const LDXH_OP: u32 = 0x7008;
+const LDXHU_OP: u32 = 0x7048;
+const LDXW_OP: u32 = 0x7010;
+const LDXWU_OP: u32 = 0x7050;
+const LDXD_OP: u32 = 0x7018;
+const STXH_OP: u32 = 0x7028;
+const STXW_OP: u32 = 0x7030;
+const STXD_OP: u32 = 0x7038;
+
+const FLDS_OP: u32 = 0xac;
+const FLDD_OP: u32 = 0xae;
+const FSTS_OP: u32 = 0xad;
+const FSTD_OP: u32 = 0xaf;
+
+const FSTXS_OP: u32 = 0x7070;
+const FSTXD_OP: u32 = 0x7078;
+const FLDXS_OP: u32 = 0x7060;
+const FLDXD_OP: u32 = 0x7068;
+
+const LDLW_OP: u32 = 0xb8;
+const LDRW_OP: u32 = 0xb9;
+const LDLD_OP: u32 = 0xba;
+const LDRD_OP: u32 = 0xbb;
+const STLW_OP: u32 = 0xbc;
+const STRW_OP: u32 = 0xbd;
+const STLD_OP: u32 = 0xbe;
+const STRD_OP: u32 = 0xbf;
+
+/// The opcode of `vld` (`inst >> 22`).
+pub const VLD_OP: u32 = 0xb0;
+/// The opcode of `vst` (`inst >> 22`).
+pub const VST_OP: u32 = 0xb1;
+/// The opcode of `xvld` (`inst >> 22`).
+pub const XVLD_OP: u32 = 0xb2;
+/// The opcode of `xvst` (`inst >> 22`).
+pub const XVST_OP: u32 = 0xb3;
+
+const VLDX_OP: u32 = 0x7080;
+const VSTX_OP: u32 = 0x7088;
+const XVLDX_OP: u32 = 0x7090;
+const XVSTX_OP: u32 = 0x7098;
+
+const LLW_OP: u32 = 0x20;
+const SCW_OP: u32 = 0x21;
+const LLD_OP: u32 = 0x22;
+const SCD_OP: u32 = 0x23;
+
+const AMSWAPW_OP: u32 = 0x70c0;
+const AMSWAPD_OP: u32 = 0x70c1;
+const AMSWAPDBW_OP: u32 = 0x70d2;
+const AMSWAPDBD_OP: u32 = 0x70d3;
+
+/// The kind of an [`UnalignedOp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    /// Loads into the GPR `rd`, sign- or zero-extended.
+    Load {
+        /// Whether the value is sign-extended.
+        signed: bool,
+    },
+    /// Stores the GPR `rd`.
+    Store,
+    /// Loads into the FPR `fd`.
+    FpLoad,
+    /// Stores the FPR `fd`.
+    FpStore,
+    /// Loads into the LSX register `vd` or the LASX register `xd`.
+    VectorLoad,
+    /// Stores the LSX register `vd` or the LASX register `xd`.
+    VectorStore,
+    /// `ldl.*`, see [`partial_range`].
+    LoadLeft,
+    /// `ldr.*`, see [`partial_range`].
+    LoadRight,
+    /// `stl.*`, see [`partial_range`].
+    StoreLeft,
+    /// `str.*`, see [`partial_range`].
+    StoreRight,
+    /// `ll.*`, which loads into the GPR `rd`, sign-extended.
+    LoadLinked,
+    /// `sc.*`, which stores the GPR `rd` and sets it to 1.
+    StoreConditional,
+    /// `amswap[_db].*`, which stores the GPR `rk` and loads the old value
+    /// into the GPR `rd`, sign-extended.
+    Swap {
+        /// The GPR that holds the value to store.
+        rk: usize,
+    },
+}
+
+/// The addressing mode of an [`UnalignedOp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrMode {
+    /// `rj` plus a sign-extended immediate offset.
+    Offset {
+        /// The base GPR.
+        rj: usize,
+        /// The offset in bytes.
+        offset: isize,
+    },
+    /// `rj` plus `rk`.
+    Indexed {
+        /// The base GPR.
+        rj: usize,
+        /// The index GPR.
+        rk: usize,
+    },
+}
+
+/// An instruction that accesses memory at an unaligned address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnalignedOp {
+    /// The kind of the access.
+    pub kind: OpKind,
+    /// The data register (`rd`, `fd`, `vd` or `xd`).
+    pub rd: usize,
+    /// The number of bytes accessed.
+    pub size: usize,
+    /// The addressing mode.
+    pub addr: AddrMode,
+}
+
+/// Decodes the instructions with a 12-bit signed offset by `badi >> 22`.
+fn decode_2ri12(op: u32) -> Option<(OpKind, usize)> {
+    const SIGNED: OpKind = OpKind::Load { signed: true };
+    const UNSIGNED: OpKind = OpKind::Load { signed: false };
+    Some(match op {
+        LDH_OP => (SIGNED, 2),
+        LDHU_OP => (UNSIGNED, 2),
+        LDW_OP => (SIGNED, 4),
+        LDWU_OP => (UNSIGNED, 4),
+        LDD_OP => (SIGNED, 8),
+        STH_OP => (OpKind::Store, 2),
+        STW_OP => (OpKind::Store, 4),
+        STD_OP => (OpKind::Store, 8),
+        FLDS_OP => (OpKind::FpLoad, 4),
+        FLDD_OP => (OpKind::FpLoad, 8),
+        FSTS_OP => (OpKind::FpStore, 4),
+        FSTD_OP => (OpKind::FpStore, 8),
+        VLD_OP => (OpKind::VectorLoad, 16),
+        VST_OP => (OpKind::VectorStore, 16),
+        XVLD_OP => (OpKind::VectorLoad, 32),
+        XVST_OP => (OpKind::VectorStore, 32),
+        LDLW_OP => (OpKind::LoadLeft, 4),
+        LDRW_OP => (OpKind::LoadRight, 4),
+        LDLD_OP => (OpKind::LoadLeft, 8),
+        LDRD_OP => (OpKind::LoadRight, 8),
+        STLW_OP => (OpKind::StoreLeft, 4),
+        STRW_OP => (OpKind::StoreRight, 4),
+        STLD_OP => (OpKind::StoreLeft, 8),
+        STRD_OP => (OpKind::StoreRight, 8),
+        _ => return None,
+    })
+}
+
+/// Decodes the instructions with a 14-bit signed offset shifted left by 2 by
+/// `badi >> 24`.
+fn decode_2ri14(op: u32) -> Option<(OpKind, usize)> {
+    Some(match op {
+        LDPTRW_OP => (OpKind::Load { signed: true }, 4),
+        LDPTRD_OP => (OpKind::Load { signed: true }, 8),
+        STPTRW_OP => (OpKind::Store, 4),
+        STPTRD_OP => (OpKind::Store, 8),
+        LLW_OP => (OpKind::LoadLinked, 4),
+        LLD_OP => (OpKind::LoadLinked, 8),
+        SCW_OP => (OpKind::StoreConditional, 4),
+        SCD_OP => (OpKind::StoreConditional, 8),
+        _ => return None,
+    })
+}
+
+/// Decodes the instructions with three registers by `badi >> 15`.
+fn decode_3r(op: u32, rk: usize) -> Option<(OpKind, usize)> {
+    const SIGNED: OpKind = OpKind::Load { signed: true };
+    const UNSIGNED: OpKind = OpKind::Load { signed: false };
+    Some(match op {
+        LDXH_OP => (SIGNED, 2),
+        LDXHU_OP => (UNSIGNED, 2),
+        LDXW_OP => (SIGNED, 4),
+        LDXWU_OP => (UNSIGNED, 4),
+        LDXD_OP => (SIGNED, 8),
+        STXH_OP => (OpKind::Store, 2),
+        STXW_OP => (OpKind::Store, 4),
+        STXD_OP => (OpKind::Store, 8),
+        FLDXS_OP => (OpKind::FpLoad, 4),
+        FLDXD_OP => (OpKind::FpLoad, 8),
+        FSTXS_OP => (OpKind::FpStore, 4),
+        FSTXD_OP => (OpKind::FpStore, 8),
+        VLDX_OP => (OpKind::VectorLoad, 16),
+        VSTX_OP => (OpKind::VectorStore, 16),
+        XVLDX_OP => (OpKind::VectorLoad, 32),
+        XVSTX_OP => (OpKind::VectorStore, 32),
+        AMSWAPW_OP | AMSWAPDBW_OP => (OpKind::Swap { rk }, 4),
+        AMSWAPD_OP | AMSWAPDBD_OP => (OpKind::Swap { rk }, 8),
+        _ => return None,
+    })
+}
+
+/// Decodes the instruction `badi` that causes an unaligned memory access.
+///
+/// Returns [`None`] if it is not a supported load or store.
+pub fn decode(badi: u32) -> Option<UnalignedOp> {
+    let rd = (badi & 0x1f) as usize;
+    let rj = ((badi >> 5) & 0x1f) as usize;
+    let rk = ((badi >> 10) & 0x1f) as usize;
+    let si12 = ((badi << 10) as i32 >> 20) as isize;
+    let si14 = ((badi << 8) as i32 >> 18 << 2) as isize;
+
+    let (kind, size, addr) = if let Some((kind, size)) = decode_2ri12(badi >> 22) {
+        (kind, size, AddrMode::Offset { rj, offset: si12 })
+    } else if let Some((kind, size)) = decode_2ri14(badi >> 24) {
+        (kind, size, AddrMode::Offset { rj, offset: si14 })
+    } else if let Some((kind, size)) = decode_3r(badi >> 15, rk) {
+        let addr = match kind {
+            // `amswap` uses `rk` as the source, and `rj` as the address.
+            OpKind::Swap { .. } => AddrMode::Offset { rj, offset: 0 },
+            _ => AddrMode::Indexed { rj, rk },
+        };
+        (kind, size, addr)
+    } else {
+        return None;
+    };
+    Some(UnalignedOp {
+        kind,
+        rd,
+        size,
+        addr,
+    })
+}
+
+/// Sign- or zero-extends the low `size` bytes of `val` to 64 bits.
+pub fn extend(val: u64, size: usize, signed: bool) -> u64 {
+    let shift = 64 - size as u32 * 8;
+    if signed {
+        ((val << shift) as i64 >> shift) as u64
+    } else {
+        val << shift >> shift
+    }
+}
+
+/// Returns the address and the number of bytes accessed by `ldl`/`stl`
+/// (`left`) or `ldr`/`str` at `addr`, which never cross the `size`-byte
+/// aligned block containing `addr`.
+///
+/// `ldl` and `stl` access the bytes from the start of the block to `addr`,
+/// which are the most significant bytes of the register. `ldr` and `str`
+/// access the bytes from `addr` to the end of the block, which are the least
+/// significant bytes of the register.
+pub fn partial_range(addr: usize, size: usize, left: bool) -> (usize, usize) {
+    let offset = addr & (size - 1);
+    if left {
+        (addr - offset, offset + 1)
+    } else {
+        (addr, size - offset)
+    }
+}
+
+/// Returns the mask of the low `n` bytes.
+fn low_bytes_mask(n: usize) -> u64 {
+    if n >= 8 {
+        u64::MAX
+    } else {
+        (1 << (n * 8)) - 1
+    }
+}
+
+/// Merges the `n` bytes `val` loaded by `ldl` (`left`) or `ldr` into the
+/// register value `old`. The result of `*.w` is sign-extended.
+pub fn merge_partial(old: u64, val: u64, n: usize, size: usize, left: bool) -> u64 {
+    let merged = if left {
+        (old & low_bytes_mask(size - n)) | val << ((size - n) * 8)
+    } else {
+        (old & !low_bytes_mask(n)) | val
+    };
+    extend(merged, size, true)
+}
+
+/// Returns the `n` bytes of the register value `val` stored by `stl`
+/// (`left`) or `str`.
+pub fn split_partial(val: u64, n: usize, size: usize, left: bool) -> u64 {
+    let val = if left { val >> ((size - n) * 8) } else { val };
+    val & low_bytes_mask(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a 2RI12 instruction.
+    fn ri12(op: u32, si12: i32, rj: u32, rd: u32) -> u32 {
+        op << 22 | (si12 as u32 & 0xfff) << 10 | rj << 5 | rd
+    }
+
+    /// Encodes a 2RI14 instruction.
+    fn ri14(op: u32, si14: i32, rj: u32, rd: u32) -> u32 {
+        op << 24 | (si14 as u32 & 0x3fff) << 10 | rj << 5 | rd
+    }
+
+    /// Encodes a 3R instruction.
+    fn r3(op: u32, rk: u32, rj: u32, rd: u32) -> u32 {
+        op << 15 | rk << 10 | rj << 5 | rd
+    }
+
+    fn op(kind: OpKind, rd: usize, size: usize, addr: AddrMode) -> Option<UnalignedOp> {
+        Some(UnalignedOp {
+            kind,
+            rd,
+            size,
+            addr,
+        })
+    }
+
+    #[test]
+    fn ri12_ops() {
+        let cases = [
+            // `ld.h $a0, $a1, -8`
+            (0x287f_e0a4, OpKind::Load { signed: true }, 2),
+            // `ld.hu $a0, $a1, -8`
+            (0x2a7f_e0a4, OpKind::Load { signed: false }, 2),
+            // `ld.w $a0, $a1, -8`
+            (0x28bf_e0a4, OpKind::Load { signed: true }, 4),
+            // `ld.wu $a0, $a1, -8`
+            (0x2abf_e0a4, OpKind::Load { signed: false }, 4),
+            // `ld.d $a0, $a1, -8`
+            (0x28ff_e0a4, OpKind::Load { signed: true }, 8),
+            // `st.h $a0, $a1, -8`
+            (0x297f_e0a4, OpKind::Store, 2),
+            // `st.w $a0, $a1, -8`
+            (0x29bf_e0a4, OpKind::Store, 4),
+            // `st.d $a0, $a1, -8`
+            (0x29ff_e0a4, OpKind::Store, 8),
+            // `fld.s $fa4, $a1, -8`
+            (0x2b3f_e0a4, OpKind::FpLoad, 4),
+            // `fld.d $fa4, $a1, -8`
+            (0x2bbf_e0a4, OpKind::FpLoad, 8),
+            // `fst.s $fa4, $a1, -8`
+            (0x2b7f_e0a4, OpKind::FpStore, 4),
+            // `fst.d $fa4, $a1, -8`
+            (0x2bff_e0a4, OpKind::FpStore, 8),
+            // `vld $vr4, $a1, -8`
+            (0x2c3f_e0a4, OpKind::VectorLoad, 16),
+            // `vst $vr4, $a1, -8`
+            (0x2c7f_e0a4, OpKind::VectorStore, 16),
+            // `xvld $xr4, $a1, -8`
+            (0x2cbf_e0a4, OpKind::VectorLoad, 32),
+            // `xvst $xr4, $a1, -8`
+            (0x2cff_e0a4, OpKind::VectorStore, 32),
+            // `ldl.w $a0, $a1, -8`
+            (0x2e3f_e0a4, OpKind::LoadLeft, 4),
+            // `ldr.w $a0, $a1, -8`
+            (0x2e7f_e0a4, OpKind::LoadRight, 4),
+            // `ldl.d $a0, $a1, -8`
+            (0x2ebf_e0a4, OpKind::LoadLeft, 8),
+            // `ldr.d $a0, $a1, -8`
+            (0x2eff_e0a4, OpKind::LoadRight, 8),
+            // `stl.w $a0, $a1, -8`
+            (0x2f3f_e0a4, OpKind::StoreLeft, 4),
+            // `str.w $a0, $a1, -8`
+            (0x2f7f_e0a4, OpKind::StoreRight, 4),
+            // `stl.d $a0, $a1, -8`
+            (0x2fbf_e0a4, OpKind::StoreLeft, 8),
+            // `str.d $a0, $a1, -8`
+            (0x2fff_e0a4, OpKind::StoreRight, 8),
+        ];
+        let addr = AddrMode::Offset { rj: 5, offset: -8 };
+        for (inst, kind, size) in cases {
+            assert_eq!(decode(inst), op(kind, 4, size, addr), "{inst:#010x}");
+        }
+    }
+
+    #[test]
+    fn ri14_ops() {
+        let cases = [
+            // `ldptr.w $a0, $a1, -8`
+            (0x24ff_f8a4, OpKind::Load { signed: true }, 4),
+            // `ldptr.d $a0, $a1, -8`
+            (0x26ff_f8a4, OpKind::Load { signed: true }, 8),
+            // `stptr.w $a0, $a1, -8`
+            (0x25ff_f8a4, OpKind::Store, 4),
+            // `stptr.d $a0, $a1, -8`
+            (0x27ff_f8a4, OpKind::Store, 8),
+            // `ll.w $a0, $a1, -8`
+            (0x20ff_f8a4, OpKind::LoadLinked, 4),
+            // `ll.d $a0, $a1, -8`
+            (0x22ff_f8a4, OpKind::LoadLinked, 8),
+            // `sc.w $a0, $a1, -8`
+            (0x21ff_f8a4, OpKind::StoreConditional, 4),
+            // `sc.d $a0, $a1, -8`
+            (0x23ff_f8a4, OpKind::StoreConditional, 8),
+        ];
+        let addr = AddrMode::Offset { rj: 5, offset: -8 };
+        for (inst, kind, size) in cases {
+            assert_eq!(decode(inst), op(kind, 4, size, addr), "{inst:#010x}");
+        }
+    }
+
+    #[test]
+    fn r3_ops() {
+        let cases = [
+            // `ldx.h $a0, $a1, $a2`
+            (0x3804_18a4, OpKind::Load { signed: true }, 2),
+            // `ldx.hu $a0, $a1, $a2`
+            (0x3824_18a4, OpKind::Load { signed: false }, 2),
+            // `ldx.w $a0, $a1, $a2`
+            (0x3808_18a4, OpKind::Load { signed: true }, 4),
+            // `ldx.wu $a0, $a1, $a2`
+            (0x3828_18a4, OpKind::Load { signed: false }, 4),
+            // `ldx.d $a0, $a1, $a2`
+            (0x380c_18a4, OpKind::Load { signed: true }, 8),
+            // `stx.h $a0, $a1, $a2`
+            (0x3814_18a4, OpKind::Store, 2),
+            // `stx.w $a0, $a1, $a2`
+            (0x3818_18a4, OpKind::Store, 4),
+            // `stx.d $a0, $a1, $a2`
+            (0x381c_18a4, OpKind::Store, 8),
+            // `fldx.s $fa4, $a1, $a2`
+            (0x3830_18a4, OpKind::FpLoad, 4),
+            // `fldx.d $fa4, $a1, $a2`
+            (0x3834_18a4, OpKind::FpLoad, 8),
+            // `fstx.s $fa4, $a1, $a2`
+            (0x3838_18a4, OpKind::FpStore, 4),
+            // `fstx.d $fa4, $a1, $a2`
+            (0x383c_18a4, OpKind::FpStore, 8),
+            // `vldx $vr4, $a1, $a2`
+            (0x3840_18a4, OpKind::VectorLoad, 16),
+            // `vstx $vr4, $a1, $a2`
+            (0x3844_18a4, OpKind::VectorStore, 16),
+            // `xvldx $xr4, $a1, $a2`
+            (0x3848_18a4, OpKind::VectorLoad, 32),
+            // `xvstx $xr4, $a1, $a2`
+            (0x384c_18a4, OpKind::VectorStore, 32),
+        ];
+        let addr = AddrMode::Indexed { rj: 5, rk: 6 };
+        for (inst, kind, size) in cases {
+            assert_eq!(decode(inst), op(kind, 4, size, addr), "{inst:#010x}");
+        }
+    }
+
+    #[test]
+    fn amswap_ops() {
+        let cases = [
+            // `amswap.w $a0, $a2, $a1`
+            (0x3860_18a4, 4),
+            // `amswap_db.w $a0, $a2, $a1`
+            (0x3869_18a4, 4),
+            // `amswap.d $a0, $a2, $a1`
+            (0x3860_98a4, 8),
+            // `amswap_db.d $a0, $a2, $a1`
+            (0x3869_98a4, 8),
+        ];
+        let addr = AddrMode::Offset { rj: 5, offset: 0 };
+        for (inst, size) in cases {
+            assert_eq!(
+                decode(inst),
+                op(OpKind::Swap { rk: 6 }, 4, size, addr),
+                "{inst:#010x}"
+            );
+        }
+    }
+
+    #[test]
+    fn operand_fields() {
+        // `ld.d` with the extreme offsets and registers.
+        for (si12, rj, rd) in [(0, 0, 0), (0x7ff, 31, 31), (-0x800, 4, 12), (-1, 3, 5)] {
+            let addr = AddrMode::Offset {
+                rj: rj as usize,
+                offset: si12 as isize,
+            };
+            let kind = OpKind::Load { signed: true };
+            assert_eq!(
+                decode(ri12(0xa3, si12, rj, rd)),
+                op(kind, rd as usize, 8, addr)
+            );
+        }
+        // `stptr.d`, whose offset is shifted left by 2.
+        for (si14, rj, rd) in [(0, 0, 0), (0x1fff, 31, 31), (-0x2000, 4, 12), (-1, 3, 5)] {
+            let addr = AddrMode::Offset {
+                rj: rj as usize,
+                offset: si14 as isize * 4,
+            };
+            assert_eq!(
+                decode(ri14(0x27, si14, rj, rd)),
+                op(OpKind::Store, rd as usize, 8, addr)
+            );
+        }
+        // `stx.d`
+        for (rk, rj, rd) in [(0, 0, 0), (31, 31, 31), (7, 4, 12)] {
+            let addr = AddrMode::Indexed {
+                rj: rj as usize,
+                rk: rk as usize,
+            };
+            assert_eq!(
+                decode(r3(0x7038, rk, rj, rd)),
+                op(OpKind::Store, rd as usize, 8, addr)
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_ops() {
+        // `add.w $a0, $a1, $a2`
+        assert_eq!(decode(0x0010_18a4), None);
+        // `ld.b $a0, $a1, 0`, which is always aligned.
+        assert_eq!(decode(ri12(0xa0, 0, 5, 4)), None);
+        // `st.b $a0, $a1, 0`
+        assert_eq!(decode(ri12(0xa4, 0, 5, 4)), None);
+        // `ldx.b $a0, $a1, $a2`
+        assert_eq!(decode(r3(0x7000, 6, 5, 4)), None);
+        // `amadd.w $a0, $a2, $a1`
+        assert_eq!(decode(r3(0x70c2, 6, 5, 4)), None);
+        // `preld 0, $a1, 0`
+        assert_eq!(decode(ri12(0xab, 0, 5, 0)), None);
+    }
+
+    #[test]
+    fn extension() {
+        assert_eq!(extend(0x8001, 2, true), 0xffff_ffff_ffff_8001);
+        assert_eq!(extend(0x8001, 2, false), 0x8001);
+        assert_eq!(extend(0x7fff, 2, true), 0x7fff);
+        assert_eq!(extend(0xdead_8001, 2, false), 0x8001);
+        assert_eq!(extend(0x8000_0000, 4, true), 0xffff_ffff_8000_0000);
+        assert_eq!(extend(0x8000_0000, 4, false), 0x8000_0000);
+        assert_eq!(extend(0x1_7fff_ffff, 4, true), 0x7fff_ffff);
+        assert_eq!(
+            extend(0x8000_0000_0000_0000, 8, true),
+            0x8000_0000_0000_0000
+        );
+        assert_eq!(extend(u64::MAX, 8, false), u64::MAX);
+    }
+
+    #[test]
+    fn partial_loads() {
+        let old = 0x1122_3344_5566_7788;
+        // `ldl.w` at offset 1 loads `[0xaa, 0xbb]` into the 2 high bytes.
+        assert_eq!(partial_range(0x1001, 4, true), (0x1000, 2));
+        assert_eq!(
+            merge_partial(old, 0xbbaa, 2, 4, true),
+            0xffff_ffff_bbaa_7788
+        );
+        // `ldr.w` at offset 1 loads `[0xaa, 0xbb, 0xcc]` into the 3 low bytes.
+        assert_eq!(partial_range(0x1001, 4, false), (0x1001, 3));
+        assert_eq!(merge_partial(old, 0xccbbaa, 3, 4, false), 0x55cc_bbaa);
+        // `ldl.d` at offset 7 and `ldr.d` at offset 0 load the whole block.
+        assert_eq!(partial_range(0x1007, 8, true), (0x1000, 8));
+        assert_eq!(partial_range(0x1000, 8, false), (0x1000, 8));
+        assert_eq!(merge_partial(old, 0xaa, 8, 8, true), 0xaa);
+        // `ldr.d` at offset 7 loads a single byte.
+        assert_eq!(partial_range(0x1007, 8, false), (0x1007, 1));
+        assert_eq!(merge_partial(old, 0xaa, 1, 8, false), 0x1122_3344_5566_77aa);
+    }
+
+    #[test]
+    fn partial_stores() {
+        let val = 0x1122_3344_5566_7788;
+        // `stl.w` at offset 1 stores the 2 high bytes of the word.
+        assert_eq!(split_partial(val, 2, 4, true), 0x5566);
+        // `str.w` at offset 1 stores the 3 low bytes.
+        assert_eq!(split_partial(val, 3, 4, false), 0x66_7788);
+        assert_eq!(split_partial(val, 1, 8, true), 0x11);
+        assert_eq!(split_partial(val, 8, 8, false), val);
+    }
+}
@@ -1,4 +1,10 @@
 use core::arch::naked_asm;
+#[cfg(feature = "vector")]
+use core::{
+    arch::asm,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use memory_addr::VirtAddr;
 use riscv::register::sstatus::{self, FS};
 
@@ -107,8 +113,185 @@ impl FpState {
     }
 }
 
-/// Saved registers when a trap (interrupt or exception) occurs.
+/// The maximum length in bytes of vector registers (`VLENB`, i.e., 1024 bits)
+/// that [`VectorState`] can hold. The V extension is not enabled on CPUs with
+/// longer registers.
+///
+/// The capacity is fixed on purpose rather than sized by the probed `VLENB`,
+/// as [`VectorState`] is embedded in the task context and this crate does not
+/// allocate. It costs 4 KiB per task regardless of the actual length.
+#[cfg(feature = "vector")]
+pub const VLENB_MAX: usize = 128;
+
+/// The length in bytes of vector registers of the CPU, or 0 if the V extension
+/// is not supported.
+#[cfg(feature = "vector")]
+static VLENB: AtomicUsize = AtomicUsize::new(0);
+
+/// The position of the `VS` field in `sstatus`.
+#[cfg(feature = "vector")]
+const SSTATUS_VS_SHIFT: usize = 9;
+
+/// The initial value of `vtype`, where `vill` is set.
+#[cfg(feature = "vector")]
+const VTYPE_VILL: usize = 1 << (usize::BITS - 1);
+
+/// Vector registers of RISC-V (the V extension).
+///
+/// It holds vector registers up to [`VLENB_MAX`] bytes long, and only the first
+/// [`VectorState::vlenb`] bytes of each register are used.
+#[cfg(feature = "vector")]
 #[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VectorState {
+    /// the vector registers `v0`-`v31`, each of [`VectorState::vlenb`] bytes
+    pub v: [u8; 32 * VLENB_MAX],
+    /// the vector start index register
+    pub vstart: usize,
+    /// the vector length register
+    pub vl: usize,
+    /// the vector data type register
+    pub vtype: usize,
+    /// the vector control and status register
+    pub vcsr: usize,
+    /// the vector status (dirty, clean, off), with the same encoding as `FS`
+    pub vs: FS,
+}
+
+#[cfg(feature = "vector")]
+impl Default for VectorState {
+    fn default() -> Self {
+        Self {
+            vs: FS::Initial,
+            v: [0; 32 * VLENB_MAX],
+            vstart: 0,
+            vl: 0,
+            vtype: VTYPE_VILL,
+            vcsr: 0,
+        }
+    }
+}
+
+#[cfg(feature = "vector")]
+impl fmt::Debug for VectorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VectorState")
+            .field("vstart", &self.vstart)
+            .field("vl", &self.vl)
+            .field("vtype", &self.vtype)
+            .field("vcsr", &self.vcsr)
+            .field("vs", &self.vs)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "vector")]
+impl VectorState {
+    /// Returns the length in bytes of vector registers, or 0 if the V
+    /// extension is not supported or not enabled.
+    #[inline]
+    pub fn vlenb() -> usize {
+        VLENB.load(Ordering::Relaxed)
+    }
+
+    /// Discovers the vector register length of the current CPU.
+    ///
+    /// `sstatus.VS` is read-only zero if the V extension is not supported. The
+    /// V extension is left disabled (i.e., [`vlenb`](Self::vlenb) returns 0) if
+    /// the registers are longer than [`VLENB_MAX`], i.e., `VLEN` is over 1024
+    /// bits, so vector instructions always trap on such CPUs.
+    pub(crate) fn init() {
+        unsafe { set_vs(FS::Initial) };
+        if read_vs() != FS::Off {
+            let vlenb: usize;
+            unsafe {
+                asm!(".option push", ".option arch, +v", "csrr {}, vlenb", ".option pop", out(reg) vlenb)
+            };
+            if vlenb <= VLENB_MAX {
+                VLENB.store(vlenb, Ordering::Relaxed);
+            } else {
+                warn!("V extension disabled: VLENB {vlenb} exceeds {VLENB_MAX}");
+            }
+        }
+        unsafe { set_vs(FS::Off) };
+    }
+
+    /// Restores the vector registers from this vector state
+    #[inline]
+    pub fn restore(&self) {
+        unsafe { restore_vector_registers(self, 8 * Self::vlenb()) }
+    }
+
+    /// Saves the current vector registers to this vector state
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe { save_vector_registers(self, 8 * Self::vlenb()) }
+    }
+
+    /// Clears all vector registers to zero, and resets `vtype` with `vill`
+    /// set
+    #[inline]
+    pub fn clear() {
+        unsafe { clear_vector_registers() }
+    }
+
+    /// Handles vector state context switching
+    ///
+    /// Saves the current task's vector state (if needed) and restores the next
+    /// task's vector state, in the same way as [`FpState::switch_to`].
+    pub fn switch_to(&mut self, next_vector_state: &VectorState) {
+        if Self::vlenb() == 0 {
+            return;
+        }
+        if read_vs() == FS::Dirty {
+            self.save();
+            self.vs = FS::Clean;
+        }
+        // Vector instructions are illegal if `sstatus.VS` is off.
+        unsafe { set_vs(FS::Clean) };
+        match next_vector_state.vs {
+            FS::Clean => next_vector_state.restore(),
+            FS::Initial => VectorState::clear(),
+            FS::Off => {}
+            FS::Dirty => unreachable!("vector state of the next task should not be dirty"),
+        }
+        unsafe { set_vs(next_vector_state.vs) };
+    }
+}
+
+/// Reads `sstatus.VS`, the status of the vector extension.
+#[cfg(feature = "vector")]
+pub(crate) fn read_vs() -> FS {
+    FS::from_usize((sstatus::read().bits() >> SSTATUS_VS_SHIFT) & 0b11).unwrap()
+}
+
+/// Writes `sstatus.VS`, the status of the vector extension.
+#[cfg(feature = "vector")]
+pub(crate) unsafe fn set_vs(vs: FS) {
+    unsafe {
+        asm!("csrc sstatus, {}", in(reg) 0b11 << SSTATUS_VS_SHIFT);
+        asm!("csrs sstatus, {}", in(reg) (vs as usize) << SSTATUS_VS_SHIFT);
+    }
+}
+
+/// Returns `sstatus` with the `VS` field replaced.
+#[cfg(feature = "vector")]
+pub(crate) fn with_vs(sstatus: sstatus::Sstatus, vs: FS) -> sstatus::Sstatus {
+    let bits = sstatus.bits() & !(0b11 << SSTATUS_VS_SHIFT);
+    sstatus::Sstatus::from_bits(bits | (vs as usize) << SSTATUS_VS_SHIFT)
+}
+
+/// Raw information about the cause of a trap.
+#[derive(Debug, Clone, Copy)]
+pub struct RawTrapCause {
+    /// Supervisor Cause Register.
+    pub scause: usize,
+    /// Supervisor Trap Value Register.
+    pub stval: usize,
+}
+
+/// Saved registers when a trap (interrupt or exception) occurs.
+#[repr(C, align(16))]
 #[derive(Debug, Clone, Copy)]
 pub struct TrapFrame {
     /// All general registers.
@@ -117,6 +300,9 @@ pub struct TrapFrame {
     pub sepc: usize,
     /// Supervisor Status Register.
     pub sstatus: sstatus::Sstatus,
+    /// The original `a0` of a syscall, which is overwritten by the return
+    /// value.
+    pub orig_arg0: usize,
 }
 
 impl Default for TrapFrame {
@@ -125,6 +311,7 @@ impl Default for TrapFrame {
             regs: GeneralRegisters::default(),
             sepc: 0,
             sstatus: sstatus::Sstatus::from_bits(0),
+            orig_arg0: 0,
         }
     }
 }
@@ -230,6 +417,16 @@ impl TrapFrame {
         self.regs.a0 = a0;
     }
 
+    /// Rewinds the syscall that has just returned, so that it is executed
+    /// again when returning to user space.
+    ///
+    /// It moves the PC back to the `ecall` instruction and restores `a0` from
+    /// [`orig_arg0`](Self::orig_arg0).
+    pub const fn rewind_syscall(&mut self) {
+        self.sepc -= 4;
+        self.regs.a0 = self.orig_arg0;
+    }
+
     /// Sets the return address.
     pub const fn set_ra(&mut self, ra: usize) {
         self.regs.ra = ra;
@@ -289,6 +486,12 @@ pub struct TaskContext {
     pub satp: memory_addr::PhysAddr,
     #[cfg(feature = "fp-simd")]
     pub fp_state: FpState,
+    /// Vector registers, which are only switched on CPUs with the V extension.
+    #[cfg(feature = "vector")]
+    pub vector_state: VectorState,
+    /// Hardware breakpoints and watchpoints.
+    #[cfg(feature = "hw-breakpoint")]
+    pub debug_state: crate::debug::DebugState,
 }
 
 impl TaskContext {
@@ -343,6 +546,15 @@ impl TaskContext {
         {
             self.fp_state.switch_to(&next_ctx.fp_state);
         }
+        #[cfg(feature = "vector")]
+        {
+            self.vector_state.switch_to(&next_ctx.vector_state);
+        }
+        #[cfg(feature = "hw-breakpoint")]
+        {
+            self.debug_state.save();
+            next_ctx.debug_state.restore();
+        }
 
         unsafe { context_switch(self, next_ctx) }
     }
@@ -385,6 +597,91 @@ unsafe extern "C" fn clear_fp_registers() {
     )
 }
 
+/// Saves `vstart`, `vl`, `vtype`, `vcsr` and then `v0`-`v31` by groups of 8
+/// registers, each of `group_size` bytes.
+#[cfg(feature = "vector")]
+unsafe fn save_vector_registers(state: &mut VectorState, group_size: usize) {
+    unsafe {
+        asm!(
+            ".option push",
+            ".option arch, +v",
+            "csrr {vstart}, vstart",
+            "csrr {vl}, vl",
+            "csrr {vtype}, vtype",
+            "csrr {vcsr}, vcsr",
+            // Whole register stores start from `vstart`.
+            "csrw vstart, zero",
+            "vs8r.v v0, ({p})",
+            "add {p}, {p}, {size}",
+            "vs8r.v v8, ({p})",
+            "add {p}, {p}, {size}",
+            "vs8r.v v16, ({p})",
+            "add {p}, {p}, {size}",
+            "vs8r.v v24, ({p})",
+            ".option pop",
+            vstart = out(reg) state.vstart,
+            vl = out(reg) state.vl,
+            vtype = out(reg) state.vtype,
+            vcsr = out(reg) state.vcsr,
+            p = inout(reg) state.v.as_mut_ptr() => _,
+            size = in(reg) group_size,
+        )
+    }
+}
+
+/// Restores `v0`-`v31` by groups of 8 registers, each of `group_size` bytes,
+/// and then `vl`, `vtype`, `vcsr` and `vstart`.
+#[cfg(feature = "vector")]
+unsafe fn restore_vector_registers(state: &VectorState, group_size: usize) {
+    unsafe {
+        asm!(
+            ".option push",
+            ".option arch, +v",
+            "csrw vstart, zero",
+            "vl8r.v v0, ({p})",
+            "add {p}, {p}, {size}",
+            "vl8r.v v8, ({p})",
+            "add {p}, {p}, {size}",
+            "vl8r.v v16, ({p})",
+            "add {p}, {p}, {size}",
+            "vl8r.v v24, ({p})",
+            "vsetvl zero, {vl}, {vtype}",
+            "csrw vcsr, {vcsr}",
+            "csrw vstart, {vstart}",
+            ".option pop",
+            p = inout(reg) state.v.as_ptr() => _,
+            size = in(reg) group_size,
+            vl = in(reg) state.vl,
+            vtype = in(reg) state.vtype,
+            vcsr = in(reg) state.vcsr,
+            vstart = in(reg) state.vstart,
+        )
+    }
+}
+
+/// Clears `v0`-`v31` to zero, and resets `vl`, `vtype`, `vcsr` and `vstart`.
+#[cfg(feature = "vector")]
+unsafe fn clear_vector_registers() {
+    unsafe {
+        asm!(
+            ".option push",
+            ".option arch, +v",
+            "vsetvli {tmp}, zero, e8, m8, ta, ma",
+            "vmv.v.i v0, 0",
+            "vmv.v.i v8, 0",
+            "vmv.v.i v16, 0",
+            "vmv.v.i v24, 0",
+            "vsetvl zero, {zero}, {vtype}",
+            "csrw vcsr, zero",
+            "csrw vstart, zero",
+            ".option pop",
+            tmp = out(reg) _,
+            zero = in(reg) 0,
+            vtype = in(reg) VTYPE_VILL,
+        )
+    }
+}
+
 #[unsafe(naked)]
 unsafe extern "C" fn context_switch(_current_task: &mut TaskContext, _next_task: &TaskContext) {
     naked_asm!(
@@ -0,0 +1,23 @@
+//! Register notes of ELF core dumps.
+
+pub use crate::coredump::riscv::{ElfGregset, ELF_FPREGSET_SIZE, ELF_NGREG};
+
+use super::{uspace::UserContext, FpState};
+use crate::coredump::riscv as layout;
+
+impl UserContext {
+    /// Converts the registers to `elf_gregset_t`: `pc` followed by `x1`-`x31`.
+    pub fn to_elf_gregset(&self) -> ElfGregset {
+        // `GeneralRegisters` has the same layout as `[usize; 32]`.
+        let regs: [usize; 32] = unsafe { core::mem::transmute(self.regs) };
+        layout::gregset(&regs, self.sepc)
+    }
+}
+
+impl FpState {
+    /// Converts the floating-point registers to the `NT_PRFPREG` note:
+    /// `f0`-`f31` and `fcsr`, padded to 8 bytes.
+    pub fn to_elf_fpregset(&self) -> [u8; ELF_FPREGSET_SIZE] {
+        layout::fpregset(&self.fp, self.fcsr as u32)
+    }
+}
@@ -0,0 +1,203 @@
+//! Sdtrig triggers (`tselect`, `tdata1` and `tdata2`).
+//!
+//! Triggers are configured as address match triggers (`mcontrol`, or
+//! `mcontrol6` if the former is not supported) that raise breakpoint
+//! exceptions in U-mode only.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::debug::{check_range, DebugError, WatchKind};
+
+/// The maximum number of triggers.
+const MAX_TRIGGERS: usize = 8;
+
+const TDATA1_TYPE_SHIFT: usize = usize::BITS as usize - 4;
+const TYPE_MCONTROL: usize = 2;
+const TYPE_MCONTROL6: usize = 6;
+
+const MCONTROL_LOAD: usize = 1 << 0;
+const MCONTROL_STORE: usize = 1 << 1;
+const MCONTROL_EXECUTE: usize = 1 << 2;
+const MCONTROL_U: usize = 1 << 3;
+const MCONTROL_MATCH_NAPOT: usize = 1 << 7;
+const MCONTROL_HIT: usize = 1 << 20;
+const MCONTROL6_HIT0: usize = 1 << 22;
+
+fn select(idx: usize) {
+    unsafe { asm!("csrw tselect, {}", in(reg) idx) };
+}
+
+fn read_tdata1() -> usize {
+    let val;
+    unsafe { asm!("csrr {}, tdata1", out(reg) val) };
+    val
+}
+
+fn write_tdata1(val: usize) {
+    unsafe { asm!("csrw tdata1, {}", in(reg) val) };
+}
+
+fn read_tdata2() -> usize {
+    let val;
+    unsafe { asm!("csrr {}, tdata2", out(reg) val) };
+    val
+}
+
+fn write_tdata2(val: usize) {
+    unsafe { asm!("csrw tdata2, {}", in(reg) val) };
+}
+
+/// Returns the number of triggers, probed by selecting each of them.
+fn num_triggers() -> usize {
+    static NUM_TRIGGERS: AtomicUsize = AtomicUsize::new(usize::MAX);
+    let num = NUM_TRIGGERS.load(Ordering::Relaxed);
+    if num != usize::MAX {
+        return num;
+    }
+    let mut num = 0;
+    while num < MAX_TRIGGERS {
+        select(num);
+        let selected: usize;
+        unsafe { asm!("csrr {}, tselect", out(reg) selected) };
+        // Type 0 means there is no trigger at this index.
+        if selected != num || read_tdata1() >> TDATA1_TYPE_SHIFT == 0 {
+            break;
+        }
+        num += 1;
+    }
+    NUM_TRIGGERS.store(num, Ordering::Relaxed);
+    num
+}
+
+/// Programs the trigger `idx` with the given match conditions.
+fn set_trigger(idx: usize, tdata2: usize, ctrl: usize) -> Result<(), DebugError> {
+    if idx >= num_triggers() {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    select(idx);
+    write_tdata1(0);
+    write_tdata2(tdata2);
+    for ty in [TYPE_MCONTROL, TYPE_MCONTROL6] {
+        write_tdata1(ty << TDATA1_TYPE_SHIFT | ctrl | MCONTROL_U);
+        if read_tdata1() >> TDATA1_TYPE_SHIFT == ty {
+            return Ok(());
+        }
+    }
+    // Neither type of address match trigger is supported.
+    write_tdata1(0);
+    Err(DebugError::InvalidIndex(idx))
+}
+
+fn clear_trigger(idx: usize) {
+    if idx < num_triggers() {
+        select(idx);
+        write_tdata1(0);
+    }
+}
+
+/// Returns the number of hardware breakpoints.
+pub fn num_breakpoints() -> usize {
+    num_triggers()
+}
+
+/// Returns the number of hardware watchpoints.
+pub fn num_watchpoints() -> usize {
+    num_triggers()
+}
+
+/// Sets the hardware breakpoint `idx` on the instruction at `addr`.
+pub fn set_breakpoint(idx: usize, addr: usize) -> Result<(), DebugError> {
+    set_trigger(idx, addr, MCONTROL_EXECUTE)
+}
+
+/// Clears the hardware breakpoint `idx`.
+pub fn clear_breakpoint(idx: usize) {
+    clear_trigger(idx)
+}
+
+/// Sets the hardware watchpoint `idx` on `len` bytes at `addr`.
+pub fn set_watchpoint(
+    idx: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), DebugError> {
+    check_range(addr, len)?;
+    let access = match kind {
+        WatchKind::Read => MCONTROL_LOAD,
+        WatchKind::Write => MCONTROL_STORE,
+        WatchKind::ReadWrite => MCONTROL_LOAD | MCONTROL_STORE,
+    };
+    if len == 1 {
+        set_trigger(idx, addr, access)
+    } else {
+        // Naturally aligned power-of-two range.
+        set_trigger(idx, addr | (len - 1) >> 1, access | MCONTROL_MATCH_NAPOT)
+    }
+}
+
+/// Clears the hardware watchpoint `idx`.
+pub fn clear_watchpoint(idx: usize) {
+    clear_trigger(idx)
+}
+
+/// Returns whether a breakpoint exception on `addr` (from `stval`) is raised
+/// by a trigger, and clears its hit bits.
+pub(crate) fn take_hit(addr: usize) -> bool {
+    for idx in 0..num_triggers() {
+        select(idx);
+        let tdata1 = read_tdata1();
+        if tdata1 & MCONTROL_U == 0 {
+            continue;
+        }
+        let tdata2 = read_tdata2();
+        let matched = if tdata1 & MCONTROL_MATCH_NAPOT != 0 {
+            let mask = tdata2 ^ (tdata2 + 1);
+            addr & !mask == tdata2 & !mask
+        } else {
+            addr == tdata2
+        };
+        let hit_bits = match tdata1 >> TDATA1_TYPE_SHIFT {
+            TYPE_MCONTROL => MCONTROL_HIT,
+            _ => MCONTROL6_HIT0,
+        };
+        if matched || tdata1 & hit_bits != 0 {
+            write_tdata1(tdata1 & !hit_bits);
+            return true;
+        }
+    }
+    false
+}
+
+/// Per-task states of the triggers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugState {
+    /// Values of `tdata1` of each trigger.
+    pub tdata1: [usize; MAX_TRIGGERS],
+    /// Values of `tdata2` of each trigger.
+    pub tdata2: [usize; MAX_TRIGGERS],
+}
+
+impl DebugState {
+    /// Saves the triggers of the current CPU to this structure.
+    pub fn save(&mut self) {
+        for i in 0..num_triggers() {
+            select(i);
+            self.tdata1[i] = read_tdata1();
+            self.tdata2[i] = read_tdata2();
+        }
+    }
+
+    /// Restores the triggers of the current CPU from this structure.
+    pub fn restore(&self) {
+        for i in 0..num_triggers() {
+            select(i);
+            write_tdata1(0);
+            write_tdata2(self.tdata2[i]);
+            write_tdata1(self.tdata1[i]);
+        }
+    }
+}
@@ -2,10 +2,15 @@
 
 /// Initializes trap handling on the current CPU.
 ///
-/// In detail, it initializes the trap vector on RISC-V platforms.
+/// In detail, it initializes the trap vector on RISC-V platforms. With the
+/// `vector` feature, it also discovers the vector register length if the V
+/// extension is supported, and leaves V disabled if the length exceeds
+/// [`VLENB_MAX`](super::VLENB_MAX).
 pub fn init_trap() {
     #[cfg(feature = "uspace")]
     crate::uspace_common::init_exception_table();
+    #[cfg(feature = "vector")]
+    super::VectorState::init();
     unsafe extern "C" {
         fn trap_vector_base();
     }
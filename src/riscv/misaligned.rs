@@ -0,0 +1,222 @@
+//! Emulation of misaligned memory accesses in user space.
+
+#[cfg(feature = "fp-simd")]
+use riscv::register::sstatus::{self, FS};
+
+use super::uspace::UserContext;
+#[cfg(feature = "fp-simd")]
+use super::FpState;
+use crate::{
+    uspace_common::{read_user, read_user_int, write_user_int, MisalignedError},
+    GeneralRegisters,
+};
+
+const OP_LOAD: u32 = 0x03;
+const OP_LOAD_FP: u32 = 0x07;
+const OP_STORE: u32 = 0x23;
+const OP_STORE_FP: u32 = 0x27;
+
+/// The stack pointer (`x2`), the implicit base of `C.*SP` instructions.
+const REG_SP: usize = 2;
+
+/// The kind of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Load { signed: bool },
+    Store,
+    FpLoad,
+    FpStore,
+}
+
+/// A decoded load or store instruction.
+#[derive(Debug, Clone, Copy)]
+struct MemOp {
+    access: Access,
+    /// The access size in bytes.
+    size: usize,
+    /// The data register (`rd` for loads and `rs2` for stores).
+    reg: usize,
+    /// The base address register (`rs1`).
+    base: usize,
+    /// The offset added to the base address.
+    offset: isize,
+}
+
+impl MemOp {
+    /// Decodes a 32-bit load or store instruction.
+    fn decode(inst: u32) -> Option<Self> {
+        let funct3 = (inst >> 12) & 0x7;
+        let rd = ((inst >> 7) & 0x1f) as usize;
+        let rs1 = ((inst >> 15) & 0x1f) as usize;
+        let rs2 = ((inst >> 20) & 0x1f) as usize;
+        let load_imm = (inst as i32 >> 20) as isize;
+        let store_imm = ((inst as i32 >> 25 << 5) | ((inst >> 7) & 0x1f) as i32) as isize;
+
+        let (access, size) = match (inst & 0x7f, funct3) {
+            (OP_LOAD, 1) => (Access::Load { signed: true }, 2),
+            (OP_LOAD, 2) => (Access::Load { signed: true }, 4),
+            #[cfg(target_arch = "riscv64")]
+            (OP_LOAD, 3) => (Access::Load { signed: true }, 8),
+            (OP_LOAD, 5) => (Access::Load { signed: false }, 2),
+            #[cfg(target_arch = "riscv64")]
+            (OP_LOAD, 6) => (Access::Load { signed: false }, 4),
+            (OP_STORE, 1) => (Access::Store, 2),
+            (OP_STORE, 2) => (Access::Store, 4),
+            #[cfg(target_arch = "riscv64")]
+            (OP_STORE, 3) => (Access::Store, 8),
+            (OP_LOAD_FP, 2) => (Access::FpLoad, 4),
+            (OP_LOAD_FP, 3) => (Access::FpLoad, 8),
+            (OP_STORE_FP, 2) => (Access::FpStore, 4),
+            (OP_STORE_FP, 3) => (Access::FpStore, 8),
+            _ => return None,
+        };
+        let (reg, offset) = match access {
+            Access::Load { .. } | Access::FpLoad => (rd, load_imm),
+            Access::Store | Access::FpStore => (rs2, store_imm),
+        };
+        Some(Self {
+            access,
+            size,
+            reg,
+            base: rs1,
+            offset,
+        })
+    }
+
+    /// Decodes a 16-bit compressed load or store instruction.
+    fn decode_compressed(inst: u16) -> Option<Self> {
+        let inst = inst as u32;
+        let bits = |hi: u32, lo: u32| (inst >> lo) & ((1 << (hi - lo + 1)) - 1);
+        // `rd'`/`rs2'` and `rs1'` of the CL and CS formats.
+        let rd_c = bits(4, 2) as usize + 8;
+        let rs1_c = bits(9, 7) as usize + 8;
+        // `rd` of the CI format, and `rs2` of the CSS format.
+        let rd = bits(11, 7) as usize;
+        let rs2 = bits(6, 2) as usize;
+        // Word and doubleword offsets of the CL/CS, CI and CSS formats.
+        let off_w = bits(12, 10) << 3 | bits(6, 6) << 2 | bits(5, 5) << 6;
+        let off_d = bits(12, 10) << 3 | bits(6, 5) << 6;
+        let off_wsp = bits(12, 12) << 5 | bits(6, 4) << 2 | bits(3, 2) << 6;
+        let off_dsp = bits(12, 12) << 5 | bits(6, 5) << 3 | bits(4, 2) << 6;
+        let off_swsp = bits(12, 9) << 2 | bits(8, 7) << 6;
+        let off_sdsp = bits(12, 10) << 3 | bits(9, 7) << 6;
+
+        let (access, size, reg, base, offset) = match (inst & 0x3, bits(15, 13)) {
+            (0b00, 0b001) => (Access::FpLoad, 8, rd_c, rs1_c, off_d), // C.FLD
+            (0b00, 0b010) => (Access::Load { signed: true }, 4, rd_c, rs1_c, off_w), // C.LW
+            #[cfg(target_arch = "riscv64")]
+            (0b00, 0b011) => (Access::Load { signed: true }, 8, rd_c, rs1_c, off_d), // C.LD
+            #[cfg(target_arch = "riscv32")]
+            (0b00, 0b011) => (Access::FpLoad, 4, rd_c, rs1_c, off_w), // C.FLW
+            (0b00, 0b101) => (Access::FpStore, 8, rd_c, rs1_c, off_d), // C.FSD
+            (0b00, 0b110) => (Access::Store, 4, rd_c, rs1_c, off_w),  // C.SW
+            #[cfg(target_arch = "riscv64")]
+            (0b00, 0b111) => (Access::Store, 8, rd_c, rs1_c, off_d), // C.SD
+            #[cfg(target_arch = "riscv32")]
+            (0b00, 0b111) => (Access::FpStore, 4, rd_c, rs1_c, off_w), // C.FSW
+            (0b10, 0b001) => (Access::FpLoad, 8, rd, REG_SP, off_dsp), // C.FLDSP
+            (0b10, 0b010) if rd != 0 => {
+                (Access::Load { signed: true }, 4, rd, REG_SP, off_wsp) // C.LWSP
+            }
+            #[cfg(target_arch = "riscv64")]
+            (0b10, 0b011) if rd != 0 => {
+                (Access::Load { signed: true }, 8, rd, REG_SP, off_dsp) // C.LDSP
+            }
+            #[cfg(target_arch = "riscv32")]
+            (0b10, 0b011) => (Access::FpLoad, 4, rd, REG_SP, off_wsp), // C.FLWSP
+            (0b10, 0b101) => (Access::FpStore, 8, rs2, REG_SP, off_sdsp), // C.FSDSP
+            (0b10, 0b110) => (Access::Store, 4, rs2, REG_SP, off_swsp),   // C.SWSP
+            #[cfg(target_arch = "riscv64")]
+            (0b10, 0b111) => (Access::Store, 8, rs2, REG_SP, off_sdsp), // C.SDSP
+            #[cfg(target_arch = "riscv32")]
+            (0b10, 0b111) => (Access::FpStore, 4, rs2, REG_SP, off_swsp), // C.FSWSP
+            _ => return None,
+        };
+        Some(Self {
+            access,
+            size,
+            reg,
+            base,
+            offset: offset as isize,
+        })
+    }
+}
+
+/// Fetches the user instruction at `pc`, and returns it with its length.
+///
+/// The instruction is fetched in 16-bit parcels, as a compressed instruction
+/// may be at the end of the last mapped page.
+fn fetch_inst(pc: usize) -> Result<(u32, usize), MisalignedError> {
+    let lo: u16 = unsafe { read_user(pc)? };
+    if lo & 0x3 != 0x3 {
+        return Ok((lo as u32, 2));
+    }
+    let hi: u16 = unsafe { read_user(pc + 2)? };
+    Ok(((hi as u32) << 16 | lo as u32, 4))
+}
+
+impl UserContext {
+    /// Emulates the misaligned load or store at `sepc`, which causes a
+    /// [`ExceptionKind::Misaligned`](super::uspace::ExceptionKind::Misaligned)
+    /// exception.
+    ///
+    /// The instruction is fetched from user memory and decoded, and the data
+    /// is accessed with [`user_copy`](crate::asm::user_copy). On success, the
+    /// destination register is updated and `sepc` is advanced to the next
+    /// instruction. Floating-point loads and stores are only
+    /// supported with the `fp-simd` feature.
+    pub fn emulate_misaligned(&mut self) -> Result<(), MisalignedError> {
+        let (inst, len) = fetch_inst(self.sepc)?;
+        let op = if len == 2 {
+            MemOp::decode_compressed(inst as u16)
+        } else {
+            MemOp::decode(inst)
+        }
+        .ok_or(MisalignedError::UnsupportedInstruction(inst))?;
+
+        // `GeneralRegisters` has the same layout as `[usize; 32]`.
+        let regs = unsafe {
+            core::mem::transmute::<&mut GeneralRegisters, &mut [usize; 32]>(&mut self.regs)
+        };
+        // `x0` holds the kernel stack pointer while in user space.
+        let read_reg = |regs: &[usize; 32], i: usize| if i == 0 { 0 } else { regs[i] };
+        let addr = read_reg(regs, op.base).wrapping_add_signed(op.offset);
+
+        match op.access {
+            Access::Load { signed } => {
+                let val = read_user_int(addr, op.size, signed)?;
+                if op.reg != 0 {
+                    regs[op.reg] = val as usize;
+                }
+            }
+            Access::Store => write_user_int(addr, read_reg(regs, op.reg) as u64, op.size)?,
+            #[cfg(feature = "fp-simd")]
+            Access::FpLoad => {
+                let val = read_user_int(addr, op.size, false)?;
+                let mut fp_state = FpState::default();
+                fp_state.save();
+                // Single-precision values are NaN-boxed.
+                fp_state.fp[op.reg] = if op.size == 4 {
+                    val | 0xffff_ffff_0000_0000
+                } else {
+                    val
+                };
+                unsafe { sstatus::set_fs(FS::Dirty) };
+                fp_state.restore();
+                self.sstatus.set_fs(FS::Dirty);
+            }
+            #[cfg(feature = "fp-simd")]
+            Access::FpStore => {
+                let mut fp_state = FpState::default();
+                fp_state.save();
+                write_user_int(addr, fp_state.fp[op.reg], op.size)?;
+            }
+            #[cfg(not(feature = "fp-simd"))]
+            Access::FpLoad | Access::FpStore => {
+                return Err(MisalignedError::UnsupportedInstruction(inst));
+            }
+        }
+        self.sepc += len;
+        Ok(())
+    }
+}
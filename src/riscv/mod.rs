@@ -4,10 +4,23 @@ mod macros;
 mod context;
 mod trap;
 
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) mod debug;
+
 pub mod asm;
 pub mod init;
 
+#[cfg(feature = "uspace")]
+mod coredump;
+#[cfg(feature = "uspace")]
+mod misaligned;
+#[cfg(feature = "uspace")]
+mod signal;
+#[cfg(feature = "uspace")]
+mod single_step;
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
-pub use self::context::{FpState, GeneralRegisters, TaskContext, TrapFrame};
+pub use self::context::{FpState, GeneralRegisters, RawTrapCause, TaskContext, TrapFrame};
+#[cfg(feature = "vector")]
+pub use self::context::{VectorState, VLENB_MAX};
@@ -0,0 +1,174 @@
+//! Signal frames compatible with the Linux ABI.
+
+use core::mem::{offset_of, size_of};
+
+#[cfg(feature = "fp-simd")]
+use riscv::register::sstatus::{self, FS};
+
+use super::uspace::UserContext;
+#[cfg(feature = "fp-simd")]
+use super::FpState;
+use crate::{
+    uspace_common::{
+        read_user, write_user, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+        SIGINFO_SIZE,
+    },
+    GeneralRegisters,
+};
+
+/// `struct __riscv_d_ext_state` in Linux, padded to the size of
+/// `union __riscv_fp_state`.
+///
+/// The trailing padding also holds the header of the extra context records,
+/// which is left zeroed to terminate the list.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct FpContext {
+    f: [u64; 32],
+    fcsr: u32,
+    padding: [u8; 528 - 32 * size_of::<u64>() - size_of::<u32>()],
+}
+
+/// `struct sigcontext` in Linux.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    /// General registers, with `pc` in place of `zero`.
+    regs: [usize; 32],
+    fpregs: FpContext,
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    uc_flags: usize,
+    uc_link: usize,
+    uc_stack: SignalStack,
+    /// `sigset_t`, split into two words as it is only 4-byte aligned on RV32.
+    uc_sigmask: [u32; 2],
+    unused: [u8; 128 - size_of::<u64>()],
+    uc_mcontext: SigContext,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+struct RtSigFrame {
+    info: [u8; SIGINFO_SIZE],
+    uc: UContext,
+}
+
+#[cfg(target_arch = "riscv64")]
+static_assertions::const_assert_eq!(size_of::<SigContext>(), 784);
+#[cfg(target_arch = "riscv64")]
+static_assertions::const_assert_eq!(offset_of!(UContext, uc_mcontext), 176);
+#[cfg(target_arch = "riscv64")]
+static_assertions::const_assert_eq!(size_of::<RtSigFrame>(), 1088);
+static_assertions::const_assert_eq!(size_of::<GeneralRegisters>(), size_of::<[usize; 32]>());
+
+impl FpContext {
+    const fn zeroed() -> Self {
+        Self {
+            f: [0; 32],
+            fcsr: 0,
+            padding: [0; _],
+        }
+    }
+
+    /// Saves the current FP states, or returns zeros if the FPU is off.
+    #[cfg(feature = "fp-simd")]
+    fn save() -> Self {
+        let mut ctx = Self::zeroed();
+        if sstatus::read().fs() != FS::Off {
+            let mut fp_state = FpState::default();
+            fp_state.save();
+            ctx.f = fp_state.fp;
+            ctx.fcsr = fp_state.fcsr as _;
+        }
+        ctx
+    }
+
+    #[cfg(not(feature = "fp-simd"))]
+    fn save() -> Self {
+        Self::zeroed()
+    }
+}
+
+impl UserContext {
+    /// Pushes a signal frame onto the user stack, and redirects the execution
+    /// to the signal handler.
+    ///
+    /// The frame has the same layout as `struct rt_sigframe` in Linux. The
+    /// FP states are saved only with the `fp-simd` feature. The handler is
+    /// called with the signal number, the address of `siginfo_t` and the
+    /// address of `ucontext_t`, and returns to [`SignalFrameParams::restorer`].
+    pub fn push_signal_frame(
+        &mut self,
+        params: &SignalFrameParams<'_>,
+    ) -> Result<(), UserAccessError> {
+        let sp = params.stack_top.unwrap_or(self.regs.sp);
+        let frame_addr = sp.wrapping_sub(size_of::<RtSigFrame>()) & !15;
+
+        // `GeneralRegisters` has the same layout as `regs`.
+        let mut regs: [usize; 32] = unsafe { core::mem::transmute(self.regs) };
+        regs[0] = self.sepc;
+        let frame = RtSigFrame {
+            info: *params.siginfo,
+            uc: UContext {
+                uc_flags: 0,
+                uc_link: 0,
+                uc_stack: params.uc_stack,
+                uc_sigmask: [params.sigmask as u32, (params.sigmask >> 32) as u32],
+                unused: [0; _],
+                uc_mcontext: SigContext {
+                    regs,
+                    fpregs: FpContext::save(),
+                },
+            },
+        };
+        write_user(frame_addr, &frame)?;
+
+        self.regs.a0 = params.signo;
+        self.regs.a1 = frame_addr + offset_of!(RtSigFrame, info);
+        self.regs.a2 = frame_addr + offset_of!(RtSigFrame, uc);
+        self.regs.ra = params.restorer;
+        self.regs.sp = frame_addr;
+        self.sepc = params.handler;
+        Ok(())
+    }
+
+    /// Restores the context saved by [`push_signal_frame`], on the
+    /// `rt_sigreturn` syscall.
+    ///
+    /// `sstatus` is not restored, except that the FS field is set to dirty if
+    /// the FP states are restored.
+    ///
+    /// [`push_signal_frame`]: UserContext::push_signal_frame
+    pub fn restore_signal_frame(&mut self) -> Result<SignalReturn, UserAccessError> {
+        let frame_addr = self.regs.sp;
+        let uc: UContext = unsafe { read_user(frame_addr + offset_of!(RtSigFrame, uc))? };
+        let mc = &uc.uc_mcontext;
+
+        #[cfg(feature = "fp-simd")]
+        {
+            unsafe { sstatus::set_fs(FS::Dirty) };
+            FpState {
+                fp: mc.fpregs.f,
+                fcsr: mc.fpregs.fcsr as _,
+                fs: FS::Dirty,
+            }
+            .restore();
+            self.sstatus.set_fs(FS::Dirty);
+        }
+
+        let mut regs = mc.regs;
+        self.sepc = regs[0];
+        regs[0] = 0;
+        self.regs = unsafe { core::mem::transmute::<[usize; 32], GeneralRegisters>(regs) };
+
+        Ok(SignalReturn {
+            sigmask: uc.uc_sigmask[0] as u64 | ((uc.uc_sigmask[1] as u64) << 32),
+            uc_stack: uc.uc_stack,
+        })
+    }
+}
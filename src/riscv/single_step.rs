@@ -0,0 +1,210 @@
+//! Software single-stepping.
+//!
+//! RISC-V has no single-step facility available to the supervisor, so it is
+//! emulated by decoding the instruction to be executed, and planting `ebreak`
+//! at all the possible addresses of the next instruction. The breakpoints are
+//! written by a [`PokeText`] function supplied by the kernel.
+
+use super::TrapFrame;
+use crate::uspace_common::{read_user, UserAccessError};
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+/// A function that writes `data` to the user text at `addr`, used to plant
+/// and remove single-step breakpoints.
+///
+/// The text is usually not writable in the user mapping, and may be shared with
+/// other address spaces (e.g., by a shared file mapping). So the kernel should
+/// write it even if it is read-only, and break copy-on-write so that the change
+/// is private to the address space, like `access_process_vm` with
+/// `FOLL_FORCE` in Linux. It is always called with IRQs enabled and outside
+/// the trap handling, so it may sleep to handle page faults.
+pub type PokeText = fn(addr: usize, data: &[u8]) -> Result<(), UserAccessError>;
+
+/// A planted breakpoint, with the original instruction it replaces.
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    orig: [u8; 4],
+    len: usize,
+}
+
+impl Breakpoint {
+    /// Plants a breakpoint at `addr`, using `c.ebreak` if the instruction
+    /// there is compressed.
+    ///
+    /// Returns `Ok(None)` if the instruction cannot be read. Fetching it then
+    /// faults in user space, which is reported instead of the step.
+    fn plant(addr: usize, poke: PokeText) -> Result<Option<Self>, UserAccessError> {
+        let Ok(orig) = (unsafe { read_user::<[u8; 2]>(addr) }) else {
+            return Ok(None);
+        };
+        let bp = if orig[0] & 0b11 != 0b11 {
+            poke(addr, &C_EBREAK.to_le_bytes())?;
+            Self {
+                addr,
+                orig: [orig[0], orig[1], 0, 0],
+                len: 2,
+            }
+        } else {
+            let Ok(orig) = (unsafe { read_user::<[u8; 4]>(addr) }) else {
+                return Ok(None);
+            };
+            poke(addr, &EBREAK.to_le_bytes())?;
+            Self { addr, orig, len: 4 }
+        };
+        Ok(Some(bp))
+    }
+
+    fn remove(&self, poke: PokeText) {
+        if poke(self.addr, &self.orig[..self.len]).is_err() {
+            warn!(
+                "failed to remove the single-step breakpoint at {:#x}",
+                self.addr
+            );
+        }
+    }
+}
+
+/// Breakpoints planted for a single step.
+pub(super) struct SingleStep {
+    bps: [Option<Breakpoint>; 2],
+    poke: PokeText,
+}
+
+impl SingleStep {
+    /// Plants breakpoints at the possible addresses of the instruction after
+    /// the one at `tf.sepc`.
+    ///
+    /// The caller must execute `fence.i` on the CPU that runs the user code.
+    pub(super) fn plant(tf: &TrapFrame, poke: PokeText) -> Result<Self, UserAccessError> {
+        let mut step = Self {
+            bps: [None; 2],
+            poke,
+        };
+        let Some(inst) = read_inst(tf.sepc) else {
+            return Ok(step);
+        };
+        // `GeneralRegisters` has the same layout as `x0..x31`, while `zero` may
+        // hold other values.
+        let mut regs: [usize; 32] = unsafe { core::mem::transmute(tf.regs) };
+        regs[0] = 0;
+
+        let targets = next_pcs(&regs, tf.sepc, inst);
+        for (i, &target) in targets.iter().enumerate() {
+            let Some(addr) = target else { continue };
+            // A branch to itself would trap before it is executed.
+            if addr == tf.sepc || targets[..i].contains(&target) {
+                continue;
+            }
+            match Breakpoint::plant(addr, poke) {
+                Ok(bp) => step.bps[i] = bp,
+                Err(e) => {
+                    step.remove();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(step)
+    }
+
+    /// Restores the instructions replaced by the breakpoints.
+    ///
+    /// The caller must execute `fence.i` before running the user code again.
+    pub(super) fn remove(&self) {
+        for bp in self.bps.iter().flatten() {
+            bp.remove(self.poke);
+        }
+    }
+
+    /// Returns whether a breakpoint has been planted at `addr`.
+    pub(super) fn contains(&self, addr: usize) -> bool {
+        self.bps.iter().flatten().any(|bp| bp.addr == addr)
+    }
+}
+
+/// Reads the (possibly compressed) instruction at `pc`.
+fn read_inst(pc: usize) -> Option<u32> {
+    let low = unsafe { read_user::<u16>(pc) }.ok()?;
+    if low & 0b11 != 0b11 {
+        return Some(low as u32);
+    }
+    let high = unsafe { read_user::<u16>(pc + 2) }.ok()?;
+    Some(low as u32 | (high as u32) << 16)
+}
+
+/// Sign-extends the lowest `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> isize {
+    ((value << (32 - bits)) as i32 >> (32 - bits)) as isize
+}
+
+/// Returns the possible addresses of the instruction after `inst` at `pc`.
+fn next_pcs(regs: &[usize; 32], pc: usize, inst: u32) -> [Option<usize>; 2] {
+    let bits = |hi: u32, lo: u32| (inst >> lo) & ((1 << (hi - lo + 1)) - 1);
+    let reg = |idx: u32| regs[idx as usize];
+
+    if inst & 0b11 != 0b11 {
+        let next = pc.wrapping_add(2);
+        // Offset of `c.j` and `c.jal`.
+        let cj_offset = || {
+            let imm = bits(12, 12) << 11
+                | bits(11, 11) << 4
+                | bits(10, 9) << 8
+                | bits(8, 8) << 10
+                | bits(7, 7) << 6
+                | bits(6, 6) << 7
+                | bits(5, 3) << 1
+                | bits(2, 2) << 5;
+            sign_extend(imm, 12)
+        };
+        return match (bits(1, 0), bits(15, 13)) {
+            // c.j
+            (0b01, 0b101) => [Some(pc.wrapping_add_signed(cj_offset())), None],
+            // c.jal
+            #[cfg(target_arch = "riscv32")]
+            (0b01, 0b001) => [Some(pc.wrapping_add_signed(cj_offset())), None],
+            // c.beqz, c.bnez
+            (0b01, 0b110 | 0b111) => {
+                let imm = bits(12, 12) << 8
+                    | bits(11, 10) << 3
+                    | bits(6, 5) << 6
+                    | bits(4, 3) << 1
+                    | bits(2, 2) << 5;
+                [
+                    Some(next),
+                    Some(pc.wrapping_add_signed(sign_extend(imm, 9))),
+                ]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if bits(6, 2) == 0 && bits(11, 7) != 0 => {
+                [Some(reg(bits(11, 7)) & !1), None]
+            }
+            _ => [Some(next), None],
+        };
+    }
+
+    let next = pc.wrapping_add(4);
+    match bits(6, 0) {
+        // jal
+        0b110_1111 => {
+            let imm =
+                bits(31, 31) << 20 | bits(30, 21) << 1 | bits(20, 20) << 11 | bits(19, 12) << 12;
+            [Some(pc.wrapping_add_signed(sign_extend(imm, 21))), None]
+        }
+        // jalr
+        0b110_0111 => {
+            let target = reg(bits(19, 15)).wrapping_add_signed(sign_extend(bits(31, 20), 12));
+            [Some(target & !1), None]
+        }
+        // beq, bne, blt, bge, bltu, bgeu
+        0b110_0011 => {
+            let imm = bits(31, 31) << 12 | bits(30, 25) << 5 | bits(11, 8) << 1 | bits(7, 7) << 11;
+            [
+                Some(next),
+                Some(pc.wrapping_add_signed(sign_extend(imm, 13))),
+            ]
+        }
+        _ => [Some(next), None],
+    }
+}
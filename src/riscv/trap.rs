@@ -4,8 +4,13 @@ use riscv::interrupt::Trap;
 use riscv::register::sstatus;
 use riscv::register::{scause, stval};
 
-use super::TrapFrame;
-use crate::trap::PageFaultFlags;
+use memory_addr::VirtAddr;
+
+use super::{RawTrapCause, TrapFrame};
+use crate::trap::{
+    handle_exception, handle_syscall, handle_unhandled_trap, trap_enter, trap_exit, PageFaultFlags,
+    PageFaultInfo, PageFaultKind, TrapCause,
+};
 
 core::arch::global_asm!(
     include_asm_macros!(),
@@ -18,9 +23,51 @@ fn handle_breakpoint(sepc: &mut usize) {
     *sepc += 2
 }
 
-fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
-    let vaddr = va!(stval::read());
-    if handle_trap!(PAGE_FAULT, vaddr, access_flags) {
+/// Decodes the cause of the trap from `scause` and `stval`.
+pub(super) fn trap_cause() -> TrapCause {
+    let scause = scause::read();
+    let stval = stval::read();
+    let raw = RawTrapCause {
+        scause: scause.bits(),
+        stval,
+    };
+    let Ok(cause) = scause.cause().try_into::<I, E>() else {
+        return TrapCause::Other(raw);
+    };
+    match cause {
+        Trap::Interrupt(_) => TrapCause::Irq(scause.bits(), raw),
+        Trap::Exception(E::LoadPageFault) => {
+            TrapCause::PageFault(va!(stval), PageFaultFlags::READ, raw)
+        }
+        Trap::Exception(E::StorePageFault) => {
+            TrapCause::PageFault(va!(stval), PageFaultFlags::WRITE, raw)
+        }
+        Trap::Exception(E::InstructionPageFault) => {
+            TrapCause::PageFault(va!(stval), PageFaultFlags::EXECUTE, raw)
+        }
+        Trap::Exception(E::Breakpoint) => TrapCause::Breakpoint(raw),
+        Trap::Exception(E::IllegalInstruction) => TrapCause::IllegalInstruction(raw),
+        Trap::Exception(E::InstructionMisaligned | E::LoadMisaligned | E::StoreMisaligned) => {
+            TrapCause::Misaligned(Some(va!(stval)), raw)
+        }
+        Trap::Exception(E::UserEnvCall | E::SupervisorEnvCall) => TrapCause::Syscall(raw),
+        Trap::Exception(_) => TrapCause::Other(raw),
+    }
+}
+
+fn page_fault_kind(_raw: &RawTrapCause) -> PageFaultKind {
+    // `scause` does not tell why the page table walk failed.
+    PageFaultKind::Unknown
+}
+
+fn handle_page_fault(
+    tf: &mut TrapFrame,
+    vaddr: VirtAddr,
+    access_flags: PageFaultFlags,
+    raw: RawTrapCause,
+) {
+    let info = PageFaultInfo::new(tf, vaddr, access_flags, page_fault_kind(&raw), raw);
+    if handle_trap!(PAGE_FAULT, tf, &info) {
         return;
     }
     #[cfg(feature = "uspace")]
@@ -28,6 +75,9 @@ fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
         return;
     }
     core::hint::cold_path();
+    if handle_unhandled_trap(tf, TrapCause::PageFault(vaddr, access_flags, raw)) {
+        return;
+    }
     panic!(
         "Unhandled Supervisor Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
         tf.sepc,
@@ -38,21 +88,33 @@ fn handle_page_fault(tf: &mut TrapFrame, access_flags: PageFaultFlags) {
     );
 }
 
+fn handle_kernel_syscall(tf: &mut TrapFrame) -> bool {
+    tf.sepc += 4;
+    if handle_syscall(tf) {
+        return true;
+    }
+    tf.sepc -= 4;
+    false
+}
+
 #[unsafe(no_mangle)]
 fn riscv_trap_handler(tf: &mut TrapFrame) {
-    let scause = scause::read();
-    if let Ok(cause) = scause.cause().try_into::<I, E>() {
-        match cause {
-            Trap::Exception(E::LoadPageFault) => handle_page_fault(tf, PageFaultFlags::READ),
-            Trap::Exception(E::StorePageFault) => handle_page_fault(tf, PageFaultFlags::WRITE),
-            Trap::Exception(E::InstructionPageFault) => {
-                handle_page_fault(tf, PageFaultFlags::EXECUTE)
-            }
-            Trap::Exception(E::Breakpoint) => handle_breakpoint(&mut tf.sepc),
-            Trap::Interrupt(_) => {
-                handle_trap!(IRQ, scause.bits());
-            }
-            _ => {
+    let cause = trap_cause();
+    trap_enter(tf, cause, false);
+    match cause {
+        TrapCause::PageFault(vaddr, access_flags, raw) => {
+            handle_page_fault(tf, vaddr, access_flags, raw)
+        }
+        TrapCause::Irq(irq, _) => {
+            handle_trap!(IRQ, irq);
+        }
+        TrapCause::Syscall(_) if handle_kernel_syscall(tf) => {}
+        _ if handle_exception(tf, cause) => {}
+        TrapCause::Breakpoint(_) => handle_breakpoint(&mut tf.sepc),
+        _ if handle_unhandled_trap(tf, cause) => {}
+        _ => {
+            let scause = scause::read();
+            if let Ok(cause) = scause.cause().try_into::<I, E>() {
                 panic!(
                     "Unhandled trap {:?} @ {:#x}, stval={:#x}:\n{:#x?}\n{}",
                     cause,
@@ -61,20 +123,25 @@ fn riscv_trap_handler(tf: &mut TrapFrame) {
                     tf,
                     tf.backtrace()
                 );
+            } else {
+                panic!(
+                    "Unknown trap {:#x?} @ {:#x}:\n{:#x?}\n{}",
+                    scause.cause(),
+                    tf.sepc,
+                    tf,
+                    tf.backtrace()
+                );
             }
         }
-    } else {
-        panic!(
-            "Unknown trap {:#x?} @ {:#x}:\n{:#x?}\n{}",
-            scause.cause(),
-            tf.sepc,
-            tf,
-            tf.backtrace()
-        );
     }
+    trap_exit(tf, cause, false);
 
     // Update tf.sstatus to preserve current hardware FS state
     // This replaces the assembly-level FS handling workaround
     #[cfg(feature = "fp-simd")]
     tf.sstatus.set_fs(sstatus::read().fs());
+    #[cfg(feature = "vector")]
+    {
+        tf.sstatus = super::context::with_vs(tf.sstatus, super::context::read_vs());
+    }
 }
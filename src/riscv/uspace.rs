@@ -14,14 +14,39 @@ use riscv::{
     register::stval,
 };
 
-use crate::{trap::PageFaultFlags, GeneralRegisters, TrapFrame};
+use super::{single_step::SingleStep, trap::trap_cause};
+use crate::{
+    trap::{trap_enter, trap_exit, PageFaultFlags},
+    uspace_common::siginfo::*,
+    GeneralRegisters, TrapFrame,
+};
+
+pub use super::coredump::*;
+pub use super::single_step::PokeText;
+pub use crate::uspace_common::{
+    ExceptionKind, MisalignedError, ReturnReason, SignalFrameParams, SignalReturn, SignalStack,
+    UserAccessError, NT_PRFPREG, NT_PRSTATUS, SIGINFO_SIZE,
+};
+
+/// The end of the user address space (the lower half of the Sv39/Sv32
+/// address space).
+#[cfg(target_arch = "riscv64")]
+pub(crate) const USER_SPACE_END: usize = 1 << 38;
+#[cfg(target_arch = "riscv32")]
+pub(crate) const USER_SPACE_END: usize = 1 << 31;
 
-pub use crate::uspace_common::{ExceptionKind, ReturnReason};
+/// The exception code of hardware error exceptions, which is not known by the
+/// `riscv` crate.
+const HARDWARE_ERROR_EXCEPTION: usize = 19;
 
 /// Context to enter user space.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
-pub struct UserContext(TrapFrame);
+pub struct UserContext {
+    tf: TrapFrame,
+    single_step: bool,
+    poke_text: PokeText,
+}
 
 impl UserContext {
     /// Creates a new context with the given entry point, user stack pointer,
@@ -32,16 +57,51 @@ impl UserContext {
         sstatus.set_sum(true); // enable user memory access in supervisor mode
         #[cfg(feature = "fp-simd")]
         sstatus.set_fs(FS::Initial); // set the FPU to initial state
+        #[cfg(feature = "vector")]
+        if super::VectorState::vlenb() != 0 {
+            // set the vector unit to initial state
+            sstatus = super::context::with_vs(sstatus, FS::Initial);
+        }
 
-        Self(TrapFrame {
-            regs: GeneralRegisters {
-                a0: arg0,
-                sp: ustack_top.as_usize(),
-                ..Default::default()
+        Self {
+            tf: TrapFrame {
+                regs: GeneralRegisters {
+                    a0: arg0,
+                    sp: ustack_top.as_usize(),
+                    ..Default::default()
+                },
+                sepc: entry,
+                sstatus,
+                orig_arg0: 0,
             },
-            sepc: entry,
-            sstatus,
-        })
+            single_step: false,
+            poke_text: crate::uspace_common::copy_to_user,
+        }
+    }
+
+    /// Enables or disables single-stepping.
+    ///
+    /// When enabled, [`run`](Self::run) returns [`ReturnReason::SingleStep`]
+    /// after a single user instruction is executed. It is emulated by planting
+    /// `ebreak` at the possible addresses of the next instruction, which are
+    /// written by the function set by [`set_poke_text`](Self::set_poke_text)
+    /// and removed before [`run`](Self::run) returns. If they cannot be
+    /// written, [`run`](Self::run) returns a write page fault at the address
+    /// without entering user space.
+    ///
+    /// Other threads sharing the address space must be stopped during the
+    /// step, or they may hit the planted breakpoints.
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
+    }
+
+    /// Sets the function to write the single-step breakpoints to user text.
+    ///
+    /// By default, they are written through the user mapping, which fails on
+    /// read-only text and is visible to other address spaces sharing the pages.
+    /// See [`PokeText`] for what the kernel should do instead.
+    pub fn set_poke_text(&mut self, poke: PokeText) {
+        self.poke_text = poke;
     }
 
     /// Enter user space.
@@ -52,21 +112,50 @@ impl UserContext {
     /// This function returns when an exception or syscall occurs.
     pub fn run(&mut self) -> ReturnReason {
         extern "C" {
-            fn enter_user(uctx: &mut UserContext);
+            fn enter_user(tf: &mut TrapFrame);
         }
 
-        crate::asm::disable_irqs();
-        unsafe { enter_user(self) };
+        let step = if self.single_step {
+            match SingleStep::plant(self, self.poke_text) {
+                Ok(step) => Some(step),
+                Err(e) => {
+                    return ReturnReason::PageFault(
+                        va!(e.addr()),
+                        PageFaultFlags::WRITE | PageFaultFlags::USER,
+                    );
+                }
+            }
+        } else {
+            None
+        };
 
+        crate::asm::disable_irqs();
+        if step.is_some() {
+            riscv::asm::fence_i();
+        }
+        unsafe { enter_user(&mut self.tf) };
         let scause = scause::read();
+        let stval = stval::read();
+        let cause = trap_cause();
+        trap_enter(self, cause, true);
+
         let ret = if let Ok(cause) = scause.cause().try_into::<I, E>() {
-            let stval = stval::read();
             match cause {
                 Trap::Interrupt(_) => {
                     handle_trap!(IRQ, scause.bits());
                     ReturnReason::Interrupt
                 }
+                Trap::Exception(E::Breakpoint)
+                    if step.as_ref().is_some_and(|s| s.contains(self.sepc)) =>
+                {
+                    ReturnReason::SingleStep
+                }
+                #[cfg(feature = "hw-breakpoint")]
+                Trap::Exception(E::Breakpoint) if crate::debug::take_hit(stval) => {
+                    ReturnReason::Watchpoint(va!(stval))
+                }
                 Trap::Exception(E::UserEnvCall) => {
+                    self.orig_arg0 = self.regs.a0;
                     self.sepc += 4;
                     ReturnReason::Syscall
                 }
@@ -81,13 +170,26 @@ impl UserContext {
                     va!(stval),
                     PageFaultFlags::EXECUTE | PageFaultFlags::USER,
                 ),
-                Trap::Exception(e) => ReturnReason::Exception(ExceptionInfo { e, stval }),
+                Trap::Exception(e) => ReturnReason::Exception(ExceptionInfo {
+                    e,
+                    stval,
+                    sepc: self.sepc,
+                }),
             }
+        } else if !scause.is_interrupt() && scause.code() == HARDWARE_ERROR_EXCEPTION {
+            ReturnReason::MachineCheck
         } else {
             ReturnReason::Unknown
         };
 
+        trap_exit(self, cause, true);
         crate::asm::enable_irqs();
+        // Restoring the instructions writes user memory, which may trap (and
+        // sleep) by itself, so it is done after the trap has been decoded.
+        if let Some(step) = &step {
+            step.remove();
+            riscv::asm::fence_i();
+        }
         ret
     }
 }
@@ -96,13 +198,13 @@ impl Deref for UserContext {
     type Target = TrapFrame;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.tf
     }
 }
 
 impl DerefMut for UserContext {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.tf
     }
 }
 
@@ -113,6 +215,8 @@ pub struct ExceptionInfo {
     pub e: E,
     /// The faulting address (from `stval`).
     pub stval: usize,
+    /// The address of the faulting instruction (from `sepc`).
+    pub sepc: usize,
 }
 
 impl ExceptionInfo {
@@ -127,4 +231,21 @@ impl ExceptionInfo {
             _ => ExceptionKind::Other,
         }
     }
+
+    /// Returns the signal to deliver for this exception, as
+    /// `(signo, si_code, si_addr)`, following Linux.
+    pub fn to_signal(&self) -> (usize, i32, usize) {
+        match self.e {
+            E::InstructionMisaligned => (SIGBUS, BUS_ADRALN, self.sepc),
+            E::LoadMisaligned | E::StoreMisaligned => (SIGBUS, BUS_ADRALN, self.stval),
+            E::InstructionFault => (SIGSEGV, SEGV_ACCERR, self.sepc),
+            E::LoadFault | E::StoreFault => (SIGSEGV, SEGV_ACCERR, self.stval),
+            E::InstructionPageFault | E::LoadPageFault | E::StorePageFault => {
+                (SIGSEGV, SEGV_MAPERR, self.stval)
+            }
+            E::IllegalInstruction => (SIGILL, ILL_ILLOPC, self.sepc),
+            E::Breakpoint => (SIGTRAP, TRAP_BRKPT, self.sepc),
+            _ => (SIGILL, ILL_ILLTRP, self.sepc),
+        }
+    }
 }
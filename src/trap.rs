@@ -1,32 +1,635 @@
 //! Trap handling.
+//!
+//! Handlers are registered into the trap slices with [`register_trap_handler`]
+//! together with a priority. When a trap occurs, the handlers of the
+//! corresponding slice are called in ascending order of their priorities
+//! (handlers with the same priority are called in link order), until one of
+//! them returns `true`.
+//!
+//! ```ignore
+//! use axcpu::trap::{
+//!     register_trap_handler, PageFaultHandler, PageFaultInfo, TrapHandler, PAGE_FAULT,
+//! };
+//! use axcpu::TrapFrame;
+//!
+//! fn handle_cow_fault(tf: &mut TrapFrame, info: &PageFaultInfo) -> bool {
+//!     // ...
+//! }
+//!
+//! #[register_trap_handler(PAGE_FAULT)]
+//! static COW_FAULT: TrapHandler<PageFaultHandler> = TrapHandler::new(10, handle_cow_fault);
+//! ```
+//!
+//! Handlers can also be installed and removed at runtime through the
+//! registries in [`dynamic`], e.g., by loadable modules or drivers probed after
+//! boot. They are ordered together with the statically registered handlers.
+//!
+//! All handlers are called with interrupts disabled. With the `nested-irq`
+//! feature, a handler may re-enable them (e.g., an IRQ handler after it has
+//! acknowledged the interrupt) to let other IRQs nest. Interrupts are disabled
+//! again before the [`TRAP_EXIT`] hooks are called, and the number of nested
+//! IRQ handlers on the current CPU is tracked by [`irq_nesting_depth`].
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicI32, AtomicPtr, AtomicUsize, Ordering},
+};
 
 use memory_addr::VirtAddr;
 
-pub use crate::TrapFrame;
+#[cfg(feature = "trap-stats")]
+pub mod stats;
+#[cfg(feature = "trap-trace")]
+pub mod trace;
+
+pub use crate::{RawTrapCause, TrapFrame};
 pub use linkme::distributed_slice as def_trap_handler;
 pub use linkme::distributed_slice as register_trap_handler;
 pub use page_table_entry::MappingFlags as PageFaultFlags;
 
+/// The signature of IRQ handler functions.
+pub type IrqHandler = fn(usize) -> bool;
+
+/// The signature of page fault handler functions.
+pub type PageFaultHandler = fn(&mut TrapFrame, &PageFaultInfo) -> bool;
+
+/// The signature of exception handler functions.
+pub type ExceptionHandler = fn(&mut TrapFrame, TrapCause) -> bool;
+
+/// The signature of syscall handler functions.
+///
+/// The handler reads the syscall number and arguments from the trap frame, and
+/// writes the return value back to it.
+pub type SyscallHandler = fn(&mut TrapFrame) -> bool;
+
+/// The signature of trap entry and exit hook functions.
+///
+/// The last argument indicates whether the trap is taken from user space.
+pub type TrapHook = fn(&TrapFrame, TrapCause, bool);
+
+/// The kind of a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultKind {
+    /// No valid mapping for the address.
+    Translation,
+    /// The access is not allowed by the permissions of the mapping.
+    Permission,
+    /// The access flag (or accessed bit) of the mapping is not set.
+    AccessFlag,
+    /// A write to a mapping whose dirty bit is not set.
+    Dirty,
+    /// The hardware does not tell the kind of the fault.
+    Unknown,
+}
+
+/// Information about a page fault.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFaultInfo {
+    /// The faulting virtual address.
+    pub vaddr: VirtAddr,
+    /// The access type.
+    pub access_flags: PageFaultFlags,
+    /// The kind of the fault.
+    pub kind: PageFaultKind,
+    /// Whether the fault is taken from user space.
+    pub from_user: bool,
+    /// The address of the faulting instruction.
+    pub pc: usize,
+    /// Whether the faulting instruction is covered by the exception table,
+    /// e.g., it is in `user_copy`.
+    pub in_extable: bool,
+    /// The raw architecture-specific cause.
+    pub raw: RawTrapCause,
+}
+
+impl PageFaultInfo {
+    #[allow(dead_code)]
+    pub(crate) fn new(
+        tf: &TrapFrame,
+        vaddr: VirtAddr,
+        access_flags: PageFaultFlags,
+        kind: PageFaultKind,
+        raw: RawTrapCause,
+    ) -> Self {
+        let pc = tf.ip();
+        Self {
+            vaddr,
+            access_flags,
+            kind,
+            from_user: access_flags.contains(PageFaultFlags::USER),
+            pc,
+            #[cfg(feature = "uspace")]
+            in_extable: crate::uspace_common::search_exception_table(pc).is_some(),
+            #[cfg(not(feature = "uspace"))]
+            in_extable: false,
+            raw,
+        }
+    }
+}
+
+/// The cause of a trap, decoded into an architecture-independent form.
+///
+/// Every variant carries the raw architecture-specific information that it
+/// was decoded from.
+#[derive(Debug, Clone, Copy)]
+pub enum TrapCause {
+    /// A page fault at the given address, with the access type.
+    PageFault(VirtAddr, PageFaultFlags, RawTrapCause),
+    /// A breakpoint exception.
+    Breakpoint(RawTrapCause),
+    /// An illegal or undefined instruction.
+    IllegalInstruction(RawTrapCause),
+    /// A misaligned access, with the faulting address if the hardware
+    /// reports it.
+    Misaligned(Option<VirtAddr>, RawTrapCause),
+    /// A floating-point exception, or an access to the disabled FP/SIMD unit.
+    FpFault(RawTrapCause),
+    /// A system call.
+    Syscall(RawTrapCause),
+    /// An interrupt with the given IRQ number.
+    Irq(usize, RawTrapCause),
+    /// Other kinds of traps.
+    Other(RawTrapCause),
+}
+
+impl TrapCause {
+    /// Returns the raw architecture-specific cause.
+    pub const fn raw(&self) -> &RawTrapCause {
+        match self {
+            Self::PageFault(_, _, raw)
+            | Self::Breakpoint(raw)
+            | Self::IllegalInstruction(raw)
+            | Self::Misaligned(_, raw)
+            | Self::FpFault(raw)
+            | Self::Syscall(raw)
+            | Self::Irq(_, raw)
+            | Self::Other(raw) => raw,
+        }
+    }
+}
+
+/// A trap handler function with its priority.
+#[derive(Debug, Clone, Copy)]
+pub struct TrapHandler<F> {
+    /// The priority of the handler. Handlers with smaller values are called
+    /// first.
+    pub priority: i32,
+    /// The handler function.
+    pub handler: F,
+}
+
+impl<F> TrapHandler<F> {
+    /// The priority used when there is no particular ordering requirement.
+    pub const DEFAULT_PRIORITY: i32 = 0;
+
+    /// Creates a new trap handler with the given priority.
+    pub const fn new(priority: i32, handler: F) -> Self {
+        Self { priority, handler }
+    }
+}
+
 /// A slice of IRQ handler functions.
 #[def_trap_handler]
-pub static IRQ: [fn(usize) -> bool];
+pub static IRQ: [TrapHandler<IrqHandler>];
 
 /// A slice of page fault handler functions.
 #[def_trap_handler]
-pub static PAGE_FAULT: [fn(VirtAddr, PageFaultFlags) -> bool];
+pub static PAGE_FAULT: [TrapHandler<PageFaultHandler>];
+
+/// A slice of exception handler functions.
+///
+/// They are called for exceptions raised in kernel mode other than page
+/// faults and IRQs. A handler that returns `true` must have made the trap
+/// frame resumable, e.g., by advancing the instruction pointer.
+#[def_trap_handler]
+pub static EXCEPTION: [TrapHandler<ExceptionHandler>];
+
+/// A slice of syscall handler functions.
+///
+/// They are called for syscalls issued in kernel mode, e.g., by applications
+/// running in kernel space: `int 0x80` on x86_64, `svc` on AArch64, `ecall` on
+/// RISC-V and `syscall` on LoongArch. The instruction pointer in the trap frame
+/// has been advanced to the next instruction before they are called.
+#[def_trap_handler]
+pub static SYSCALL: [TrapHandler<SyscallHandler>];
+
+/// A slice of handler functions for unhandled traps.
+///
+/// They are called as the last resort for kernel-mode traps that no other
+/// handler has handled, e.g., to log the trap, kill the current task or switch
+/// to a crash kernel. A handler may not return at all. If one returns `true`,
+/// execution resumes from the (possibly modified) trap frame. If there are no
+/// such handlers or all of them return `false`, the kernel panics.
+#[def_trap_handler]
+pub static UNHANDLED_TRAP: [TrapHandler<ExceptionHandler>];
+
+/// A slice of hook functions called on every trap entry.
+///
+/// They are called with interrupts disabled, before the trap is handled. All
+/// hooks are called in ascending order of their priorities.
+#[def_trap_handler]
+pub static TRAP_ENTER: [TrapHandler<TrapHook>];
+
+/// A slice of hook functions called on every trap exit.
+///
+/// They are called with interrupts disabled, after the trap is handled and
+/// right before returning to the interrupted context. All hooks are called in
+/// ascending order of their priorities.
+///
+/// A hook may switch to another task, e.g., to preempt the current task on
+/// return from an IRQ. The interrupted context is resumed when the current
+/// task is switched back.
+#[def_trap_handler]
+pub static TRAP_EXIT: [TrapHandler<TrapHook>];
+
+/// Runtime registries of trap handlers.
+///
+/// Each registry corresponds to the trap slice with the same name in
+/// [`crate::trap`], and is consulted together with it when dispatching.
+pub mod dynamic {
+    use super::{
+        DynTrapHandlers, ExceptionHandler, IrqHandler, PageFaultHandler, SyscallHandler, TrapHook,
+    };
+
+    /// IRQ handlers registered at runtime.
+    pub static IRQ: DynTrapHandlers<IrqHandler> = DynTrapHandlers::new();
+
+    /// Page fault handlers registered at runtime.
+    pub static PAGE_FAULT: DynTrapHandlers<PageFaultHandler> = DynTrapHandlers::new();
+
+    /// Exception handlers registered at runtime.
+    pub static EXCEPTION: DynTrapHandlers<ExceptionHandler> = DynTrapHandlers::new();
+
+    /// Syscall handlers registered at runtime.
+    pub static SYSCALL: DynTrapHandlers<SyscallHandler> = DynTrapHandlers::new();
+
+    /// Unhandled trap handlers registered at runtime.
+    pub static UNHANDLED_TRAP: DynTrapHandlers<ExceptionHandler> = DynTrapHandlers::new();
+
+    /// Trap entry hooks registered at runtime.
+    pub static TRAP_ENTER: DynTrapHandlers<TrapHook> = DynTrapHandlers::new();
+
+    /// Trap exit hooks registered at runtime.
+    pub static TRAP_EXIT: DynTrapHandlers<TrapHook> = DynTrapHandlers::new();
+}
+
+/// Function pointer types that can be stored in a [`DynTrapHandlers`].
+///
+/// # Safety
+///
+/// The implementor must be a function pointer type, so that converting it to
+/// a raw pointer and back is lossless.
+pub unsafe trait TrapHandlerFn: Copy {}
+
+unsafe impl TrapHandlerFn for IrqHandler {}
+unsafe impl TrapHandlerFn for PageFaultHandler {}
+unsafe impl TrapHandlerFn for ExceptionHandler {}
+unsafe impl TrapHandlerFn for SyscallHandler {}
+unsafe impl TrapHandlerFn for TrapHook {}
+
+/// The identifier of a handler registered in a [`DynTrapHandlers`].
+///
+/// It is only valid for the registry that returned it, and only until the
+/// handler is unregistered, even if the slot is reused by another handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynHandlerId {
+    /// The address of the registry.
+    registry: usize,
+    /// The index of the slot.
+    slot: usize,
+    /// The generation of the slot when the handler is registered.
+    generation: usize,
+}
+
+struct DynSlot {
+    handler: AtomicPtr<()>,
+    priority: AtomicI32,
+    /// Even while the slot is free or holds a live handler, and odd while the
+    /// handler is being unregistered. It is advanced by 2 each time a handler
+    /// is unregistered from the slot.
+    generation: AtomicUsize,
+    /// The number of calls to the handler that are running on any CPU.
+    active: AtomicUsize,
+}
+
+/// A handler read from a [`DynSlot`] by [`DynTrapHandlers::snapshot`].
+#[derive(Clone, Copy)]
+struct DynEntry<F> {
+    priority: i32,
+    slot: usize,
+    generation: usize,
+    handler: F,
+}
+
+/// A slot that has been claimed but whose handler is not yet published.
+const SLOT_RESERVED: *mut () = 1 as _;
+
+/// The maximum number of handlers in one [`DynTrapHandlers`].
+pub const MAX_DYN_HANDLERS: usize = 16;
+
+/// A lock-free registry of trap handlers that can be modified at runtime.
+///
+/// Registration and unregistration only use atomic operations, so they can be
+/// performed on any CPU, even concurrently with trap dispatching on other CPUs.
+pub struct DynTrapHandlers<F> {
+    slots: [DynSlot; MAX_DYN_HANDLERS],
+    /// The number of live handlers, so that dispatching can skip the slots if
+    /// there are none.
+    count: AtomicUsize,
+    _marker: PhantomData<F>,
+}
+
+impl<F: TrapHandlerFn> DynTrapHandlers<F> {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            slots: [const {
+                DynSlot {
+                    handler: AtomicPtr::new(core::ptr::null_mut()),
+                    priority: AtomicI32::new(0),
+                    generation: AtomicUsize::new(0),
+                    active: AtomicUsize::new(0),
+                }
+            }; MAX_DYN_HANDLERS],
+            count: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Registers a handler with the given priority.
+    ///
+    /// Returns [`None`] if the registry is full.
+    pub fn register(&self, priority: i32, handler: F) -> Option<DynHandlerId> {
+        const { assert!(size_of::<F>() == size_of::<*mut ()>()) };
+        let raw = unsafe { core::mem::transmute_copy::<F, *mut ()>(&handler) };
+        for (idx, slot) in self.slots.iter().enumerate() {
+            if slot
+                .handler
+                .compare_exchange(
+                    core::ptr::null_mut(),
+                    SLOT_RESERVED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                // Released by `get` that reads the new priority, so that it
+                // also observes the generation advanced by the last unregister.
+                slot.priority.store(priority, Ordering::Release);
+                slot.handler.store(raw, Ordering::Release);
+                self.count.fetch_add(1, Ordering::Release);
+                return Some(DynHandlerId {
+                    registry: self as *const _ as usize,
+                    slot: idx,
+                    generation: slot.generation.load(Ordering::Relaxed),
+                });
+            }
+        }
+        None
+    }
+
+    /// Unregisters a handler previously returned by [`register`], and waits
+    /// until it has returned on all CPUs.
+    ///
+    /// Returns `false` if `id` is not returned by this registry, or the handler
+    /// has already been unregistered.
+    ///
+    /// Once it returns `true`, the handler is no longer running or called, so
+    /// its code can be unloaded. As it busy-waits for the running calls, it
+    /// must not be called by the handler itself, or by code that the handler
+    /// interrupted on the current CPU.
+    ///
+    /// [`register`]: DynTrapHandlers::register
+    pub fn unregister(&self, id: DynHandlerId) -> bool {
+        if id.registry != self as *const _ as usize {
+            return false;
+        }
+        let slot = &self.slots[id.slot];
+        // Only one call can retire a generation, and the slot is not reused
+        // until it is released below.
+        if slot
+            .generation
+            .compare_exchange(
+                id.generation,
+                id.generation.wrapping_add(1),
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        // Pairs with `call`: either the caller observes the odd generation and
+        // skips the handler, or its call is counted here.
+        while slot.active.load(Ordering::SeqCst) != 0 {
+            core::hint::spin_loop();
+        }
+        slot.generation
+            .store(id.generation.wrapping_add(2), Ordering::Relaxed);
+        slot.handler.store(core::ptr::null_mut(), Ordering::Release);
+        true
+    }
+
+    fn get(&self, idx: usize) -> Option<DynEntry<F>> {
+        let slot = &self.slots[idx];
+        loop {
+            let generation = slot.generation.load(Ordering::Acquire);
+            let raw = slot.handler.load(Ordering::Acquire);
+            if generation & 1 != 0 || raw.is_null() || raw == SLOT_RESERVED {
+                return None;
+            }
+            let priority = slot.priority.load(Ordering::Acquire);
+            // The handler has been replaced if the generation has changed, in
+            // which case the priority may belong to either handler.
+            if slot.generation.load(Ordering::Relaxed) == generation {
+                return Some(DynEntry {
+                    priority,
+                    slot: idx,
+                    generation,
+                    handler: unsafe { core::mem::transmute_copy::<*mut (), F>(&raw) },
+                });
+            }
+        }
+    }
+
+    /// Reads the live handlers into `entries`, sorted by their priorities (and
+    /// then slots), and returns the number of them.
+    fn snapshot(&self, entries: &mut [Option<DynEntry<F>>; MAX_DYN_HANDLERS]) -> usize {
+        if self.count.load(Ordering::Acquire) == 0 {
+            return 0;
+        }
+        let mut n = 0;
+        for idx in 0..MAX_DYN_HANDLERS {
+            if let Some(entry) = self.get(idx) {
+                entries[n] = Some(entry);
+                n += 1;
+            }
+        }
+        entries[..n].sort_unstable_by_key(|e| e.map(|e| (e.priority, e.slot)));
+        n
+    }
+
+    /// Calls the handler of `entry` with `call`, unless it has been
+    /// unregistered since the snapshot.
+    fn call(&self, entry: &DynEntry<F>, call: &mut impl FnMut(F) -> bool) -> Option<bool> {
+        let slot = &self.slots[entry.slot];
+        slot.active.fetch_add(1, Ordering::SeqCst);
+        let ret = if slot.generation.load(Ordering::SeqCst) == entry.generation {
+            Some(call(entry.handler))
+        } else {
+            None
+        };
+        slot.active.fetch_sub(1, Ordering::Release);
+        ret
+    }
+}
+
+/// Calls the static and dynamic handlers in ascending order of their
+/// priorities, until one of them returns `true`.
+///
+/// Returns [`None`] if there are no handlers at all.
+#[doc(hidden)]
+pub fn dispatch<F: TrapHandlerFn>(
+    handlers: &[TrapHandler<F>],
+    dyn_handlers: &DynTrapHandlers<F>,
+    mut call: impl FnMut(F) -> bool,
+) -> Option<bool> {
+    // The dynamic handlers are read and sorted once, which is skipped if there
+    // are none.
+    let mut entries = [None; MAX_DYN_HANDLERS];
+    let n = dyn_handlers.snapshot(&mut entries);
+    let mut entries = entries[..n].iter().flatten().peekable();
+    // Linker-collected slices cannot be sorted in place, and there are only a
+    // few handlers per slice, so just pick the next one by a linear scan.
+    let mut last: Option<(i32, usize)> = None;
+    let mut found = false;
+    loop {
+        let next = handlers
+            .iter()
+            .enumerate()
+            .map(|(idx, h)| (h.priority, idx))
+            .filter(|&key| last.is_none_or(|last| key > last))
+            .min();
+        // Static handlers come first among the ones with the same priority.
+        if let Some(key) = next.filter(|key| entries.peek().is_none_or(|e| key.0 <= e.priority)) {
+            found = true;
+            if call(handlers[key.1].handler) {
+                return Some(true);
+            }
+            last = Some(key);
+        } else if let Some(entry) = entries.next() {
+            if let Some(handled) = dyn_handlers.call(entry, &mut call) {
+                found = true;
+                if handled {
+                    return Some(true);
+                }
+            }
+        } else {
+            return found.then_some(false);
+        }
+    }
+}
+
+/// Passes an exception to the [`EXCEPTION`] handlers.
+///
+/// Unlike `handle_trap!`, it does not complain if there are no handlers, as
+/// the caller has its own fallback.
+#[allow(dead_code)]
+pub(crate) fn handle_exception(tf: &mut TrapFrame, cause: TrapCause) -> bool {
+    dispatch(&EXCEPTION, &dynamic::EXCEPTION, |handler| {
+        handler(tf, cause)
+    })
+    .unwrap_or(false)
+}
+
+/// Passes a syscall issued in kernel mode to the [`SYSCALL`] handlers.
+#[allow(dead_code)]
+pub(crate) fn handle_syscall(tf: &mut TrapFrame) -> bool {
+    dispatch(&SYSCALL, &dynamic::SYSCALL, |handler| handler(tf)).unwrap_or(false)
+}
+
+/// Passes a trap that has not been handled to the [`UNHANDLED_TRAP`] handlers.
+///
+/// Returns `false` if the caller should panic.
+#[allow(dead_code)]
+pub(crate) fn handle_unhandled_trap(tf: &mut TrapFrame, cause: TrapCause) -> bool {
+    let handled = dispatch(&UNHANDLED_TRAP, &dynamic::UNHANDLED_TRAP, |handler| {
+        handler(tf, cause)
+    })
+    .unwrap_or(false);
+    #[cfg(feature = "trap-trace")]
+    if !handled {
+        error!("{}", trace::dump_current());
+    }
+    handled
+}
+
+#[cfg(feature = "nested-irq")]
+#[percpu::def_percpu]
+static IRQ_NESTING: usize = 0;
+
+/// Returns the number of IRQ handlers that are running on the current CPU.
+///
+/// It is greater than 1 if IRQs are nested.
+#[cfg(feature = "nested-irq")]
+pub fn irq_nesting_depth() -> usize {
+    IRQ_NESTING.read_current()
+}
+
+/// Returns whether the current CPU is handling an IRQ.
+#[cfg(feature = "nested-irq")]
+pub fn in_irq() -> bool {
+    irq_nesting_depth() > 0
+}
+
+/// Records the trap and calls all the [`TRAP_ENTER`] hooks.
+#[allow(dead_code)]
+pub(crate) fn trap_enter(tf: &TrapFrame, cause: TrapCause, from_user: bool) {
+    #[cfg(feature = "nested-irq")]
+    if let TrapCause::Irq(..) = cause {
+        unsafe { IRQ_NESTING.write_current_raw(IRQ_NESTING.read_current_raw() + 1) };
+    }
+    #[cfg(feature = "trap-stats")]
+    stats::record(&cause);
+    #[cfg(feature = "trap-trace")]
+    trace::record(tf, &cause, from_user);
+    dispatch(&TRAP_ENTER, &dynamic::TRAP_ENTER, |hook| {
+        hook(tf, cause, from_user);
+        false
+    });
+}
+
+/// Calls all the [`TRAP_EXIT`] hooks.
+#[allow(dead_code)]
+pub(crate) fn trap_exit(tf: &TrapFrame, cause: TrapCause, from_user: bool) {
+    #[cfg(feature = "nested-irq")]
+    {
+        // The handler may have re-enabled interrupts.
+        crate::asm::disable_irqs();
+        if let TrapCause::Irq(..) = cause {
+            unsafe { IRQ_NESTING.write_current_raw(IRQ_NESTING.read_current_raw() - 1) };
+        }
+    }
+    dispatch(&TRAP_EXIT, &dynamic::TRAP_EXIT, |hook| {
+        hook(tf, cause, from_user);
+        false
+    });
+}
 
 #[allow(unused_macros)]
 macro_rules! handle_trap {
     ($trap:ident, $($args:tt)*) => {{
-        let mut iter = $crate::trap::$trap.iter();
-        if let Some(func) = iter.next() {
-            if iter.next().is_some() {
-                warn!("Multiple handlers for trap {} are not currently supported", stringify!($trap));
+        match $crate::trap::dispatch(
+            &$crate::trap::$trap,
+            &$crate::trap::dynamic::$trap,
+            |handler| handler($($args)*),
+        ) {
+            Some(handled) => handled,
+            None => {
+                warn!("No registered handler for trap {}", stringify!($trap));
+                false
             }
-            func($($args)*)
-        } else {
-            warn!("No registered handler for trap {}", stringify!($trap));
-            false
         }
     }}
 }
@@ -0,0 +1,148 @@
+//! Per-CPU trap statistics.
+//!
+//! Every trap taken on a CPU, from either kernel or user space, is counted in
+//! the statistics of that CPU. The counters are stored in per-CPU data, so the
+//! [`percpu`] crate must have been initialized before any trap occurs.
+//!
+//! [`percpu`]: https://docs.rs/percpu/latest/percpu/index.html
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::TrapCause;
+
+/// The number of IRQs that are counted separately.
+///
+/// IRQs with larger numbers are all counted in [`TrapStats::other_irqs`].
+pub const MAX_IRQS: usize = 256;
+
+/// A snapshot of the trap statistics of a CPU.
+#[derive(Debug, Clone)]
+pub struct TrapStats {
+    /// The number of interrupts of each IRQ number.
+    pub irqs: [usize; MAX_IRQS],
+    /// The number of interrupts with IRQ numbers not less than [`MAX_IRQS`].
+    pub other_irqs: usize,
+    /// The number of page faults.
+    pub page_faults: usize,
+    /// The number of breakpoint exceptions.
+    pub breakpoints: usize,
+    /// The number of illegal instruction exceptions.
+    pub illegal_instructions: usize,
+    /// The number of misaligned access exceptions.
+    pub misaligned: usize,
+    /// The number of floating-point exceptions.
+    pub fp_faults: usize,
+    /// The number of system calls.
+    pub syscalls: usize,
+    /// The number of other exceptions.
+    pub others: usize,
+}
+
+impl TrapStats {
+    /// Returns the total number of interrupts.
+    pub fn total_irqs(&self) -> usize {
+        self.irqs.iter().sum::<usize>() + self.other_irqs
+    }
+
+    /// Returns the total number of exceptions, including system calls.
+    pub fn total_exceptions(&self) -> usize {
+        self.page_faults
+            + self.breakpoints
+            + self.illegal_instructions
+            + self.misaligned
+            + self.fp_faults
+            + self.syscalls
+            + self.others
+    }
+}
+
+struct TrapCounters {
+    irqs: [AtomicUsize; MAX_IRQS],
+    other_irqs: AtomicUsize,
+    page_faults: AtomicUsize,
+    breakpoints: AtomicUsize,
+    illegal_instructions: AtomicUsize,
+    misaligned: AtomicUsize,
+    fp_faults: AtomicUsize,
+    syscalls: AtomicUsize,
+    others: AtomicUsize,
+}
+
+impl TrapCounters {
+    const fn new() -> Self {
+        Self {
+            irqs: [const { AtomicUsize::new(0) }; MAX_IRQS],
+            other_irqs: AtomicUsize::new(0),
+            page_faults: AtomicUsize::new(0),
+            breakpoints: AtomicUsize::new(0),
+            illegal_instructions: AtomicUsize::new(0),
+            misaligned: AtomicUsize::new(0),
+            fp_faults: AtomicUsize::new(0),
+            syscalls: AtomicUsize::new(0),
+            others: AtomicUsize::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> TrapStats {
+        TrapStats {
+            irqs: core::array::from_fn(|i| self.irqs[i].load(Ordering::Relaxed)),
+            other_irqs: self.other_irqs.load(Ordering::Relaxed),
+            page_faults: self.page_faults.load(Ordering::Relaxed),
+            breakpoints: self.breakpoints.load(Ordering::Relaxed),
+            illegal_instructions: self.illegal_instructions.load(Ordering::Relaxed),
+            misaligned: self.misaligned.load(Ordering::Relaxed),
+            fp_faults: self.fp_faults.load(Ordering::Relaxed),
+            syscalls: self.syscalls.load(Ordering::Relaxed),
+            others: self.others.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[percpu::def_percpu]
+static TRAP_COUNTERS: TrapCounters = TrapCounters::new();
+
+/// Counts a trap on the current CPU.
+///
+/// It must be called with interrupts disabled.
+pub(crate) fn record(cause: &TrapCause) {
+    let counters = unsafe { TRAP_COUNTERS.current_ref_raw() };
+    let counter = match *cause {
+        TrapCause::Irq(irq, _) => {
+            // The interrupt bit of `scause` is not a part of the IRQ number.
+            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+            let irq = irq & (usize::MAX >> 1);
+            counters.irqs.get(irq).unwrap_or(&counters.other_irqs)
+        }
+        TrapCause::PageFault(..) => &counters.page_faults,
+        TrapCause::Breakpoint(_) => &counters.breakpoints,
+        TrapCause::IllegalInstruction(_) => &counters.illegal_instructions,
+        TrapCause::Misaligned(..) => &counters.misaligned,
+        TrapCause::FpFault(_) => &counters.fp_faults,
+        TrapCause::Syscall(_) => &counters.syscalls,
+        TrapCause::Other(_) => &counters.others,
+    };
+    // Only the owner CPU updates the counters, but the update may still be
+    // interrupted by a nested trap, so it must be a single atomic operation.
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns a snapshot of the trap statistics of the given CPU.
+///
+/// # Panics
+///
+/// Panics if `cpu_id` is not less than [`cpu_num`].
+pub fn snapshot(cpu_id: usize) -> TrapStats {
+    assert!(cpu_id < cpu_num(), "invalid CPU ID {cpu_id}");
+    unsafe { TRAP_COUNTERS.remote_ref_raw(cpu_id) }.snapshot()
+}
+
+/// Returns an iterator over the snapshots of the trap statistics of all CPUs,
+/// ordered by CPU ID.
+pub fn snapshot_all() -> impl Iterator<Item = TrapStats> {
+    (0..cpu_num()).map(snapshot)
+}
+
+/// Returns the number of CPUs that have trap statistics.
+pub fn cpu_num() -> usize {
+    percpu::percpu_area_num()
+}
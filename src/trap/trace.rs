@@ -0,0 +1,199 @@
+//! Per-CPU trap event tracing.
+//!
+//! The most recent [`TRACE_LEN`] traps taken on each CPU are recorded in a
+//! per-CPU ring buffer, which can be dumped for post-mortem debugging, e.g.,
+//! from the panic handler. The ring buffers are stored in per-CPU data, so the
+//! [`percpu`] crate must have been initialized before any trap occurs.
+//!
+//! [`percpu`]: https://docs.rs/percpu/latest/percpu/index.html
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::MaybeUninit,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+use memory_addr::VirtAddr;
+
+use super::{TrapCause, TrapFrame};
+
+/// The number of events recorded in the ring buffer of each CPU.
+pub const TRACE_LEN: usize = 64;
+
+/// A recorded trap event.
+#[derive(Debug, Clone, Copy)]
+pub struct TrapEvent {
+    /// The value of the CPU cycle or timer counter when the trap was taken.
+    pub timestamp: u64,
+    /// The cause of the trap.
+    pub cause: TrapCause,
+    /// The instruction pointer when the trap was taken.
+    pub ip: usize,
+    /// The faulting address, if any.
+    pub fault_addr: Option<VirtAddr>,
+    /// Whether the trap was taken from user space.
+    pub from_user: bool,
+}
+
+struct TraceSlot {
+    /// Sequence number of the slot. It is odd while the slot is being written.
+    seq: AtomicUsize,
+    event: UnsafeCell<MaybeUninit<TrapEvent>>,
+}
+
+struct TraceBuffer {
+    head: AtomicUsize,
+    slots: [TraceSlot; TRACE_LEN],
+}
+
+unsafe impl Sync for TraceBuffer {}
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+            slots: [const {
+                TraceSlot {
+                    seq: AtomicUsize::new(0),
+                    event: UnsafeCell::new(MaybeUninit::uninit()),
+                }
+            }; TRACE_LEN],
+        }
+    }
+
+    fn push(&self, event: TrapEvent) {
+        // Claiming the index with an atomic operation keeps the slots of
+        // nested traps (e.g., NMIs) apart.
+        let idx = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[idx % TRACE_LEN];
+        slot.seq.store(idx * 2 + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { slot.event.get().write_volatile(MaybeUninit::new(event)) };
+        slot.seq.store(idx * 2 + 2, Ordering::Release);
+    }
+
+    fn get(&self, idx: usize) -> Option<TrapEvent> {
+        let slot = &self.slots[idx % TRACE_LEN];
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq != idx * 2 + 2 {
+            return None;
+        }
+        let event = unsafe { slot.event.get().read_volatile() };
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) != seq {
+            // Overwritten while reading.
+            return None;
+        }
+        Some(unsafe { event.assume_init() })
+    }
+
+    fn events(&self) -> impl Iterator<Item = TrapEvent> + '_ {
+        let head = self.head.load(Ordering::Acquire);
+        (head.saturating_sub(TRACE_LEN)..head).filter_map(|idx| self.get(idx))
+    }
+}
+
+#[percpu::def_percpu]
+static TRAP_TRACE: TraceBuffer = TraceBuffer::new();
+
+fn timestamp() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            unsafe { core::arch::x86_64::_rdtsc() }
+        } else if #[cfg(target_arch = "aarch64")] {
+            use aarch64_cpu::registers::{Readable, CNTPCT_EL0};
+            CNTPCT_EL0.get()
+        } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            riscv::register::time::read() as u64
+        } else if #[cfg(target_arch = "loongarch64")] {
+            loongArch64::time::Time::read() as u64
+        } else {
+            0
+        }
+    }
+}
+
+/// Records a trap in the ring buffer of the current CPU.
+///
+/// It must be called with interrupts disabled.
+pub(crate) fn record(tf: &TrapFrame, cause: &TrapCause, from_user: bool) {
+    let fault_addr = match *cause {
+        TrapCause::PageFault(vaddr, ..) => Some(vaddr),
+        TrapCause::Misaligned(vaddr, _) => vaddr,
+        _ => None,
+    };
+    let event = TrapEvent {
+        timestamp: timestamp(),
+        cause: *cause,
+        ip: tf.ip(),
+        fault_addr,
+        from_user,
+    };
+    unsafe { TRAP_TRACE.current_ref_raw() }.push(event);
+}
+
+/// Calls `f` on the recorded events of the given CPU, from the oldest to the
+/// newest.
+///
+/// Events that are being overwritten concurrently are skipped.
+///
+/// # Panics
+///
+/// Panics if `cpu_id` is not less than the number of per-CPU data areas.
+pub fn for_each_event(cpu_id: usize, f: impl FnMut(TrapEvent)) {
+    assert!(
+        cpu_id < percpu::percpu_area_num(),
+        "invalid CPU ID {cpu_id}"
+    );
+    unsafe { TRAP_TRACE.remote_ref_raw(cpu_id) }
+        .events()
+        .for_each(f);
+}
+
+/// Calls `f` on the recorded events of the current CPU, from the oldest to
+/// the newest.
+pub fn for_each_current_event(f: impl FnMut(TrapEvent)) {
+    unsafe { TRAP_TRACE.current_ref_raw() }.events().for_each(f);
+}
+
+/// A displayable dump of the recorded events of a CPU.
+///
+/// It is returned by [`dump`] and [`dump_current`], and can be printed in the
+/// panic path along with [`TrapFrame::backtrace`].
+pub struct TraceDump(Option<usize>);
+
+impl fmt::Display for TraceDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Recent traps (oldest first):")?;
+        let mut res = Ok(());
+        let mut print = |e: TrapEvent| {
+            if res.is_ok() {
+                res = writeln!(
+                    f,
+                    "  [{:>20}] {} ip={:#x} addr={:#x?} {:?}",
+                    e.timestamp,
+                    if e.from_user { "U" } else { "K" },
+                    e.ip,
+                    e.fault_addr,
+                    e.cause,
+                );
+            }
+        };
+        match self.0 {
+            Some(cpu_id) => for_each_event(cpu_id, &mut print),
+            None => for_each_current_event(&mut print),
+        }
+        res
+    }
+}
+
+/// Returns a displayable dump of the recorded events of the given CPU.
+pub fn dump(cpu_id: usize) -> TraceDump {
+    TraceDump(Some(cpu_id))
+}
+
+/// Returns a displayable dump of the recorded events of the current CPU.
+pub fn dump_current() -> TraceDump {
+    TraceDump(None)
+}
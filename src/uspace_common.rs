@@ -1,3 +1,5 @@
+use core::fmt;
+
 use memory_addr::VirtAddr;
 
 use crate::{trap::PageFaultFlags, uspace::ExceptionInfo, TrapFrame};
@@ -14,6 +16,25 @@ pub enum ReturnReason {
     PageFault(VirtAddr, PageFaultFlags),
     /// Other kinds of exceptions.
     Exception(ExceptionInfo),
+    /// A single instruction has been executed with
+    /// [`UserContext::set_single_step`](crate::uspace::UserContext::set_single_step)
+    /// enabled.
+    SingleStep,
+    /// A hardware breakpoint or watchpoint set by the `debug` module is hit,
+    /// with the address of the instruction or the data being watched.
+    Watchpoint(VirtAddr),
+    /// A fast interrupt request (AArch64 FIQ).
+    Fiq,
+    /// An asynchronous error, such as an AArch64 SError, which is usually
+    /// caused by an uncorrectable hardware error.
+    AsyncError {
+        /// The raw syndrome of the error (`ESR_EL1` on AArch64).
+        syndrome: usize,
+    },
+    /// A machine check, i.e., a hardware error is detected when executing
+    /// the user program (x86_64 `#MC`, the RISC-V hardware error exception,
+    /// or the LoongArch machine error exception).
+    MachineCheck,
     /// Unknown reason.
     Unknown,
 }
@@ -27,10 +48,274 @@ pub enum ExceptionKind {
     IllegalInstruction,
     /// A misaligned access exception.
     Misaligned,
+    /// An integer divide-by-zero exception.
+    DivideByZero,
+    /// A floating-point exception.
+    FloatingPoint,
+    /// A general protection fault.
+    GeneralProtection,
+    /// A stack-segment fault.
+    StackSegment,
+    /// A debug exception, such as a hardware breakpoint, a watchpoint, or a
+    /// single-step trap.
+    Debug,
     /// Other kinds of exceptions.
     Other,
 }
 
+/// Signal numbers and `si_code`s returned by `ExceptionInfo::to_signal`,
+/// which are the same on all supported architectures.
+#[allow(dead_code)]
+pub(crate) mod siginfo {
+    pub const SIGILL: usize = 4;
+    pub const SIGTRAP: usize = 5;
+    pub const SIGBUS: usize = 7;
+    pub const SIGFPE: usize = 8;
+    pub const SIGSEGV: usize = 11;
+
+    pub const SI_KERNEL: i32 = 0x80;
+    pub const ILL_ILLOPC: i32 = 1;
+    pub const ILL_ILLOPN: i32 = 2;
+    pub const ILL_ILLTRP: i32 = 4;
+    pub const ILL_PRVOPC: i32 = 5;
+    pub const FPE_INTDIV: i32 = 1;
+    pub const FPE_INTOVF: i32 = 2;
+    pub const FPE_FLTDIV: i32 = 3;
+    pub const FPE_FLTOVF: i32 = 4;
+    pub const FPE_FLTUND: i32 = 5;
+    pub const FPE_FLTRES: i32 = 6;
+    pub const FPE_FLTINV: i32 = 7;
+    pub const FPE_FLTUNK: i32 = 14;
+    pub const SEGV_MAPERR: i32 = 1;
+    pub const SEGV_ACCERR: i32 = 2;
+    pub const SEGV_BNDERR: i32 = 3;
+    pub const BUS_ADRALN: i32 = 1;
+    pub const BUS_OBJERR: i32 = 3;
+    pub const TRAP_BRKPT: i32 = 1;
+    pub const TRAP_TRACE: i32 = 2;
+    pub const TRAP_HWBKPT: i32 = 4;
+}
+
+/// The size of `siginfo_t` in bytes.
+pub const SIGINFO_SIZE: usize = 128;
+
+/// The note type of the process status in ELF core dumps, whose `pr_reg`
+/// field is the `elf_gregset_t` returned by
+/// [`UserContext::to_elf_gregset`](crate::uspace::UserContext::to_elf_gregset).
+pub const NT_PRSTATUS: u32 = 1;
+
+/// The note type of the floating-point registers in ELF core dumps.
+pub const NT_PRFPREG: u32 = 2;
+
+/// A signal stack description, laid out as `stack_t`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SignalStack {
+    /// Base address of the stack.
+    pub sp: usize,
+    /// Flags of the stack (`SS_ONSTACK`, `SS_DISABLE`, etc.).
+    pub flags: i32,
+    /// Size of the stack.
+    pub size: usize,
+}
+
+/// Parameters to deliver a signal with
+/// [`UserContext::push_signal_frame`](crate::uspace::UserContext::push_signal_frame).
+#[derive(Debug, Clone, Copy)]
+pub struct SignalFrameParams<'a> {
+    /// The signal number.
+    pub signo: usize,
+    /// The raw `siginfo_t` passed to the handler.
+    pub siginfo: &'a [u8; SIGINFO_SIZE],
+    /// The address of the signal handler.
+    pub handler: usize,
+    /// The address the handler returns to, which should issue the
+    /// `rt_sigreturn` syscall, e.g., `sa_restorer` or a trampoline in vDSO.
+    pub restorer: usize,
+    /// The signal mask to be restored on `rt_sigreturn`.
+    pub sigmask: u64,
+    /// The faulting address of the exception that causes the signal (e.g.,
+    /// `ExceptionInfo::far` on aarch64), or 0 if not applicable.
+    ///
+    /// It is saved in `cr2` of `sigcontext` on x86_64, and in
+    /// `fault_address` on aarch64.
+    pub fault_addr: usize,
+    /// The top of the stack to build the frame on, e.g., the top of the
+    /// alternate signal stack. The current user stack is used if it is
+    /// [`None`].
+    pub stack_top: Option<usize>,
+    /// The alternate signal stack saved in `uc_stack`.
+    pub uc_stack: SignalStack,
+}
+
+/// States restored by
+/// [`UserContext::restore_signal_frame`](crate::uspace::UserContext::restore_signal_frame)
+/// that are maintained by the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalReturn {
+    /// The signal mask saved in `uc_sigmask`.
+    pub sigmask: u64,
+    /// The alternate signal stack saved in `uc_stack`.
+    pub uc_stack: SignalStack,
+}
+
+/// An error that occurs when the kernel fails to access user memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserAccessError {
+    addr: usize,
+}
+
+impl UserAccessError {
+    /// Creates an error for the user address that cannot be accessed.
+    pub const fn new(addr: usize) -> Self {
+        Self { addr }
+    }
+
+    /// Returns the user address that cannot be accessed.
+    pub const fn addr(&self) -> usize {
+        self.addr
+    }
+}
+
+impl fmt::Display for UserAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to access user memory at {:#x}", self.addr)
+    }
+}
+
+impl core::error::Error for UserAccessError {}
+
+/// An error that occurs when emulating a misaligned user memory access with
+/// `UserContext::emulate_misaligned`.
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisalignedError {
+    /// The faulting instruction is not a load or store that can be emulated.
+    UnsupportedInstruction(u32),
+    /// Failed to fetch the instruction or to access the data in user memory.
+    Access(UserAccessError),
+}
+
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+impl From<UserAccessError> for MisalignedError {
+    fn from(e: UserAccessError) -> Self {
+        Self::Access(e)
+    }
+}
+
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+impl fmt::Display for MisalignedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedInstruction(inst) => {
+                write!(
+                    f,
+                    "cannot emulate misaligned access of instruction {inst:#x}"
+                )
+            }
+            Self::Access(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+impl core::error::Error for MisalignedError {}
+
+fn check_user_range(addr: usize, len: usize) -> Result<(), UserAccessError> {
+    match addr.checked_add(len) {
+        Some(end) if end <= crate::uspace::USER_SPACE_END => Ok(()),
+        _ => Err(UserAccessError::new(addr)),
+    }
+}
+
+/// Copies `src` to user memory at `dst` with [`user_copy`](crate::asm::user_copy).
+pub(crate) fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), UserAccessError> {
+    check_user_range(dst, src.len())?;
+    let left = unsafe { crate::asm::user_copy(dst as *mut u8, src.as_ptr(), src.len()) };
+    if left != 0 {
+        return Err(UserAccessError::new(dst + src.len() - left));
+    }
+    Ok(())
+}
+
+/// Copies user memory at `src` to `dst` with [`user_copy`](crate::asm::user_copy).
+pub(crate) fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), UserAccessError> {
+    check_user_range(src, dst.len())?;
+    let left = unsafe { crate::asm::user_copy(dst.as_mut_ptr(), src as *const u8, dst.len()) };
+    if left != 0 {
+        return Err(UserAccessError::new(src + dst.len() - left));
+    }
+    Ok(())
+}
+
+/// Writes a value to user memory at `dst`.
+pub(crate) fn write_user<T>(dst: usize, val: &T) -> Result<(), UserAccessError> {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    copy_to_user(dst, bytes)
+}
+
+/// Reads a value from user memory at `src`.
+///
+/// # Safety
+///
+/// Any bit pattern must be a valid value of `T`.
+pub(crate) unsafe fn read_user<T>(src: usize) -> Result<T, UserAccessError> {
+    let mut val = core::mem::MaybeUninit::<T>::zeroed();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    copy_from_user(bytes, src)?;
+    Ok(unsafe { val.assume_init() })
+}
+
+/// Reads a little-endian integer of `size` bytes from user memory at `src`,
+/// and sign- or zero-extends it to 64 bits.
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+pub(crate) fn read_user_int(src: usize, size: usize, signed: bool) -> Result<u64, UserAccessError> {
+    let mut buf = [0; 8];
+    copy_from_user(&mut buf[..size], src)?;
+    let val = u64::from_le_bytes(buf);
+    let shift = 64 - size as u32 * 8;
+    if signed {
+        Ok(((val << shift) as i64 >> shift) as u64)
+    } else {
+        Ok(val)
+    }
+}
+
+/// Writes the low `size` bytes of `val` to user memory at `dst` in
+/// little-endian order.
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+pub(crate) fn write_user_int(dst: usize, val: u64, size: usize) -> Result<(), UserAccessError> {
+    copy_to_user(dst, &val.to_le_bytes()[..size])
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct ExceptionTableEntry {
@@ -43,22 +328,31 @@ unsafe extern "C" {
     static _ex_table_end: [ExceptionTableEntry; 0];
 }
 
+/// Returns the fixup address of the instruction at `ip` if it is in the
+/// exception table.
+pub(crate) fn search_exception_table(ip: usize) -> Option<usize> {
+    let entries = unsafe {
+        core::slice::from_raw_parts(
+            _ex_table_start.as_ptr(),
+            _ex_table_end
+                .as_ptr()
+                .offset_from_unsigned(_ex_table_start.as_ptr()),
+        )
+    };
+    entries
+        .binary_search_by(|e| e.from.cmp(&ip))
+        .ok()
+        .map(|entry| entries[entry].to)
+}
+
 impl TrapFrame {
     pub(crate) fn fixup_exception(&mut self) -> bool {
-        let entries = unsafe {
-            core::slice::from_raw_parts(
-                _ex_table_start.as_ptr(),
-                _ex_table_end
-                    .as_ptr()
-                    .offset_from_unsigned(_ex_table_start.as_ptr()),
-            )
-        };
-        match entries.binary_search_by(|e| e.from.cmp(&self.ip())) {
-            Ok(entry) => {
-                self.set_ip(entries[entry].to);
+        match search_exception_table(self.ip()) {
+            Some(to) => {
+                self.set_ip(to);
                 true
             }
-            Err(_) => false,
+            None => false,
         }
     }
 }
@@ -140,6 +140,26 @@ pub unsafe fn write_thread_pointer(fs_base: usize) {
     unsafe { msr::wrmsr(msr::IA32_FS_BASE, fs_base as u64) }
 }
 
+/// Enables FP/SIMD instructions by clearing the `TS` flag in `CR0`.
+#[inline]
+pub fn enable_fp() {
+    unsafe { asm!("clts") }
+}
+
+/// Disables FP/SIMD instructions by setting the `TS` flag in `CR0`, so that
+/// they raise a device-not-available exception (`#NM`).
+#[inline]
+pub fn disable_fp() {
+    unsafe { controlregs::cr0_write(controlregs::cr0() | controlregs::Cr0::CR0_TASK_SWITCHED) }
+}
+
+/// Returns whether FP/SIMD instructions are enabled, i.e., the `TS` flag in
+/// `CR0` is clear.
+#[inline]
+pub fn fp_enabled() -> bool {
+    !unsafe { controlregs::cr0() }.contains(controlregs::Cr0::CR0_TASK_SWITCHED)
+}
+
 #[cfg(feature = "uspace")]
 core::arch::global_asm!(include_str!("user_copy.S"));
 
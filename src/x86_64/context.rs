@@ -2,6 +2,9 @@ use core::{arch::naked_asm, fmt};
 
 use memory_addr::VirtAddr;
 
+#[cfg(feature = "fp-simd")]
+use super::xsave::{self, XsaveMode};
+
 /// Saved registers when a trap (interrupt or exception) occurs.
 #[allow(missing_docs)]
 #[repr(C)]
@@ -23,8 +26,16 @@ pub struct TrapFrame {
     pub r14: u64,
     pub r15: u64,
 
+    // Reserved by `trap.S`
+    /// The original `RAX` (the syscall number) of a syscall, which is
+    /// overwritten by the return value, i.e., `orig_rax` in Linux. It is named
+    /// after the other architectures, where the return value overwrites the
+    /// 0th argument.
+    pub orig_arg0: u64,
+
     // Pushed by `trap.S`
     pub vector: u64,
+    /// The error code of exceptions, or 0 if there is none.
     pub error_code: u64,
 
     // Pushed by CPU
@@ -136,12 +147,31 @@ impl TrapFrame {
         self.rax = rax as _;
     }
 
+    /// Rewinds the syscall that has just returned, so that it is executed
+    /// again when returning to user space.
+    ///
+    /// It moves the PC back to the `SYSCALL` (or `INT 0x80`) instruction and
+    /// restores `RAX` from [`orig_arg0`](Self::orig_arg0).
+    pub const fn rewind_syscall(&mut self) {
+        self.rip -= 2;
+        self.rax = self.orig_arg0;
+    }
+
     /// Unwind the stack and get the backtrace.
     pub fn backtrace(&self) -> axbacktrace::Backtrace {
         axbacktrace::Backtrace::capture_trap(self.rbp as _, self.rip as _, 0)
     }
 }
 
+/// Raw information about the cause of a trap.
+#[derive(Debug, Clone, Copy)]
+pub struct RawTrapCause {
+    /// The interrupt vector.
+    pub vector: u8,
+    /// The error code pushed by the CPU, or 0 if there is none.
+    pub error_code: u64,
+}
+
 #[repr(C)]
 #[derive(Debug, Default)]
 struct ContextSwitchFrame {
@@ -177,34 +207,202 @@ pub struct FxsaveArea {
 
 static_assertions::const_assert_eq!(core::mem::size_of::<FxsaveArea>(), 512);
 
+/// The capacity of the XSAVE area in bytes, which holds x87, SSE, AVX, AVX-512
+/// and PKRU states in the standard (non-compacted) format.
+///
+/// Unlike the sizes reported by CPUID leaf 0xD, the capacity is fixed, as the
+/// area is embedded in the task context and this crate does not allocate. So
+/// larger components are not supported: AMX (`XTILECFG` and the 8 KiB
+/// `XTILEDATA`) is never enabled, and neither is any other component located
+/// beyond the capacity on a CPU.
+pub(super) const XSAVE_AREA_SIZE: usize = 2752;
+
+/// The x87 state component in the XSAVE header.
+#[cfg(feature = "fp-simd")]
+pub(super) const XFEATURE_X87: u64 = 1 << 0;
+
+/// The SSE state component in the XSAVE header.
+#[cfg(feature = "fp-simd")]
+pub(super) const XFEATURE_SSE: u64 = 1 << 1;
+
+/// The bit in `XCOMP_BV` indicating the compacted format used by `XSAVES`.
+#[cfg(feature = "fp-simd")]
+const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+
 /// Extended state of a task, such as FP/SIMD states.
+///
+/// It is the memory region for the XSAVE family of instructions, or for
+/// FXSAVE/FXRSTOR on CPUs without XSAVE. The instructions are selected and
+/// the enabled state components (e.g., AVX, AVX-512 and PKRU) are configured
+/// by [`init_trap`](crate::init::init_trap).
+///
+/// Its size is fixed rather than taken from CPUID, so only the components
+/// fitting in it are enabled, which excludes AMX.
+#[repr(C, align(64))]
 pub struct ExtendedState {
-    /// Memory region for the FXSAVE/FXRSTOR instruction.
+    /// The legacy region, i.e., the x87 FPU and SSE states.
+    ///
+    /// It is always up to date after [`save`](Self::save), and is loaded by
+    /// [`restore`](Self::restore).
     pub fxsave_area: FxsaveArea,
+    /// The XSAVE header: `XSTATE_BV`, `XCOMP_BV` and reserved words.
+    xsave_header: [u64; 8],
+    /// The extended region, e.g., AVX and AVX-512 states.
+    xsave_ext: [u8; XSAVE_AREA_SIZE - 576],
 }
 
+static_assertions::const_assert_eq!(core::mem::size_of::<ExtendedState>(), XSAVE_AREA_SIZE);
+
 #[cfg(feature = "fp-simd")]
 impl ExtendedState {
     /// Saves the current extended states from CPU to this structure.
     #[inline]
     pub fn save(&mut self) {
-        unsafe { core::arch::x86_64::_fxsave64(&mut self.fxsave_area as *mut _ as *mut u8) }
+        use core::arch::x86_64::*;
+        let area = self as *mut _ as *mut u8;
+        let features = xsave::features();
+        unsafe {
+            match xsave::mode() {
+                XsaveMode::Fxsave => return _fxsave64(area),
+                XsaveMode::Xsave => _xsave64(area, features),
+                XsaveMode::Xsaveopt => _xsaveopt64(area, features),
+                XsaveMode::Xsaves => _xsaves64(area, features),
+            }
+        }
+        self.fill_init_legacy();
     }
 
     /// Restores the extended states from this structure to CPU.
     #[inline]
     pub fn restore(&self) {
-        unsafe { core::arch::x86_64::_fxrstor64(&self.fxsave_area as *const _ as *const u8) }
+        use core::arch::x86_64::*;
+        let area = self as *const _ as *const u8;
+        let features = xsave::features();
+        unsafe {
+            match xsave::mode() {
+                XsaveMode::Fxsave => _fxrstor64(area),
+                // The states that have not been saved by `XSAVES` (e.g., the
+                // default ones) are in the standard format.
+                XsaveMode::Xsaves if self.xsave_header[1] & XCOMP_BV_COMPACTED != 0 => {
+                    _xrstors64(area, features)
+                }
+                _ => _xrstor64(area, features),
+            }
+        }
+    }
+
+    /// Returns the valid part of the save area, whose size depends on the
+    /// save instruction and the enabled state components.
+    pub fn as_bytes(&self) -> &[u8] {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self as *const _ as *const u8, size_of::<Self>())
+        };
+        &bytes[..xsave::area_size()]
+    }
+
+    /// Saves the current extended states in the standard format of `XSAVE`
+    /// (or `FXSAVE` without XSAVE) as used by signal frames, and returns the
+    /// saved area.
+    #[cfg(feature = "uspace")]
+    pub(super) fn save_standard(&mut self) -> &[u8] {
+        use core::arch::x86_64::*;
+        let area = self as *mut _ as *mut u8;
+        unsafe {
+            if xsave::mode() == XsaveMode::Fxsave {
+                _fxsave64(area);
+            } else {
+                _xsave64(area, xsave::features());
+                self.fill_init_legacy();
+            }
+        }
+        &self.as_bytes_mut()[..xsave::standard_size()]
+    }
+
+    /// Restores the components in `rfbm` from the area in the standard format
+    /// to CPU, and leaves the others unchanged.
+    ///
+    /// The area may come from user space, so the bits that would cause `#GP`
+    /// are cleared first. The components in `rfbm` but not in `XSTATE_BV` are
+    /// reset to their initial values.
+    #[cfg(feature = "uspace")]
+    pub(super) fn restore_standard(&mut self, rfbm: u64) {
+        use core::arch::x86_64::*;
+        self.fxsave_area.mxcsr &= xsave::mxcsr_mask();
+        if xsave::mode() == XsaveMode::Fxsave {
+            unsafe { _fxrstor64(self as *const _ as *const u8) };
+            return;
+        }
+        let rfbm = rfbm & xsave::features();
+        self.xsave_header[0] &= rfbm;
+        // `XCOMP_BV` must be 0 for the standard format, and the rest of the
+        // header is reserved.
+        self.xsave_header[1..].fill(0);
+        unsafe { _xrstor64(self as *const _ as *const u8, rfbm) };
+    }
+
+    /// Returns the whole save area, which is large enough for any supported
+    /// format.
+    #[cfg(feature = "uspace")]
+    pub(super) fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as *mut u8, size_of::<Self>()) }
+    }
+
+    /// Writes the initial values of x87 and SSE states to the legacy region if
+    /// they are skipped by the init optimization of XSAVE instructions, and
+    /// marks them as saved, so that the legacy region is always valid.
+    fn fill_init_legacy(&mut self) {
+        let xstate_bv = self.xsave_header[0];
+        let area = &mut self.fxsave_area;
+        if xstate_bv & XFEATURE_X87 == 0 {
+            area.fcw = 0x37f;
+            area.fsw = 0;
+            area.ftw = 0;
+            area.fop = 0;
+            area.fip = 0;
+            area.fdp = 0;
+            area.st = [0; 16];
+        }
+        if xstate_bv & XFEATURE_SSE == 0 {
+            area.xmm = [0; 32];
+        }
+        self.xsave_header[0] |= XFEATURE_X87 | XFEATURE_SSE;
     }
 
     /// Returns the extended state with initialized values.
     pub const fn default() -> Self {
-        let mut area: FxsaveArea = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
-        area.fcw = 0x37f;
-        area.ftw = 0xffff;
-        area.mxcsr = 0x1f80;
-        Self { fxsave_area: area }
+        let mut state: Self = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+        state.fxsave_area.fcw = 0x37f;
+        state.fxsave_area.ftw = 0xffff;
+        state.fxsave_area.mxcsr = 0x1f80;
+        // Other components are restored to their initial values.
+        state.xsave_header[0] = XFEATURE_X87 | XFEATURE_SSE;
+        state
+    }
+}
+
+/// The extended states of the task running on the current CPU, which are
+/// restored on its first FP/SIMD instruction after being switched in.
+#[cfg(feature = "lazy-fp")]
+#[percpu::def_percpu]
+static CURRENT_EXT_STATE: usize = 0;
+
+/// Handles the device-not-available exception (`#NM`) raised by an FP/SIMD
+/// instruction while they are disabled by lazy switching, by enabling them and
+/// restoring the extended states of the current task.
+///
+/// Returns `false` if FP/SIMD instructions are already enabled, i.e., the trap
+/// is not caused by lazy switching.
+#[cfg(feature = "lazy-fp")]
+pub(crate) fn handle_lazy_fp_trap() -> bool {
+    if crate::asm::fp_enabled() {
+        return false;
     }
+    crate::asm::enable_fp();
+    let state = CURRENT_EXT_STATE.read_current() as *const ExtendedState;
+    if let Some(state) = unsafe { state.as_ref() } {
+        state.restore();
+    }
+    true
 }
 
 impl fmt::Debug for ExtendedState {
@@ -248,6 +446,9 @@ pub struct TaskContext {
     /// The `CR3` register value, i.e., the page table root.
     #[cfg(feature = "uspace")]
     pub cr3: memory_addr::PhysAddr,
+    /// Hardware breakpoints and watchpoints.
+    #[cfg(feature = "hw-breakpoint")]
+    pub debug_state: crate::debug::DebugState,
 }
 
 impl TaskContext {
@@ -267,6 +468,8 @@ impl TaskContext {
             cr3: crate::asm::read_kernel_page_table(),
             #[cfg(feature = "fp-simd")]
             ext_state: ExtendedState::default(),
+            #[cfg(feature = "hw-breakpoint")]
+            debug_state: crate::debug::DebugState::default(),
         }
     }
 
@@ -305,12 +508,25 @@ impl TaskContext {
     ///
     /// It first saves the current task's context from CPU to this place, and then
     /// restores the next task's context from `next_ctx` to CPU.
+    ///
+    /// With the `lazy-fp` feature, the extended states are only saved if the
+    /// current task has used them since it was switched in, and are restored
+    /// on the first FP/SIMD instruction of the next task, so `next_ctx` must
+    /// not be moved while the task is running.
     pub fn switch_to(&mut self, next_ctx: &Self) {
-        #[cfg(feature = "fp-simd")]
+        #[cfg(all(feature = "fp-simd", not(feature = "lazy-fp")))]
         {
             self.ext_state.save();
             next_ctx.ext_state.restore();
         }
+        #[cfg(feature = "lazy-fp")]
+        {
+            if crate::asm::fp_enabled() {
+                self.ext_state.save();
+                crate::asm::disable_fp();
+            }
+            CURRENT_EXT_STATE.write_current(&next_ctx.ext_state as *const _ as usize);
+        }
         #[cfg(feature = "tls")]
         unsafe {
             self.fs_base = crate::asm::read_thread_pointer();
@@ -323,6 +539,11 @@ impl TaskContext {
                 // writing to CR3 has flushed the TLB
             }
         }
+        #[cfg(feature = "hw-breakpoint")]
+        {
+            self.debug_state.save();
+            next_ctx.debug_state.restore();
+        }
         unsafe { context_switch(&mut self.rsp, &next_ctx.rsp) }
     }
 }
@@ -0,0 +1,127 @@
+//! Register notes of ELF core dumps.
+
+pub use crate::coredump::x86_64::{ElfGregset, ELF_FPREGSET_SIZE, ELF_NGREG};
+
+use super::{trap::LEGACY_SYSCALL_VECTOR, uspace::UserContext, FxsaveArea};
+use crate::coredump::x86_64::{UserFpregs, UserRegs};
+
+impl UserContext {
+    /// Converts the registers to `elf_gregset_t`.
+    ///
+    /// `orig_rax` is set to `-1` if not in a syscall, and the `ds`, `es`, `fs`
+    /// and `gs` selectors are set to 0, as they are not used in 64-bit mode.
+    pub fn to_elf_gregset(&self) -> ElfGregset {
+        let orig_rax = if self.vector == LEGACY_SYSCALL_VECTOR as u64 {
+            self.orig_arg0
+        } else {
+            u64::MAX
+        };
+        UserRegs {
+            r15: self.r15,
+            r14: self.r14,
+            r13: self.r13,
+            r12: self.r12,
+            rbp: self.rbp,
+            rbx: self.rbx,
+            r11: self.r11,
+            r10: self.r10,
+            r9: self.r9,
+            r8: self.r8,
+            rax: self.rax,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            orig_rax,
+            rip: self.rip,
+            cs: self.cs,
+            eflags: self.rflags,
+            rsp: self.rsp,
+            ss: self.ss,
+            fs_base: self.fs_base,
+            gs_base: self.gs_base,
+            ..Default::default()
+        }
+        .to_gregset()
+    }
+}
+
+impl FxsaveArea {
+    /// Converts the FXSAVE area to the `NT_PRFPREG` note, which has the same
+    /// layout.
+    pub fn to_elf_fpregset(&self) -> [u8; ELF_FPREGSET_SIZE] {
+        UserFpregs {
+            cwd: self.fcw,
+            swd: self.fsw,
+            ftw: self.ftw,
+            fop: self.fop,
+            rip: self.fip,
+            rdp: self.fdp,
+            mxcsr: self.mxcsr,
+            mxcr_mask: self.mxcsr_mask,
+            st_space: self.st,
+            xmm_space: self.xmm,
+        }
+        .to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_addr::va;
+
+    use super::*;
+
+    #[test]
+    fn gregset_layout() {
+        let mut uctx = UserContext::new(0x40_1000, va!(0x7fff_0000), 0x1234);
+        uctx.rax = 0xa;
+        uctx.r15 = 0xf;
+        uctx.rflags = 0x246;
+        uctx.fs_base = 0x5000;
+        uctx.gs_base = 0x6000;
+
+        let regs = uctx.to_elf_gregset();
+        assert_eq!(regs[0], 0xf); // r15
+        assert_eq!(regs[10], 0xa); // rax
+        assert_eq!(regs[14], 0x1234); // rdi
+        assert_eq!(regs[15], u64::MAX); // orig_rax
+        assert_eq!(regs[16], 0x40_1000); // rip
+        assert_eq!(regs[17], uctx.cs);
+        assert_eq!(regs[18], 0x246); // eflags
+        assert_eq!(regs[19], 0x7fff_0000); // rsp
+        assert_eq!(regs[20], uctx.ss);
+        assert_eq!(regs[21], 0x5000); // fs_base
+        assert_eq!(regs[22], 0x6000); // gs_base
+        assert_eq!(regs[23..], [0; 4]);
+
+        uctx.vector = LEGACY_SYSCALL_VECTOR as u64;
+        uctx.orig_arg0 = 39;
+        assert_eq!(uctx.to_elf_gregset()[15], 39); // orig_rax
+    }
+
+    #[test]
+    fn fpregset_matches_fxsave_layout() {
+        let mut area: FxsaveArea = unsafe { core::mem::zeroed() };
+        area.fcw = 0x37f;
+        area.ftw = 0xff;
+        area.fip = 0x1122_3344_5566_7788;
+        area.mxcsr = 0x1f80;
+        area.mxcsr_mask = 0xffff;
+        area.st[0] = 0xaaaa;
+        area.st[15] = 0xbbbb;
+        area.xmm[0] = 0xcccc;
+        area.xmm[31] = 0xdddd;
+
+        let buf = area.to_elf_fpregset();
+        let raw = unsafe { &*(&area as *const FxsaveArea as *const [u8; ELF_FPREGSET_SIZE]) };
+        assert_eq!(&buf, raw);
+        assert_eq!(buf[0..2], 0x37f_u16.to_ne_bytes());
+        assert_eq!(buf[24..28], 0x1f80_u32.to_ne_bytes());
+        assert_eq!(buf[32..40], 0xaaaa_u64.to_ne_bytes()); // st_space
+        assert_eq!(buf[152..160], 0xbbbb_u64.to_ne_bytes());
+        assert_eq!(buf[160..168], 0xcccc_u64.to_ne_bytes()); // xmm_space
+        assert_eq!(buf[408..416], 0xdddd_u64.to_ne_bytes());
+        assert_eq!(buf[416..], [0; 96]);
+    }
+}
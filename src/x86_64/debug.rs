@@ -0,0 +1,156 @@
+//! Debug registers (`DR0`–`DR7`).
+
+use x86::debugregs::{self, dr0, dr1, dr2, dr3, dr6, dr6_write, dr7, dr7_write, Dr6, Dr7};
+
+use crate::debug::{check_range, DebugError, WatchKind};
+
+const NUM_SLOTS: usize = 4;
+
+/// Bits of `DR7` that are updated when configuring a slot: the local enable
+/// bit, and the condition and length fields.
+const fn slot_mask(idx: usize) -> usize {
+    (1 << (idx * 2)) | (0xf << (16 + idx * 4))
+}
+
+fn write_addr(idx: usize, addr: usize) {
+    unsafe {
+        match idx {
+            0 => debugregs::dr0_write(addr),
+            1 => debugregs::dr1_write(addr),
+            2 => debugregs::dr2_write(addr),
+            _ => debugregs::dr3_write(addr),
+        }
+    }
+}
+
+fn read_addr(idx: usize) -> usize {
+    unsafe {
+        match idx {
+            0 => dr0(),
+            1 => dr1(),
+            2 => dr2(),
+            _ => dr3(),
+        }
+    }
+}
+
+fn set_slot(idx: usize, addr: usize, rw: usize, len: usize) -> Result<(), DebugError> {
+    if idx >= NUM_SLOTS {
+        return Err(DebugError::InvalidIndex(idx));
+    }
+    write_addr(idx, addr);
+    let dr7 = unsafe { dr7() }.0 & !slot_mask(idx);
+    let ctrl = (1 << (idx * 2)) | ((rw | len << 2) << (16 + idx * 4));
+    unsafe { dr7_write(Dr7(dr7 | ctrl)) };
+    Ok(())
+}
+
+fn clear_slot(idx: usize) {
+    if idx < NUM_SLOTS {
+        unsafe { dr7_write(Dr7(dr7().0 & !slot_mask(idx))) };
+    }
+}
+
+/// Returns the number of hardware breakpoints.
+pub fn num_breakpoints() -> usize {
+    NUM_SLOTS
+}
+
+/// Returns the number of hardware watchpoints.
+pub fn num_watchpoints() -> usize {
+    NUM_SLOTS
+}
+
+/// Sets the hardware breakpoint `idx` on the instruction at `addr`.
+pub fn set_breakpoint(idx: usize, addr: usize) -> Result<(), DebugError> {
+    set_slot(idx, addr, 0b00, 0b00)
+}
+
+/// Clears the hardware breakpoint `idx`.
+pub fn clear_breakpoint(idx: usize) {
+    clear_slot(idx)
+}
+
+/// Sets the hardware watchpoint `idx` on `len` bytes at `addr`.
+///
+/// Watchpoints on reads only are not supported.
+pub fn set_watchpoint(
+    idx: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), DebugError> {
+    check_range(addr, len)?;
+    let rw = match kind {
+        WatchKind::Write => 0b01,
+        WatchKind::ReadWrite => 0b11,
+        WatchKind::Read => return Err(DebugError::Unsupported(kind)),
+    };
+    let len = match len {
+        1 => 0b00,
+        2 => 0b01,
+        8 => 0b10,
+        _ => 0b11,
+    };
+    set_slot(idx, addr, rw, len)
+}
+
+/// Clears the hardware watchpoint `idx`.
+pub fn clear_watchpoint(idx: usize) {
+    clear_slot(idx)
+}
+
+/// Takes the hit breakpoint or watchpoint recorded in `DR6`, and returns its
+/// address and whether it is an instruction breakpoint.
+///
+/// `DR6` is cleared, as it is never cleared by the processor.
+pub(crate) fn take_hit() -> Option<(usize, bool)> {
+    let status = unsafe { dr6() };
+    let dr7 = unsafe { dr7() }.0;
+    // The status bits may also be set for disabled slots.
+    let idx =
+        (0..NUM_SLOTS).find(|&i| status.bits() & (1 << i) != 0 && dr7 & (0b11 << (i * 2)) != 0)?;
+    unsafe { dr6_write(Dr6::RTM) };
+    let rw = (dr7 >> (16 + idx * 4)) & 0b11;
+    Some((read_addr(idx), rw == 0b00))
+}
+
+/// Per-task states of the debug registers.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugState {
+    /// Addresses of the breakpoints (`DR0`–`DR3`).
+    pub addrs: [usize; NUM_SLOTS],
+    /// Debug control register (`DR7`).
+    pub dr7: usize,
+}
+
+impl Default for DebugState {
+    fn default() -> Self {
+        Self {
+            addrs: [0; NUM_SLOTS],
+            dr7: Dr7::EMPTY,
+        }
+    }
+}
+
+impl DebugState {
+    /// Saves the debug registers of the current CPU to this structure.
+    pub fn save(&mut self) {
+        self.dr7 = unsafe { dr7() }.0;
+        if self.dr7 & 0xff != 0 {
+            for (i, addr) in self.addrs.iter_mut().enumerate() {
+                *addr = read_addr(i);
+            }
+        }
+    }
+
+    /// Restores the debug registers of the current CPU from this structure.
+    pub fn restore(&self) {
+        if self.dr7 & 0xff != 0 {
+            for (i, &addr) in self.addrs.iter().enumerate() {
+                write_addr(i, addr);
+            }
+        }
+        unsafe { dr7_write(Dr7(self.dr7)) };
+    }
+}
@@ -15,7 +15,9 @@ pub fn init_percpu(cpu_id: usize) {
 ///
 /// In detail, it initializes the GDT, IDT on x86_64 platforms. If the `uspace`
 /// feature is enabled, it also initializes relevant model-specific registers to
-/// configure the handler for `syscall` instruction.
+/// configure the handler for `syscall` instruction. With the `fp-simd` feature,
+/// it enables XSAVE and the supported state components (e.g., AVX and AVX-512)
+/// in `XCR0`, and selects the instructions to save extended states.
 ///
 /// # Notes
 /// Before calling this function, the initialization function of the [`percpu`]
@@ -28,6 +30,8 @@ pub fn init_trap() {
     crate::uspace_common::init_exception_table();
     super::gdt::init();
     super::idt::init();
+    #[cfg(feature = "fp-simd")]
+    super::xsave::init();
     #[cfg(feature = "uspace")]
     super::uspace::init_syscall();
 }
@@ -6,8 +6,17 @@ pub mod asm;
 pub mod init;
 
 mod trap;
+#[cfg(feature = "fp-simd")]
+mod xsave;
 
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) mod debug;
+
+#[cfg(feature = "uspace")]
+mod coredump;
+#[cfg(feature = "uspace")]
+mod signal;
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
-pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
+pub use self::context::{ExtendedState, FxsaveArea, RawTrapCause, TaskContext, TrapFrame};
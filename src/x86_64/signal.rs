@@ -0,0 +1,309 @@
+//! Signal frames compatible with the Linux ABI.
+
+use core::mem::{offset_of, size_of};
+
+use x86_64::registers::rflags::RFlags;
+
+use super::uspace::{UserContext, USER_SPACE_END};
+#[cfg(feature = "fp-simd")]
+use super::{
+    context::{XFEATURE_SSE, XFEATURE_X87},
+    xsave, ExtendedState, FxsaveArea,
+};
+#[cfg(feature = "fp-simd")]
+use crate::uspace_common::{copy_from_user, copy_to_user};
+use crate::uspace_common::{
+    read_user, write_user, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+    SIGINFO_SIZE,
+};
+
+/// The area below the stack pointer that may be used by leaf functions.
+const RED_ZONE_SIZE: usize = 128;
+
+#[cfg(feature = "fp-simd")]
+const UC_FP_XSTATE: u64 = 0x1;
+const UC_SIGCONTEXT_SS: u64 = 0x2;
+const UC_STRICT_RESTORE_SS: u64 = 0x4;
+
+/// `struct sigcontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rax: u64,
+    rcx: u64,
+    rsp: u64,
+    rip: u64,
+    eflags: u64,
+    cs: u16,
+    gs: u16,
+    fs: u16,
+    ss: u16,
+    err: u64,
+    trapno: u64,
+    oldmask: u64,
+    cr2: u64,
+    fpstate: u64,
+    reserved1: [u64; 8],
+}
+
+/// The magic number in [`FpxSwBytes`] indicating an XSAVE area.
+#[cfg(feature = "fp-simd")]
+const FP_XSTATE_MAGIC1: u32 = 0x4650_5853;
+/// The magic number after the XSAVE area.
+#[cfg(feature = "fp-simd")]
+const FP_XSTATE_MAGIC2: u32 = 0x4650_5845;
+
+/// The offset of [`FpxSwBytes`] in the FXSAVE area, i.e., the bytes reserved
+/// for software.
+#[cfg(feature = "fp-simd")]
+const FPX_SW_BYTES_OFFSET: usize = 464;
+
+/// `struct _fpx_sw_bytes` in Linux, which describes the XSAVE area following
+/// the FXSAVE area.
+#[cfg(feature = "fp-simd")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpxSwBytes {
+    magic1: u32,
+    /// The size of the XSAVE area and [`FP_XSTATE_MAGIC2`].
+    extended_size: u32,
+    /// The state components in the XSAVE area.
+    xfeatures: u64,
+    /// The size of the XSAVE area.
+    xstate_size: u32,
+    padding: [u32; 7],
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    uc_flags: u64,
+    uc_link: u64,
+    uc_stack: SignalStack,
+    uc_mcontext: SigContext,
+    uc_sigmask: u64,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+struct RtSigFrame {
+    pretcode: u64,
+    uc: UContext,
+    info: [u8; SIGINFO_SIZE],
+}
+
+static_assertions::const_assert_eq!(size_of::<SigContext>(), 256);
+static_assertions::const_assert_eq!(size_of::<UContext>(), 304);
+static_assertions::const_assert_eq!(size_of::<RtSigFrame>(), 440);
+#[cfg(feature = "fp-simd")]
+static_assertions::const_assert_eq!(
+    FPX_SW_BYTES_OFFSET + size_of::<FpxSwBytes>(),
+    size_of::<FxsaveArea>()
+);
+
+impl UserContext {
+    /// Pushes a signal frame onto the user stack, and redirects the execution
+    /// to the signal handler.
+    ///
+    /// The frame has the same layout as `struct rt_sigframe` in Linux, followed
+    /// by the FXSAVE area of the current FP/SIMD states (only with the
+    /// `fp-simd` feature). The handler is called with the signal number, the
+    /// address of `siginfo_t` and the address of `ucontext_t`, and returns to
+    /// [`SignalFrameParams::restorer`].
+    pub fn push_signal_frame(
+        &mut self,
+        params: &SignalFrameParams<'_>,
+    ) -> Result<(), UserAccessError> {
+        let sp = params
+            .stack_top
+            .unwrap_or((self.rsp as usize).wrapping_sub(RED_ZONE_SIZE));
+
+        #[cfg(feature = "fp-simd")]
+        let (sp, fpstate, uc_flags) = push_fpstate(sp)?;
+        #[cfg(not(feature = "fp-simd"))]
+        let (fpstate, uc_flags) = (0, 0);
+
+        // Aligned as if the handler is called by a `call` instruction.
+        let frame_addr = (sp.wrapping_sub(size_of::<RtSigFrame>()) & !15).wrapping_sub(8);
+        let frame = RtSigFrame {
+            pretcode: params.restorer as _,
+            uc: UContext {
+                uc_flags: uc_flags | UC_SIGCONTEXT_SS | UC_STRICT_RESTORE_SS,
+                uc_link: 0,
+                uc_stack: params.uc_stack,
+                uc_mcontext: SigContext {
+                    r8: self.r8,
+                    r9: self.r9,
+                    r10: self.r10,
+                    r11: self.r11,
+                    r12: self.r12,
+                    r13: self.r13,
+                    r14: self.r14,
+                    r15: self.r15,
+                    rdi: self.rdi,
+                    rsi: self.rsi,
+                    rbp: self.rbp,
+                    rbx: self.rbx,
+                    rdx: self.rdx,
+                    rax: self.rax,
+                    rcx: self.rcx,
+                    rsp: self.rsp,
+                    rip: self.rip,
+                    eflags: self.rflags,
+                    cs: self.cs as _,
+                    gs: 0,
+                    fs: 0,
+                    ss: self.ss as _,
+                    err: self.error_code,
+                    trapno: self.vector,
+                    oldmask: params.sigmask,
+                    cr2: params.fault_addr as _,
+                    fpstate: fpstate as _,
+                    reserved1: [0; 8],
+                },
+                uc_sigmask: params.sigmask,
+            },
+            info: *params.siginfo,
+        };
+        write_user(frame_addr, &frame)?;
+
+        self.rdi = params.signo as _;
+        self.rsi = (frame_addr + offset_of!(RtSigFrame, info)) as _;
+        self.rdx = (frame_addr + offset_of!(RtSigFrame, uc)) as _;
+        self.rax = 0;
+        self.rsp = frame_addr as _;
+        self.rip = params.handler as _;
+        self.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG | RFlags::RESUME_FLAG).bits();
+        Ok(())
+    }
+
+    /// Restores the context saved by [`push_signal_frame`], on the
+    /// `rt_sigreturn` syscall.
+    ///
+    /// Only the user-modifiable bits of `RFLAGS` are restored, and the segment
+    /// selectors are left unchanged. It fails if the saved `RIP` is not a user
+    /// address.
+    ///
+    /// [`push_signal_frame`]: UserContext::push_signal_frame
+    pub fn restore_signal_frame(&mut self) -> Result<SignalReturn, UserAccessError> {
+        // `pretcode` has been popped by the `ret` instruction of the handler.
+        let frame_addr = (self.rsp as usize).wrapping_sub(8);
+        let uc: UContext = unsafe { read_user(frame_addr + offset_of!(RtSigFrame, uc))? };
+        let mc = &uc.uc_mcontext;
+        // A non-canonical RIP would make IRETQ fault in the kernel.
+        if mc.rip as usize >= USER_SPACE_END {
+            return Err(UserAccessError::new(mc.rip as _));
+        }
+
+        #[cfg(feature = "fp-simd")]
+        if mc.fpstate != 0 {
+            restore_fpstate(mc.fpstate as usize, uc.uc_flags)?;
+        }
+
+        self.r8 = mc.r8;
+        self.r9 = mc.r9;
+        self.r10 = mc.r10;
+        self.r11 = mc.r11;
+        self.r12 = mc.r12;
+        self.r13 = mc.r13;
+        self.r14 = mc.r14;
+        self.r15 = mc.r15;
+        self.rdi = mc.rdi;
+        self.rsi = mc.rsi;
+        self.rbp = mc.rbp;
+        self.rbx = mc.rbx;
+        self.rdx = mc.rdx;
+        self.rax = mc.rax;
+        self.rcx = mc.rcx;
+        self.rsp = mc.rsp;
+        self.rip = mc.rip;
+        let user_flags = (RFlags::CARRY_FLAG
+            | RFlags::PARITY_FLAG
+            | RFlags::AUXILIARY_CARRY_FLAG
+            | RFlags::ZERO_FLAG
+            | RFlags::SIGN_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::OVERFLOW_FLAG
+            | RFlags::RESUME_FLAG
+            | RFlags::ALIGNMENT_CHECK)
+            .bits();
+        self.rflags = (self.rflags & !user_flags) | (mc.eflags & user_flags);
+
+        Ok(SignalReturn {
+            sigmask: uc.uc_sigmask,
+            uc_stack: uc.uc_stack,
+        })
+    }
+}
+
+/// Saves the current extended states below `sp` in the standard format of
+/// XSAVE, followed by [`FP_XSTATE_MAGIC2`], as Linux does.
+///
+/// Returns the address of the area (which is also the new stack top) and the
+/// flags to be set in `uc_flags`. Only the FXSAVE area is saved on CPUs
+/// without XSAVE.
+#[cfg(feature = "fp-simd")]
+fn push_fpstate(sp: usize) -> Result<(usize, usize, u64), UserAccessError> {
+    let mut ext_state = ExtendedState::default();
+    let area = ext_state.save_standard();
+    if area.len() == size_of::<FxsaveArea>() {
+        let fpstate = sp.wrapping_sub(area.len()) & !63;
+        copy_to_user(fpstate, area)?;
+        return Ok((fpstate, fpstate, 0));
+    }
+
+    let extended_size = area.len() + size_of::<u32>();
+    let fpstate = sp.wrapping_sub(extended_size) & !63;
+    copy_to_user(fpstate, area)?;
+    let sw_bytes = FpxSwBytes {
+        magic1: FP_XSTATE_MAGIC1,
+        extended_size: extended_size as u32,
+        xfeatures: xsave::features(),
+        xstate_size: area.len() as u32,
+        padding: [0; 7],
+    };
+    write_user(fpstate + FPX_SW_BYTES_OFFSET, &sw_bytes)?;
+    write_user(fpstate + area.len(), &FP_XSTATE_MAGIC2)?;
+    Ok((fpstate, fpstate, UC_FP_XSTATE))
+}
+
+/// Restores the extended states saved at `fpstate` by [`push_fpstate`].
+///
+/// If the area is not marked as an XSAVE area, only the x87 and SSE states are
+/// restored from the FXSAVE area, and other states are left unchanged.
+#[cfg(feature = "fp-simd")]
+fn restore_fpstate(fpstate: usize, uc_flags: u64) -> Result<(), UserAccessError> {
+    let sw_bytes: FpxSwBytes = unsafe { read_user(fpstate + FPX_SW_BYTES_OFFSET)? };
+    let size = sw_bytes.xstate_size as usize;
+    // The legacy region and the XSAVE header are always present.
+    let is_xstate = uc_flags & UC_FP_XSTATE != 0
+        && sw_bytes.magic1 == FP_XSTATE_MAGIC1
+        && (size_of::<FxsaveArea>() + 64..=xsave::standard_size()).contains(&size)
+        && sw_bytes.extended_size as usize == size + size_of::<u32>()
+        && unsafe { read_user::<u32>(fpstate + size)? } == FP_XSTATE_MAGIC2;
+    let (size, rfbm) = if is_xstate {
+        (size, sw_bytes.xfeatures)
+    } else {
+        (size_of::<FxsaveArea>(), XFEATURE_X87 | XFEATURE_SSE)
+    };
+
+    let mut ext_state = ExtendedState::default();
+    copy_from_user(&mut ext_state.as_bytes_mut()[..size], fpstate)?;
+    ext_state.restore_standard(rfbm);
+    Ok(())
+}
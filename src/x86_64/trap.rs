@@ -1,8 +1,13 @@
 use x86::{controlregs::cr2, irq::*};
 use x86_64::structures::idt::PageFaultErrorCode;
 
-use super::{gdt, TrapFrame};
-use crate::trap::PageFaultFlags;
+use memory_addr::VirtAddr;
+
+use super::{gdt, RawTrapCause, TrapFrame};
+use crate::trap::{
+    handle_exception, handle_syscall, handle_unhandled_trap, trap_enter, trap_exit, PageFaultFlags,
+    PageFaultInfo, PageFaultKind, TrapCause,
+};
 
 core::arch::global_asm!(
     include_str!("trap.S"),
@@ -16,11 +21,23 @@ pub(super) const LEGACY_SYSCALL_VECTOR: u8 = 0x80;
 pub(super) const IRQ_VECTOR_START: u8 = 0x20;
 pub(super) const IRQ_VECTOR_END: u8 = 0xff;
 
-fn handle_page_fault(tf: &mut TrapFrame) {
-    let access_flags = err_code_to_flags(tf.error_code)
-        .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
-    let vaddr = va!(unsafe { cr2() });
-    if handle_trap!(PAGE_FAULT, vaddr, access_flags) {
+fn page_fault_kind(raw: &RawTrapCause) -> PageFaultKind {
+    let code = PageFaultErrorCode::from_bits_truncate(raw.error_code);
+    if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        PageFaultKind::Permission
+    } else {
+        PageFaultKind::Translation
+    }
+}
+
+fn handle_page_fault(
+    tf: &mut TrapFrame,
+    vaddr: VirtAddr,
+    access_flags: PageFaultFlags,
+    raw: RawTrapCause,
+) {
+    let info = PageFaultInfo::new(tf, vaddr, access_flags, page_fault_kind(&raw), raw);
+    if handle_trap!(PAGE_FAULT, tf, &info) {
         return;
     }
     #[cfg(feature = "uspace")]
@@ -28,6 +45,9 @@ fn handle_page_fault(tf: &mut TrapFrame) {
         return;
     }
     core::hint::cold_path();
+    if handle_unhandled_trap(tf, TrapCause::PageFault(vaddr, access_flags, raw)) {
+        return;
+    }
     panic!(
         "Unhandled #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}\n{}",
         tf.rip,
@@ -39,12 +59,55 @@ fn handle_page_fault(tf: &mut TrapFrame) {
     );
 }
 
+/// Decodes the cause of the trap saved in the trap frame.
+pub(super) fn trap_cause(tf: &TrapFrame) -> TrapCause {
+    let raw = RawTrapCause {
+        vector: tf.vector as u8,
+        error_code: tf.error_code,
+    };
+    match raw.vector {
+        PAGE_FAULT_VECTOR => match err_code_to_flags(tf.error_code) {
+            Ok(flags) => TrapCause::PageFault(va!(unsafe { cr2() }), flags, raw),
+            Err(_) => TrapCause::Other(raw),
+        },
+        BREAKPOINT_VECTOR => TrapCause::Breakpoint(raw),
+        INVALID_OPCODE_VECTOR => TrapCause::IllegalInstruction(raw),
+        ALIGNMENT_CHECK_VECTOR => TrapCause::Misaligned(None, raw),
+        DEVICE_NOT_AVAILABLE_VECTOR | X87_FPU_VECTOR | SIMD_FLOATING_POINT_VECTOR => {
+            TrapCause::FpFault(raw)
+        }
+        LEGACY_SYSCALL_VECTOR => TrapCause::Syscall(raw),
+        IRQ_VECTOR_START..=IRQ_VECTOR_END => TrapCause::Irq(raw.vector as _, raw),
+        _ => TrapCause::Other(raw),
+    }
+}
+
 #[unsafe(no_mangle)]
 fn x86_trap_handler(tf: &mut TrapFrame) {
-    match tf.vector as u8 {
-        PAGE_FAULT_VECTOR => handle_page_fault(tf),
-        BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
-        GENERAL_PROTECTION_FAULT_VECTOR => {
+    let cause = trap_cause(tf);
+    trap_enter(tf, cause, false);
+    match cause {
+        TrapCause::PageFault(vaddr, access_flags, raw) => {
+            handle_page_fault(tf, vaddr, access_flags, raw)
+        }
+        TrapCause::Irq(vector, _) => {
+            handle_trap!(IRQ, vector);
+        }
+        // `int 0x80` is a trap, so `rip` already points to the next instruction.
+        TrapCause::Syscall(_) if handle_syscall(tf) => {}
+        #[cfg(feature = "lazy-fp")]
+        TrapCause::FpFault(_) if super::context::handle_lazy_fp_trap() => {}
+        _ if handle_exception(tf, cause) => {}
+        TrapCause::Breakpoint(_) => debug!("#BP @ {:#x} ", tf.rip),
+        // Watchpoints on user memory also hit on accesses from the kernel,
+        // e.g., in `user_copy`. They are traps, so just ignore them.
+        #[cfg(feature = "hw-breakpoint")]
+        _ if tf.vector as u8 == DEBUG_VECTOR && crate::debug::take_hit().is_some() => {}
+        _ if handle_unhandled_trap(tf, cause) => {}
+        _ if tf.vector as u8 == PAGE_FAULT_VECTOR => {
+            panic!("Invalid #PF error code: {:#x}", tf.error_code);
+        }
+        _ if tf.vector as u8 == GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}\n{}",
                 tf.rip,
@@ -53,9 +116,6 @@ fn x86_trap_handler(tf: &mut TrapFrame) {
                 tf.backtrace()
             );
         }
-        IRQ_VECTOR_START..=IRQ_VECTOR_END => {
-            handle_trap!(IRQ, tf.vector as _);
-        }
         _ => {
             panic!(
                 "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}\n{}",
@@ -68,6 +128,7 @@ fn x86_trap_handler(tf: &mut TrapFrame) {
             );
         }
     }
+    trap_exit(tf, cause, false);
 }
 
 fn vec_to_str(vec: u64) -> &'static str {
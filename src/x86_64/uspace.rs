@@ -3,6 +3,7 @@
 use core::ops::{Deref, DerefMut};
 
 use memory_addr::VirtAddr;
+use x86::debugregs::{dr6, dr6_write, Dr6};
 use x86_64::{
     registers::{
         control::Cr2,
@@ -15,11 +16,25 @@ use x86_64::{
 use super::{
     asm::{read_thread_pointer, write_thread_pointer},
     gdt,
-    trap::{err_code_to_flags, IRQ_VECTOR_END, IRQ_VECTOR_START, LEGACY_SYSCALL_VECTOR},
+    trap::{
+        err_code_to_flags, trap_cause, IRQ_VECTOR_END, IRQ_VECTOR_START, LEGACY_SYSCALL_VECTOR,
+    },
     TrapFrame,
 };
+use crate::{
+    trap::{trap_enter, trap_exit},
+    uspace_common::siginfo::*,
+};
+
+pub use super::coredump::*;
+pub use crate::uspace_common::{
+    ExceptionKind, ReturnReason, SignalFrameParams, SignalReturn, SignalStack, UserAccessError,
+    NT_PRFPREG, NT_PRSTATUS, SIGINFO_SIZE,
+};
 
-pub use crate::uspace_common::{ExceptionKind, ReturnReason};
+/// The end of the user address space (the lower half of the canonical
+/// addresses).
+pub(crate) const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
 
 /// Context to enter user space.
 #[derive(Debug, Clone, Copy)]
@@ -62,13 +77,36 @@ impl UserContext {
         self.fs_base = tls_area as _;
     }
 
+    /// Enables or disables single-stepping.
+    ///
+    /// When enabled, [`run`](Self::run) returns [`ReturnReason::SingleStep`]
+    /// after a single user instruction is executed. It is implemented with the
+    /// trap flag (`RFLAGS.TF`), so it persists until disabled.
+    pub fn set_single_step(&mut self, enable: bool) {
+        let mut rflags = RFlags::from_bits_retain(self.rflags);
+        rflags.set(RFlags::TRAP_FLAG, enable);
+        self.rflags = rflags.bits();
+    }
+
     /// Enters user space.
     ///
     /// It restores the user registers and jumps to the user entry point
     /// (saved in `rip`).
     ///
     /// This function returns when an exception or syscall occurs.
+    /// With the `lazy-fp` feature, the trap of the first FP/SIMD instruction
+    /// after a context switch is handled internally, without returning.
     pub fn run(&mut self) -> ReturnReason {
+        loop {
+            if let Some(ret) = self.run_once() {
+                return ret;
+            }
+        }
+    }
+
+    /// Enters user space once, and returns [`None`] if the trap is handled
+    /// internally, so that user space should be entered again.
+    fn run_once(&mut self) -> Option<ReturnReason> {
         extern "C" {
             fn enter_user(uctx: &mut UserContext);
         }
@@ -88,16 +126,48 @@ impl UserContext {
         self.fs_base = read_thread_pointer() as _;
         unsafe { write_thread_pointer(kernel_fs_base) };
 
+        let cause = trap_cause(self);
+        trap_enter(self, cause, true);
+
+        #[cfg(feature = "lazy-fp")]
+        if matches!(cause, crate::trap::TrapCause::FpFault(_))
+            && super::context::handle_lazy_fp_trap()
+        {
+            // Re-execute the trapped instruction with FP/SIMD enabled.
+            trap_exit(self, cause, true);
+            return None;
+        }
+
         let cr2 = Cr2::read().unwrap().as_u64() as usize;
         let vector = self.vector as u8;
 
+        const DEBUG_VECTOR: u8 = ExceptionVector::Debug as u8;
         const PAGE_FAULT_VECTOR: u8 = ExceptionVector::Page as u8;
+        const MACHINE_CHECK_VECTOR: u8 = ExceptionVector::MachineCheck as u8;
 
         let ret = match vector {
             PAGE_FAULT_VECTOR if let Ok(flags) = err_code_to_flags(self.error_code) => {
                 ReturnReason::PageFault(va!(cr2), flags)
             }
-            LEGACY_SYSCALL_VECTOR => ReturnReason::Syscall,
+            DEBUG_VECTOR if unsafe { dr6() }.contains(Dr6::BS) => {
+                // DR6 is never cleared by the processor.
+                unsafe { dr6_write(Dr6::RTM) };
+                ReturnReason::SingleStep
+            }
+            #[cfg(feature = "hw-breakpoint")]
+            DEBUG_VECTOR if let Some((addr, is_exec)) = crate::debug::take_hit() => {
+                if is_exec {
+                    // Instruction breakpoints are faults, so skip the one at
+                    // `rip` when resuming.
+                    self.rflags |= RFlags::RESUME_FLAG.bits();
+                }
+                ReturnReason::Watchpoint(va!(addr))
+            }
+            MACHINE_CHECK_VECTOR => ReturnReason::MachineCheck,
+            LEGACY_SYSCALL_VECTOR => {
+                self.orig_arg0 = self.rax;
+                ReturnReason::Syscall
+            }
             IRQ_VECTOR_START..=IRQ_VECTOR_END => {
                 handle_trap!(IRQ, vector as _);
                 ReturnReason::Interrupt
@@ -106,11 +176,13 @@ impl UserContext {
                 vector,
                 error_code: self.error_code,
                 cr2,
+                rip: self.rip as _,
             }),
         };
 
+        trap_exit(self, cause, true);
         crate::asm::enable_irqs();
-        ret
+        Some(ret)
     }
 }
 
@@ -137,17 +209,57 @@ pub struct ExceptionInfo {
     pub error_code: u64,
     /// The faulting virtual address (if applicable).
     pub cr2: usize,
+    /// The address of the faulting instruction.
+    pub rip: usize,
 }
 
 impl ExceptionInfo {
     /// Returns a generalized kind of this exception.
     pub fn kind(&self) -> ExceptionKind {
         match ExceptionVector::try_from(self.vector) {
+            Ok(ExceptionVector::Division) => ExceptionKind::DivideByZero,
+            Ok(ExceptionVector::Debug) => ExceptionKind::Debug,
             Ok(ExceptionVector::Breakpoint) => ExceptionKind::Breakpoint,
             Ok(ExceptionVector::InvalidOpcode) => ExceptionKind::IllegalInstruction,
+            Ok(ExceptionVector::Stack) => ExceptionKind::StackSegment,
+            Ok(ExceptionVector::GeneralProtection) => ExceptionKind::GeneralProtection,
+            Ok(ExceptionVector::X87FloatingPoint) | Ok(ExceptionVector::SimdFloatingPoint) => {
+                ExceptionKind::FloatingPoint
+            }
+            Ok(ExceptionVector::AlignmentCheck) => ExceptionKind::Misaligned,
             _ => ExceptionKind::Other,
         }
     }
+
+    /// Returns the signal to deliver for this exception, as
+    /// `(signo, si_code, si_addr)`, following Linux.
+    ///
+    /// The cause of floating-point exceptions is not recorded, so they are
+    /// reported as `FPE_FLTINV`.
+    pub fn to_signal(&self) -> (usize, i32, usize) {
+        match ExceptionVector::try_from(self.vector) {
+            Ok(ExceptionVector::Division) => (SIGFPE, FPE_INTDIV, self.rip),
+            Ok(ExceptionVector::Debug) => (SIGTRAP, TRAP_BRKPT, self.rip),
+            Ok(ExceptionVector::InvalidOpcode) => (SIGILL, ILL_ILLOPN, self.rip),
+            Ok(ExceptionVector::SegmentNotPresent) | Ok(ExceptionVector::Stack) => {
+                (SIGBUS, SI_KERNEL, 0)
+            }
+            Ok(ExceptionVector::Page) => {
+                let code = if self.error_code & 1 != 0 {
+                    SEGV_ACCERR
+                } else {
+                    SEGV_MAPERR
+                };
+                (SIGSEGV, code, self.cr2)
+            }
+            Ok(ExceptionVector::X87FloatingPoint) | Ok(ExceptionVector::SimdFloatingPoint) => {
+                (SIGFPE, FPE_FLTINV, self.rip)
+            }
+            Ok(ExceptionVector::AlignmentCheck) => (SIGBUS, BUS_ADRALN, 0),
+            Ok(ExceptionVector::Breakpoint) => (SIGTRAP, SI_KERNEL, 0),
+            _ => (SIGSEGV, SI_KERNEL, 0),
+        }
+    }
 }
 
 /// Initializes syscall support and setups the syscall handler.
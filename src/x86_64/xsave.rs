@@ -0,0 +1,163 @@
+//! Detection and initialization of the XSAVE feature set, which manages the
+//! extended states beyond the legacy FXSAVE area (e.g., AVX and AVX-512).
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use x86::controlregs::{cr4, cr4_write, xcr0_write, Cr4, Xcr0};
+use x86::cpuid::native_cpuid::cpuid_count;
+use x86::msr::wrmsr;
+
+use super::context::XSAVE_AREA_SIZE;
+use super::FxsaveArea;
+
+/// The `IA32_XSS` MSR, which enables supervisor state components for XSAVES.
+const IA32_XSS: u32 = 0xda0;
+
+/// The state components saved and restored if supported by the CPU: x87, SSE,
+/// AVX, the AVX-512 opmask and upper ZMM registers, and PKRU.
+///
+/// AMX is not included, as its tile data does not fit in [`XSAVE_AREA_SIZE`].
+const XFEATURES: Xcr0 = Xcr0::XCR0_FPU_MMX_STATE
+    .union(Xcr0::XCR0_SSE_STATE)
+    .union(Xcr0::XCR0_AVX_STATE)
+    .union(Xcr0::XCR0_OPMASK_STATE)
+    .union(Xcr0::XCR0_ZMM_HI256_STATE)
+    .union(Xcr0::XCR0_HI16_ZMM_STATE)
+    .union(Xcr0::XCR0_PKRU_STATE);
+
+/// The instructions used to save and restore the extended states.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum XsaveMode {
+    /// `FXSAVE`/`FXRSTOR`, for CPUs without XSAVE.
+    Fxsave = 0,
+    /// `XSAVE`/`XRSTOR`.
+    Xsave = 1,
+    /// `XSAVEOPT`/`XRSTOR`, which skips the components that are in their
+    /// initial state or not modified since the last `XRSTOR`.
+    Xsaveopt = 2,
+    /// `XSAVES`/`XRSTORS`, which also uses the compacted format.
+    Xsaves = 3,
+}
+
+/// The `MXCSR` mask assumed if FXSAVE reports 0, i.e., all bits except DAZ.
+const MXCSR_DEFAULT_MASK: u32 = 0xffbf;
+
+static MODE: AtomicU8 = AtomicU8::new(XsaveMode::Fxsave as u8);
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+static SIZE: AtomicUsize = AtomicUsize::new(512);
+static STANDARD_SIZE: AtomicUsize = AtomicUsize::new(512);
+static MXCSR_MASK: AtomicU32 = AtomicU32::new(MXCSR_DEFAULT_MASK);
+
+/// Returns the instructions used to save and restore the extended states.
+#[inline]
+pub(super) fn mode() -> XsaveMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => XsaveMode::Xsave,
+        2 => XsaveMode::Xsaveopt,
+        3 => XsaveMode::Xsaves,
+        _ => XsaveMode::Fxsave,
+    }
+}
+
+/// Returns the state components enabled in `XCR0`, i.e., the requested-feature
+/// bitmap of XSAVE instructions.
+#[inline]
+pub(super) fn features() -> u64 {
+    FEATURES.load(Ordering::Relaxed)
+}
+
+/// Returns the size in bytes of the area written by the save instruction.
+pub(super) fn area_size() -> usize {
+    SIZE.load(Ordering::Relaxed)
+}
+
+/// Returns the size in bytes of the area in the standard (non-compacted)
+/// format, i.e., the area written by `XSAVE`, or by `FXSAVE` without XSAVE.
+#[cfg(feature = "uspace")]
+pub(super) fn standard_size() -> usize {
+    STANDARD_SIZE.load(Ordering::Relaxed)
+}
+
+/// Returns the bits of `MXCSR` supported by the CPU. Setting other bits causes
+/// `#GP` on restoring the extended states.
+#[cfg(feature = "uspace")]
+pub(super) fn mxcsr_mask() -> u32 {
+    MXCSR_MASK.load(Ordering::Relaxed)
+}
+
+/// Reads the supported bits of `MXCSR` from the `MXCSR_MASK` field written by
+/// FXSAVE.
+fn init_mxcsr_mask() {
+    let mut area = MaybeUninit::<FxsaveArea>::uninit();
+    let area = unsafe {
+        core::arch::x86_64::_fxsave64(area.as_mut_ptr() as *mut u8);
+        area.assume_init()
+    };
+    if area.mxcsr_mask != 0 {
+        MXCSR_MASK.store(area.mxcsr_mask, Ordering::Relaxed);
+    }
+}
+
+/// Enables XSAVE and the supported components of [`XFEATURES`] in `XCR0` on
+/// the current CPU, and selects the save instruction by CPUID.
+///
+/// The components located beyond [`XSAVE_AREA_SIZE`] are not enabled, as the
+/// area has a fixed capacity. Nothing is done if XSAVE is not supported, so
+/// that `FXSAVE` is used. The supported bits of `MXCSR` are also detected.
+pub(super) fn init() {
+    init_mxcsr_mask();
+    if cpuid_count(1, 0).ecx & (1 << 26) == 0 {
+        return;
+    }
+    let leaf = cpuid_count(0xd, 0);
+    let mut features = ((leaf.edx as u64) << 32 | leaf.eax as u64) & XFEATURES.bits();
+    // x87 and SSE are in the legacy region, which is always present.
+    for i in 2..64 {
+        if features & (1 << i) != 0 {
+            let leaf = cpuid_count(0xd, i);
+            if (leaf.ebx + leaf.eax) as usize > XSAVE_AREA_SIZE {
+                features &= !(1 << i);
+            }
+        }
+    }
+    // AVX-512 components can only be enabled together.
+    let avx512 =
+        (Xcr0::XCR0_OPMASK_STATE | Xcr0::XCR0_ZMM_HI256_STATE | Xcr0::XCR0_HI16_ZMM_STATE).bits();
+    if features & avx512 != avx512 {
+        features &= !avx512;
+    }
+    unsafe {
+        cr4_write(cr4() | Cr4::CR4_ENABLE_OS_XSAVE);
+        xcr0_write(Xcr0::from_bits_truncate(features));
+    }
+
+    // The sizes reported by CPUID depend on the current `XCR0` and `IA32_XSS`.
+    let caps = cpuid_count(0xd, 1).eax;
+    let mode = if caps & (1 << 3) != 0 {
+        // No supervisor state is managed.
+        unsafe { wrmsr(IA32_XSS, 0) };
+        XsaveMode::Xsaves
+    } else if caps & 1 != 0 {
+        XsaveMode::Xsaveopt
+    } else {
+        XsaveMode::Xsave
+    };
+    let standard_size = cpuid_count(0xd, 0).ebx as usize;
+    let size = if mode == XsaveMode::Xsaves {
+        cpuid_count(0xd, 1).ebx as usize
+    } else {
+        standard_size
+    };
+    assert!(size <= XSAVE_AREA_SIZE, "XSAVE area too large: {size}");
+    assert!(
+        standard_size <= XSAVE_AREA_SIZE,
+        "XSAVE area too large: {standard_size}"
+    );
+
+    FEATURES.store(features, Ordering::Relaxed);
+    SIZE.store(size, Ordering::Relaxed);
+    STANDARD_SIZE.store(standard_size, Ordering::Relaxed);
+    MODE.store(mode as u8, Ordering::Relaxed);
+}